@@ -1,8 +1,10 @@
 use anyhow::{Context, Result};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{SampleFormat, SampleRate, StreamConfig};
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::mpsc;
 use tracing::{debug, error, info, warn};
 
@@ -10,6 +12,56 @@ const TARGET_SAMPLE_RATE: u32 = 16000;
 const TARGET_CHANNELS: u16 = 1;
 const CHUNK_DURATION_MS: u32 = 100;
 
+/// 100ms of 16kHz mono 16-bit PCM with the time it was captured
+#[derive(Debug, Clone)]
+pub struct AudioChunk {
+    pub data: Vec<u8>,
+    /// When the last sample of the chunk was captured
+    pub captured_at: Instant,
+}
+
+impl AudioChunk {
+    /// Chunk captured just now
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            captured_at: Instant::now(),
+        }
+    }
+}
+
+/// Read a 16kHz mono 16-bit WAV file as 100ms chunks of PCM
+pub fn read_wav_chunks(path: &Path) -> Result<Vec<Vec<u8>>> {
+    let mut reader = hound::WavReader::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let spec = reader.spec();
+    if spec.sample_rate != TARGET_SAMPLE_RATE
+        || spec.channels != TARGET_CHANNELS
+        || spec.bits_per_sample != 16
+        || spec.sample_format != hound::SampleFormat::Int
+    {
+        anyhow::bail!(
+            "{} is {}Hz, {} channel(s), {}-bit; expected 16000Hz mono 16-bit PCM",
+            path.display(),
+            spec.sample_rate,
+            spec.channels,
+            spec.bits_per_sample
+        );
+    }
+
+    let samples_per_chunk = (TARGET_SAMPLE_RATE * CHUNK_DURATION_MS / 1000) as usize;
+    let pcm: Vec<u8> = reader
+        .samples::<i16>()
+        .map(|sample| sample.map(i16::to_le_bytes))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .concat();
+    Ok(pcm
+        .chunks(samples_per_chunk * 2)
+        .map(<[u8]>::to_vec)
+        .collect())
+}
+
 pub struct AudioCapture {
     stream: Option<cpal::Stream>,
     is_recording: Arc<AtomicBool>,
@@ -23,13 +75,15 @@ impl AudioCapture {
         }
     }
 
-    pub fn start(&mut self, audio_tx: mpsc::Sender<Vec<u8>>) -> Result<()> {
+    pub fn start(&mut self, audio_tx: mpsc::Sender<AudioChunk>) -> Result<()> {
         let host = cpal::default_host();
         let device = host
             .default_input_device()
             .context("No input device available")?;
 
-        info!("Using input device: {}", device.name()?);
+        let name = device.name()?;
+        info!("Using input device: {}", name);
+        tracing::Span::current().record("device", name.as_str());
 
         let supported_configs = device.supported_input_configs()?;
         debug!("Supported configs:");
@@ -142,7 +196,12 @@ impl AudioCapture {
                 channels,
                 samples_per_chunk,
             )?,
-            _ => return Err(anyhow::anyhow!("Unsupported sample format: {:?}", sample_format)),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Unsupported sample format: {:?}",
+                    sample_format
+                ))
+            }
         };
 
         stream.play()?;
@@ -172,7 +231,7 @@ fn find_best_config(device: &cpal::Device) -> Result<cpal::SupportedStreamConfig
             && config.min_sample_rate().0 <= TARGET_SAMPLE_RATE
             && config.max_sample_rate().0 >= TARGET_SAMPLE_RATE
         {
-            return Ok(config.clone().with_sample_rate(SampleRate(TARGET_SAMPLE_RATE)));
+            return Ok(config.with_sample_rate(SampleRate(TARGET_SAMPLE_RATE)));
         }
     }
 
@@ -186,7 +245,7 @@ fn find_best_config(device: &cpal::Device) -> Result<cpal::SupportedStreamConfig
             } else {
                 config.max_sample_rate().0.min(48000)
             };
-            return Ok(config.clone().with_sample_rate(SampleRate(sample_rate)));
+            return Ok(config.with_sample_rate(SampleRate(sample_rate)));
         }
     }
 
@@ -200,7 +259,7 @@ fn find_best_config(device: &cpal::Device) -> Result<cpal::SupportedStreamConfig
             } else {
                 config.max_sample_rate().0.min(48000)
             };
-            return Ok(config.clone().with_sample_rate(SampleRate(sample_rate)));
+            return Ok(config.with_sample_rate(SampleRate(sample_rate)));
         }
     }
 
@@ -217,7 +276,7 @@ fn process_audio_samples(
     buffer: &mut Vec<f32>,
     samples_per_chunk: usize,
     resampler: &Option<SimpleResampler>,
-    audio_tx: &mpsc::Sender<Vec<u8>>,
+    audio_tx: &mpsc::Sender<AudioChunk>,
 ) {
     // Convert to mono if stereo
     let mono_samples: Vec<f32> = if source_channels == 2 {
@@ -258,7 +317,7 @@ fn process_audio_samples(
             .collect();
 
         // Send to channel (non-blocking)
-        if let Err(e) = audio_tx.try_send(pcm_bytes) {
+        if let Err(e) = audio_tx.try_send(AudioChunk::new(pcm_bytes)) {
             warn!("Failed to send audio chunk: {}", e);
         }
     }
@@ -267,7 +326,7 @@ fn process_audio_samples(
 fn build_stream_i8(
     device: &cpal::Device,
     config: &StreamConfig,
-    audio_tx: mpsc::Sender<Vec<u8>>,
+    audio_tx: mpsc::Sender<AudioChunk>,
     is_recording: Arc<AtomicBool>,
     source_sample_rate: u32,
     source_channels: u16,
@@ -311,7 +370,7 @@ fn build_stream_i8(
 fn build_stream_i16(
     device: &cpal::Device,
     config: &StreamConfig,
-    audio_tx: mpsc::Sender<Vec<u8>>,
+    audio_tx: mpsc::Sender<AudioChunk>,
     is_recording: Arc<AtomicBool>,
     source_sample_rate: u32,
     source_channels: u16,
@@ -355,7 +414,7 @@ fn build_stream_i16(
 fn build_stream_i32(
     device: &cpal::Device,
     config: &StreamConfig,
-    audio_tx: mpsc::Sender<Vec<u8>>,
+    audio_tx: mpsc::Sender<AudioChunk>,
     is_recording: Arc<AtomicBool>,
     source_sample_rate: u32,
     source_channels: u16,
@@ -378,10 +437,7 @@ fn build_stream_i32(
             }
 
             // Convert i32 to f32
-            let samples: Vec<f32> = data
-                .iter()
-                .map(|&s| s as f32 / (i32::MAX as f32))
-                .collect();
+            let samples: Vec<f32> = data.iter().map(|&s| s as f32 / (i32::MAX as f32)).collect();
 
             process_audio_samples(
                 samples,
@@ -402,7 +458,7 @@ fn build_stream_i32(
 fn build_stream_i64(
     device: &cpal::Device,
     config: &StreamConfig,
-    audio_tx: mpsc::Sender<Vec<u8>>,
+    audio_tx: mpsc::Sender<AudioChunk>,
     is_recording: Arc<AtomicBool>,
     source_sample_rate: u32,
     source_channels: u16,
@@ -449,7 +505,7 @@ fn build_stream_i64(
 fn build_stream_u8(
     device: &cpal::Device,
     config: &StreamConfig,
-    audio_tx: mpsc::Sender<Vec<u8>>,
+    audio_tx: mpsc::Sender<AudioChunk>,
     is_recording: Arc<AtomicBool>,
     source_sample_rate: u32,
     source_channels: u16,
@@ -472,10 +528,7 @@ fn build_stream_u8(
             }
 
             // Convert u8 to f32 (range 0 to 255, center at 128)
-            let samples: Vec<f32> = data
-                .iter()
-                .map(|&s| (s as f32 - 128.0) / 128.0)
-                .collect();
+            let samples: Vec<f32> = data.iter().map(|&s| (s as f32 - 128.0) / 128.0).collect();
 
             process_audio_samples(
                 samples,
@@ -496,7 +549,7 @@ fn build_stream_u8(
 fn build_stream_u16(
     device: &cpal::Device,
     config: &StreamConfig,
-    audio_tx: mpsc::Sender<Vec<u8>>,
+    audio_tx: mpsc::Sender<AudioChunk>,
     is_recording: Arc<AtomicBool>,
     source_sample_rate: u32,
     source_channels: u16,
@@ -543,7 +596,7 @@ fn build_stream_u16(
 fn build_stream_u32(
     device: &cpal::Device,
     config: &StreamConfig,
-    audio_tx: mpsc::Sender<Vec<u8>>,
+    audio_tx: mpsc::Sender<AudioChunk>,
     is_recording: Arc<AtomicBool>,
     source_sample_rate: u32,
     source_channels: u16,
@@ -590,7 +643,7 @@ fn build_stream_u32(
 fn build_stream_u64(
     device: &cpal::Device,
     config: &StreamConfig,
-    audio_tx: mpsc::Sender<Vec<u8>>,
+    audio_tx: mpsc::Sender<AudioChunk>,
     is_recording: Arc<AtomicBool>,
     source_sample_rate: u32,
     source_channels: u16,
@@ -615,9 +668,7 @@ fn build_stream_u64(
             // Convert u64 to f32
             let samples: Vec<f32> = data
                 .iter()
-                .map(|&s| {
-                    ((s as f64 - (u64::MAX as f64 / 2.0)) / (u64::MAX as f64 / 2.0)) as f32
-                })
+                .map(|&s| ((s as f64 - (u64::MAX as f64 / 2.0)) / (u64::MAX as f64 / 2.0)) as f32)
                 .collect();
 
             process_audio_samples(
@@ -639,7 +690,7 @@ fn build_stream_u64(
 fn build_stream_f32(
     device: &cpal::Device,
     config: &StreamConfig,
-    audio_tx: mpsc::Sender<Vec<u8>>,
+    audio_tx: mpsc::Sender<AudioChunk>,
     is_recording: Arc<AtomicBool>,
     source_sample_rate: u32,
     source_channels: u16,
@@ -683,7 +734,7 @@ fn build_stream_f32(
 fn build_stream_f64(
     device: &cpal::Device,
     config: &StreamConfig,
-    audio_tx: mpsc::Sender<Vec<u8>>,
+    audio_tx: mpsc::Sender<AudioChunk>,
     is_recording: Arc<AtomicBool>,
     source_sample_rate: u32,
     source_channels: u16,
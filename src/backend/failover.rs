@@ -0,0 +1,402 @@
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn, Instrument};
+
+use super::{Backend, BackendKind};
+use crate::audio::AudioChunk;
+use crate::notify::notify;
+use crate::usage::Ledger;
+use crate::websocket::{
+    AsrClient, AsrConnection, AsrError, AsrEvent, LatencyTracker, SharedTimeline,
+};
+
+/// Bytes of 16kHz 16-bit mono PCM per millisecond
+const BYTES_PER_MS: usize = 32;
+
+/// Audio kept for replay into the next backend: 30s of 100ms chunks
+const REPLAY_CHUNKS: usize = 300;
+
+/// Audio captured shortly before a final result is kept, as it may already
+/// belong to the next sentence
+const REPLAY_TAIL: Duration = Duration::from_millis(500);
+
+/// Retries with another API key of the same backend within one session
+const MAX_KEY_RETRIES: u32 = 3;
+
+/// Wait before the first retry with another key, doubled for each further one
+const KEY_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// How often a failed primary is checked while a fallback is active
+const FAILBACK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Backends tried in order: the primary, then each fallback
+///
+/// When the active backend fails (connection, auth, quota or a failed
+/// task), the session continues on the same backend with its next API key
+/// if it has one, or else on the next backend. Either way the audio of the
+/// sentence in progress is replayed first, and sentence ids and times stay
+/// continuous.
+#[derive(Clone)]
+pub struct BackendChain {
+    backends: Vec<(BackendKind, Backend)>,
+    /// The backend sessions start on
+    active: Arc<AtomicUsize>,
+    failback: bool,
+    /// A task is checking whether the primary is back
+    probing: Arc<AtomicBool>,
+}
+
+impl BackendChain {
+    pub fn new(kind: BackendKind, primary: Backend) -> Self {
+        Self {
+            backends: vec![(kind, primary)],
+            active: Arc::new(AtomicUsize::new(0)),
+            failback: false,
+            probing: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Try `backend` after the ones added so far
+    pub fn with_fallback(mut self, kind: BackendKind, backend: Backend) -> Self {
+        self.backends.push((kind, backend));
+        self
+    }
+
+    /// Check a failed primary in the background and go back to it for the
+    /// next session once it answers
+    pub fn with_failback(mut self, failback: bool) -> Self {
+        self.failback = failback;
+        self
+    }
+
+    /// Measure how long results take after the audio was captured
+    pub fn with_latency(mut self, latency: Arc<LatencyTracker>) -> Self {
+        self.backends = self
+            .backends
+            .into_iter()
+            .map(|(kind, backend)| (kind, backend.with_latency(latency.clone())))
+            .collect();
+        self
+    }
+
+    /// Record the usage of every session in `ledger`
+    pub fn with_ledger(mut self, ledger: Arc<Ledger>) -> Self {
+        self.backends = self
+            .backends
+            .into_iter()
+            .map(|(kind, backend)| (kind, backend.with_ledger(ledger.clone())))
+            .collect();
+        self
+    }
+
+    /// The primary's DashScope client, which supports pre-warmed connections
+    pub fn dashscope(&self) -> Option<&AsrClient> {
+        self.backends[0].1.dashscope()
+    }
+
+    /// The backend the next session starts on
+    pub fn active(&self) -> BackendKind {
+        self.backends[self.active.load(Ordering::SeqCst)].0
+    }
+
+    /// Recognize audio until the channel closes, moving down the chain when
+    /// a backend fails; `warm` is only used by the primary
+    pub async fn run(
+        &mut self,
+        warm: Option<AsrConnection>,
+        mut audio_rx: mpsc::Receiver<AudioChunk>,
+        event_tx: mpsc::Sender<AsrEvent>,
+    ) -> Result<()> {
+        let mut index = self.active.load(Ordering::SeqCst);
+        let mut warm = warm.filter(|_| index == 0);
+        let mut replay = ReplayBuffer::default();
+        let timeline = SharedTimeline::new();
+        let mut audio_open = true;
+        let mut started = false;
+        let mut key_retries = 0;
+
+        loop {
+            let (kind, backend) = self.backends[index].clone();
+            // The backend stitches its results, starting where the replay does
+            let backend = backend.with_timeline(timeline.starting_at(replay.start_ms()));
+            let (inner_audio_tx, inner_audio_rx) = mpsc::channel(REPLAY_CHUNKS * 2);
+            let (inner_event_tx, mut inner_event_rx) = mpsc::channel(100);
+            for chunk in replay.chunks() {
+                let _ = inner_audio_tx.try_send(chunk.clone());
+            }
+            let mut inner_audio = audio_open.then_some(inner_audio_tx);
+            let set_aside = backend.keys_set_aside();
+            let handle = tokio::spawn(
+                run_backend(backend, warm.take(), inner_audio_rx, inner_event_tx).in_current_span(),
+            );
+
+            let error = loop {
+                tokio::select! {
+                    chunk = audio_rx.recv(), if audio_open => match chunk {
+                        Some(chunk) => {
+                            replay.push(chunk.clone());
+                            if let Some(audio_tx) = &inner_audio {
+                                // A backend that stopped reading reports why as an event
+                                let _ = audio_tx.send(chunk).await;
+                            }
+                        }
+                        None => {
+                            audio_open = false;
+                            inner_audio = None;
+                        }
+                    },
+                    event = inner_event_rx.recv() => match event {
+                        Some(AsrEvent::TaskStarted) => {
+                            if !started {
+                                started = true;
+                                event_tx.send(AsrEvent::TaskStarted).await?;
+                            }
+                        }
+                        Some(AsrEvent::ResultGenerated(result)) => {
+                            if result.is_final {
+                                replay.sentence_done();
+                            }
+                            event_tx.send(AsrEvent::ResultGenerated(result)).await?;
+                        }
+                        Some(AsrEvent::TaskFinished) => {
+                            event_tx.send(AsrEvent::TaskFinished).await?;
+                            return Ok(());
+                        }
+                        Some(AsrEvent::TaskFailed { error }) => break error,
+                        None => {
+                            break AsrError::Protocol(
+                                "ASR client stopped without finishing the task".to_string(),
+                            )
+                        }
+                    },
+                }
+            };
+            handle.abort();
+
+            if key_retries < MAX_KEY_RETRIES && self.backends[index].1.can_retry(&error, set_aside)
+            {
+                let backoff = KEY_RETRY_BACKOFF * 2u32.pow(key_retries);
+                key_retries += 1;
+                info!(
+                    "Retrying {} with the next API key in {}ms, replaying {:.1}s of audio",
+                    kind,
+                    backoff.as_millis(),
+                    replay.seconds()
+                );
+                tokio::time::sleep(backoff).await;
+                continue;
+            }
+            if index + 1 == self.backends.len() {
+                event_tx.send(AsrEvent::TaskFailed { error }).await?;
+                return Ok(());
+            }
+            index += 1;
+            key_retries = 0;
+            self.active.store(index, Ordering::SeqCst);
+            let next = self.backends[index].0;
+            warn!(
+                "{} failed, switching to {} and replaying {:.1}s of audio: {}",
+                kind,
+                next,
+                replay.seconds(),
+                error
+            );
+            notify(
+                &format!("Audio2Text switched to {}", next),
+                &error.to_string(),
+            );
+            self.watch_primary();
+        }
+    }
+
+    /// Probe the primary until it answers, then make it active again
+    fn watch_primary(&self) {
+        if !self.failback || self.probing.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let (kind, primary) = self.backends[0].clone();
+        let active = self.active.clone();
+        let probing = self.probing.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(FAILBACK_INTERVAL).await;
+                match primary.probe().await {
+                    Ok(()) => {
+                        active.store(0, Ordering::SeqCst);
+                        probing.store(false, Ordering::SeqCst);
+                        notify(
+                            &format!("Audio2Text back on {}", kind),
+                            "Used again from the next recording",
+                        );
+                        return;
+                    }
+                    Err(e) => debug!("{} still unavailable: {:#}", kind, e),
+                }
+            }
+        });
+        info!("Checking {} every {}s", kind, FAILBACK_INTERVAL.as_secs());
+    }
+}
+
+/// Run one backend, reporting an error it returns as a failed task
+async fn run_backend(
+    mut backend: Backend,
+    warm: Option<AsrConnection>,
+    audio_rx: mpsc::Receiver<AudioChunk>,
+    event_tx: mpsc::Sender<AsrEvent>,
+) {
+    if let Err(e) = backend.run(warm, audio_rx, event_tx.clone()).await {
+        let error = AsrError::from_anyhow(&e);
+        let _ = event_tx.send(AsrEvent::TaskFailed { error }).await;
+    }
+}
+
+/// Audio of the sentence in progress, to replay into the next backend
+#[derive(Default)]
+struct ReplayBuffer {
+    /// Chunks with their offset in milliseconds since the session started
+    chunks: VecDeque<(i64, AudioChunk)>,
+    received_ms: i64,
+}
+
+impl ReplayBuffer {
+    fn push(&mut self, chunk: AudioChunk) {
+        let offset = self.received_ms;
+        self.received_ms += (chunk.data.len() / BYTES_PER_MS) as i64;
+        self.chunks.push_back((offset, chunk));
+        if self.chunks.len() > REPLAY_CHUNKS {
+            self.chunks.pop_front();
+        }
+    }
+
+    /// A sentence was finalized: its audio is no longer needed
+    fn sentence_done(&mut self) {
+        let Some(keep_from) = Instant::now().checked_sub(REPLAY_TAIL) else {
+            return;
+        };
+        while self
+            .chunks
+            .front()
+            .is_some_and(|(_, chunk)| chunk.captured_at < keep_from)
+        {
+            self.chunks.pop_front();
+        }
+    }
+
+    fn chunks(&self) -> impl Iterator<Item = &AudioChunk> {
+        self.chunks.iter().map(|(_, chunk)| chunk)
+    }
+
+    /// Offset of the oldest chunk kept
+    fn start_ms(&self) -> i64 {
+        self.chunks
+            .front()
+            .map_or(self.received_ms, |(offset, _)| *offset)
+    }
+
+    fn seconds(&self) -> f32 {
+        (self.received_ms - self.start_ms()) as f32 / 1000.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::{read_cassette, ConnectionConfig, KeyPool, MockServer, RealtimeClient};
+    use std::path::Path;
+
+    fn chunk_captured(ago: Duration) -> AudioChunk {
+        AudioChunk {
+            data: vec![0; 3200],
+            captured_at: Instant::now() - ago,
+        }
+    }
+
+    #[test]
+    fn replay_keeps_audio_since_the_last_sentence() {
+        let mut replay = ReplayBuffer::default();
+        for ms in [3000, 2000, 1000, 300, 100] {
+            replay.push(chunk_captured(Duration::from_millis(ms)));
+        }
+        assert_eq!(replay.start_ms(), 0);
+
+        replay.sentence_done();
+        assert_eq!(replay.chunks().count(), 2);
+        assert_eq!(replay.start_ms(), 300);
+        assert_eq!(replay.seconds(), 0.2);
+
+        for _ in 0..REPLAY_CHUNKS + 10 {
+            replay.push(chunk_captured(Duration::ZERO));
+        }
+        assert_eq!(replay.chunks().count(), REPLAY_CHUNKS);
+    }
+
+    #[test]
+    fn retries_only_when_the_key_was_set_aside() {
+        let keys = Arc::new(KeyPool::parse("sk-one,sk-two").unwrap());
+        let backend = Backend::DashScope(AsrClient::from_keys(keys.clone()));
+        let rejected = AsrError::Auth {
+            code: "InvalidApiKey".to_string(),
+            message: "bad key".to_string(),
+        };
+
+        // An auth failure the pool never heard of, say from a proxy
+        let before = backend.keys_set_aside();
+        assert!(!backend.can_retry(&rejected, before));
+
+        keys.report(0, &rejected);
+        assert!(backend.can_retry(&rejected, before));
+
+        // The last key is rejected too: nothing left to retry with
+        let before = backend.keys_set_aside();
+        keys.report(1, &rejected);
+        assert!(!backend.can_retry(&rejected, before));
+    }
+
+    #[tokio::test]
+    async fn fails_over_and_replays_audio() {
+        // Nothing listens on the primary's port
+        let unused = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let dead =
+            ConnectionConfig::for_endpoint(&format!("ws://{}/", unused.local_addr().unwrap()))
+                .unwrap();
+        drop(unused);
+        let primary = RealtimeClient::new(None, dead);
+
+        let entries = read_cassette(Path::new("tests/fixtures/streaming_en.jsonl")).unwrap();
+        let server = MockServer::start(entries).await.unwrap();
+        let fallback = AsrClient::new("mock".to_string()).with_connection(server.connection());
+
+        let mut chain = BackendChain::new(BackendKind::Openai, Backend::OpenAi(primary))
+            .with_fallback(BackendKind::Dashscope, Backend::DashScope(fallback));
+
+        let (audio_tx, audio_rx) = mpsc::channel(100);
+        let (event_tx, mut event_rx) = mpsc::channel(100);
+        // 5 seconds of audio covers every result of the fixture
+        for _ in 0..50 {
+            audio_tx.send(AudioChunk::new(vec![0; 3200])).await.unwrap();
+        }
+        drop(audio_tx);
+        chain.run(None, audio_rx, event_tx).await.unwrap();
+
+        let mut finals = Vec::new();
+        while let Some(event) = event_rx.recv().await {
+            match event {
+                AsrEvent::ResultGenerated(result) if result.is_final => {
+                    finals.push(result.sentence_id)
+                }
+                AsrEvent::TaskFinished => break,
+                AsrEvent::TaskFailed { error } => panic!("task failed: {}", error),
+                _ => {}
+            }
+        }
+        // Numbered once, by the chain's timeline
+        assert!(!finals.is_empty());
+        assert_eq!(finals, (0..finals.len() as i32).collect::<Vec<_>>());
+        assert_eq!(chain.active(), BackendKind::Dashscope);
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use crate::audio::AudioChunk;
+use crate::usage::Ledger;
+use crate::websocket::{
+    AsrClient, AsrConnection, AsrError, AsrEvent, BatchClient, LatencyTracker, RealtimeClient,
+    SharedTimeline,
+};
+
+mod failover;
+
+pub use failover::BackendChain;
+
+/// Streaming recognition protocol
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// Alibaba Cloud DashScope realtime ASR
+    #[default]
+    Dashscope,
+    /// OpenAI realtime transcription, or a self-hosted server speaking it
+    Openai,
+    /// A non-streaming transcription endpoint (e.g. a local whisper.cpp
+    /// server), fed utterances cut by a local VAD
+    Batch,
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Dashscope => "dashscope",
+            Self::Openai => "openai",
+            Self::Batch => "batch",
+        })
+    }
+}
+
+/// A configured recognition service that sessions stream to
+#[derive(Clone)]
+pub enum Backend {
+    DashScope(AsrClient),
+    OpenAi(RealtimeClient),
+    Batch(BatchClient),
+}
+
+impl Backend {
+    /// Measure how long results take after the audio was captured
+    pub fn with_latency(self, latency: Arc<LatencyTracker>) -> Self {
+        match self {
+            Self::DashScope(client) => Self::DashScope(client.with_latency(latency)),
+            Self::OpenAi(client) => Self::OpenAi(client.with_latency(latency)),
+            Self::Batch(client) => Self::Batch(client.with_latency(latency)),
+        }
+    }
+
+    /// Record the usage of every session in `ledger`
+    pub fn with_ledger(self, ledger: Arc<Ledger>) -> Self {
+        match self {
+            Self::DashScope(client) => Self::DashScope(client.with_ledger(ledger)),
+            Self::OpenAi(client) => Self::OpenAi(client.with_ledger(ledger)),
+            Self::Batch(client) => Self::Batch(client.with_ledger(ledger)),
+        }
+    }
+
+    /// Stitch results onto `timeline`, which the chain hands down
+    pub fn with_timeline(self, timeline: SharedTimeline) -> Self {
+        match self {
+            Self::DashScope(client) => Self::DashScope(client.with_timeline(timeline)),
+            Self::OpenAi(client) => Self::OpenAi(client.with_timeline(timeline)),
+            Self::Batch(client) => Self::Batch(client.with_timeline(timeline)),
+        }
+    }
+
+    /// The DashScope client, which supports pre-warmed connections
+    pub fn dashscope(&self) -> Option<&AsrClient> {
+        match self {
+            Self::DashScope(client) => Some(client),
+            Self::OpenAi(_) | Self::Batch(_) => None,
+        }
+    }
+
+    /// Failures that set an API key aside so far, to pass to `can_retry`
+    pub fn keys_set_aside(&self) -> u64 {
+        match self {
+            Self::DashScope(client) => client.keys().set_aside_count(),
+            Self::OpenAi(_) | Self::Batch(_) => 0,
+        }
+    }
+
+    /// Whether a failed session can be retried on this backend, because the
+    /// failure set its API key aside (`keys_set_aside` has moved on from
+    /// `set_aside_before`) and another key is available
+    pub fn can_retry(&self, error: &AsrError, set_aside_before: u64) -> bool {
+        let key_error = matches!(
+            error,
+            AsrError::RateLimited { .. } | AsrError::QuotaExceeded { .. } | AsrError::Auth { .. }
+        );
+        match self {
+            Self::DashScope(client) => {
+                key_error
+                    && client.keys().len() > 1
+                    && self.keys_set_aside() > set_aside_before
+                    && client.keys().has_available()
+            }
+            Self::OpenAi(_) | Self::Batch(_) => false,
+        }
+    }
+
+    /// Check the service accepts us again, without recognizing anything
+    pub async fn probe(&self) -> Result<()> {
+        match self {
+            Self::DashScope(client) => client.connect().await.map(drop),
+            Self::OpenAi(client) => client.probe().await,
+            Self::Batch(client) => client.probe().await,
+        }
+    }
+
+    /// Recognize audio until the channel closes, on `warm` if one is ready
+    pub async fn run(
+        &mut self,
+        warm: Option<AsrConnection>,
+        audio_rx: mpsc::Receiver<AudioChunk>,
+        event_tx: mpsc::Sender<AsrEvent>,
+    ) -> Result<()> {
+        match (self, warm) {
+            (Self::DashScope(client), Some(connection)) => {
+                client.run(connection, audio_rx, event_tx).await
+            }
+            (Self::DashScope(client), None) => client.start_recognition(audio_rx, event_tx).await,
+            (Self::OpenAi(client), _) => client.start_recognition(audio_rx, event_tx).await,
+            (Self::Batch(client), _) => client.start_recognition(audio_rx, event_tx).await,
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::backend::BackendKind;
+use crate::output::{Content, OutputConfig, Template};
+use crate::typing::TypingMode;
+use crate::usage::UsageConfig;
+use crate::websocket::{KeepalivePolicy, RecognitionOptions};
+
+/// Contents of `~/.config/audio2text/config.toml`
+///
+/// ```toml
+/// default_profile = "dictation"
+///
+/// [profiles.dictation]
+/// typing = "streaming"
+/// max_sentence_silence = 800
+///
+/// [profiles.meeting]
+/// model = "paraformer-realtime-v2"
+/// diarize = true
+/// semantic_punctuation = true
+/// disfluency_removal = true
+/// vocabulary = "team"
+/// keepalive = "heartbeat"
+/// max_silence = 0
+///
+/// [[profiles.meeting.outputs]]
+/// sink = "command"
+/// stream = "translation:en"
+/// command = "caption-overlay"
+///
+/// [usage]
+/// monthly_cap = 50.0
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Profile used when `--profile` is not given
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Usage ledger, prices and spending cap
+    #[serde(default)]
+    pub usage: UsageConfig,
+}
+
+/// Settings for one use case, e.g. dictation or meetings
+///
+/// Command line flags take precedence over the profile.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub backend: Option<BackendKind>,
+    /// Backends to switch to when the active one fails, in order
+    pub fallback: Option<Vec<BackendKind>>,
+    /// Go back to the primary backend once it is reachable again
+    pub failback: Option<bool>,
+    pub model: Option<String>,
+    pub typing: Option<TypingMode>,
+    pub diarize: Option<bool>,
+    /// Silence in milliseconds that ends a sentence
+    pub max_sentence_silence: Option<u32>,
+    pub semantic_punctuation: Option<bool>,
+    pub disfluency_removal: Option<bool>,
+    pub inverse_text_normalization: Option<bool>,
+    pub heartbeat: Option<bool>,
+    pub keepalive: Option<KeepalivePolicy>,
+    /// Translation target languages; each sentence is shown in the first one
+    /// not spoken
+    pub translate: Option<Vec<String>>,
+    /// What to type and write of each sentence (see `--content`)
+    pub content: Option<Content>,
+    /// How bilingual content is written, e.g. `"{source} ({translation})"`
+    pub content_template: Option<Template>,
+    /// Where each stream of the results goes, instead of typing the content
+    pub outputs: Option<Vec<OutputConfig>>,
+    /// Hotword vocabulary name (see `audio2text vocab`) or id
+    pub vocabulary: Option<String>,
+    /// Seconds without speech before recording stops, 0 to never stop
+    pub max_silence: Option<u64>,
+    /// Meeting mode (see `--meeting`)
+    pub meeting: Option<bool>,
+    /// Minutes after which a task is replaced by a new one, 0 to never rotate
+    pub rotate_after: Option<u64>,
+}
+
+impl Config {
+    /// Default location of the config file
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("audio2text").join("config.toml"))
+    }
+
+    /// Load the config file; a missing file at the default location is an empty config
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, explicit) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match Self::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => {
+                return Ok(Self::default())
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+
+        Self::parse(&content).with_context(|| format!("Invalid config {}", path.display()))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        Ok(toml::from_str(content)?)
+    }
+
+    /// The requested profile, the default profile, or an empty one
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile> {
+        let Some(name) = name.or(self.default_profile.as_deref()) else {
+            return Ok(Profile::default());
+        };
+
+        self.profiles.get(name).cloned().with_context(|| {
+            let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            format!(
+                "Unknown profile {:?} (configured: {})",
+                name,
+                if known.is_empty() {
+                    "none".to_string()
+                } else {
+                    known.join(", ")
+                }
+            )
+        })
+    }
+}
+
+impl Profile {
+    /// Recognition parameters of this profile; `diarize` overrides the profile
+    /// when set, and `translate` when not empty
+    pub fn recognition_options(&self, diarize: bool, translate: &[String]) -> RecognitionOptions {
+        RecognitionOptions {
+            diarization: diarize || self.diarize.unwrap_or(false),
+            max_sentence_silence: self.max_sentence_silence,
+            semantic_punctuation: self.semantic_punctuation,
+            disfluency_removal: self.disfluency_removal,
+            inverse_text_normalization: self.inverse_text_normalization,
+            heartbeat: self.heartbeat,
+            translate: if translate.is_empty() {
+                self.translate.clone().unwrap_or_default()
+            } else {
+                translate.to_vec()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::StreamSelector;
+
+    const EXAMPLE: &str = r#"
+default_profile = "dictation"
+
+[profiles.dictation]
+typing = "streaming"
+max_sentence_silence = 800
+fallback = ["openai", "batch"]
+failback = true
+translate = ["zh", "en"]
+
+[[profiles.dictation.outputs]]
+sink = "type"
+
+[[profiles.dictation.outputs]]
+sink = "file"
+stream = "translation:en"
+path = "en.txt"
+
+[profiles.meeting]
+model = "paraformer-realtime-v2"
+diarize = true
+semantic_punctuation = true
+disfluency_removal = true
+vocabulary = "team"
+content = "bilingual"
+content_template = "{source} / {translation}"
+heartbeat = true
+keepalive = "reconnect"
+max_silence = 3600
+
+[usage]
+currency = "CNY"
+monthly_cap = 50.0
+
+[usage.prices]
+"gummy-realtime-v1" = 0.00015
+"#;
+
+    #[test]
+    fn parses_profiles() {
+        let config = Config::parse(EXAMPLE).unwrap();
+
+        let dictation = config.profile(None).unwrap();
+        assert_eq!(dictation.typing, Some(TypingMode::Streaming));
+        assert_eq!(
+            dictation.fallback,
+            Some(vec![BackendKind::Openai, BackendKind::Batch])
+        );
+        assert_eq!(
+            dictation.recognition_options(false, &[]),
+            RecognitionOptions {
+                max_sentence_silence: Some(800),
+                translate: vec!["zh".to_string(), "en".to_string()],
+                ..RecognitionOptions::default()
+            }
+        );
+        let outputs = dictation.outputs.as_deref().unwrap();
+        assert_eq!(outputs.len(), 2);
+        assert!(matches!(
+            &outputs[1],
+            OutputConfig::File { stream: Some(StreamSelector::Translation(lang)), .. } if lang == "en"
+        ));
+        let one_way = dictation.recognition_options(false, &["ja".to_string()]);
+        assert_eq!(one_way.translate, vec!["ja"]);
+
+        let meeting = config.profile(Some("meeting")).unwrap();
+        let options = meeting.recognition_options(false, &[]);
+        assert!(options.diarization);
+        assert_eq!(options.semantic_punctuation, Some(true));
+        assert_eq!(meeting.keepalive, Some(KeepalivePolicy::Reconnect));
+        assert_eq!(meeting.max_silence, Some(3600));
+        assert_eq!(meeting.vocabulary.as_deref(), Some("team"));
+        assert_eq!(meeting.content, Some(Content::Bilingual));
+        assert_eq!(
+            meeting.content_template.unwrap().render("你好", "Hello"),
+            "你好 / Hello"
+        );
+        options.validate(meeting.model.as_deref().unwrap()).unwrap();
+
+        assert!(config.profile(Some("podcast")).is_err());
+
+        assert_eq!(config.usage.monthly_cap, Some(50.0));
+        assert_eq!(config.usage.prices["gummy-realtime-v1"], 0.00015);
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let error = Config::parse("[profiles.x]\nmax_pause = 800\n").unwrap_err();
+        assert!(format!("{:#}", error).contains("max_pause"));
+    }
+
+    #[test]
+    fn missing_default_profile_is_empty() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.profile(None).unwrap(), Profile::default());
+    }
+}
@@ -4,6 +4,7 @@ use tracing::{debug, error, info, warn};
 
 /// Text input handler for Wayland
 /// Uses wtype (preferred) or ydotool for text input simulation
+#[derive(Clone)]
 pub struct TextInputHandler {
     method: InputMethod,
 }
@@ -13,6 +14,7 @@ enum InputMethod {
     Wtype,
     Ydotool,
     WlCopy, // Fallback: copy to clipboard
+    Stdout, // Print instead of typing, e.g. when replaying a cassette
 }
 
 impl TextInputHandler {
@@ -35,18 +37,31 @@ impl TextInputHandler {
         Self { method }
     }
 
+    /// Print text to stdout instead of typing it into a window
+    pub fn stdout() -> Self {
+        Self {
+            method: InputMethod::Stdout,
+        }
+    }
+
+    /// Whether typed text can be deleted again (needed for live correction)
+    pub fn can_delete(&self) -> bool {
+        !matches!(self.method, InputMethod::WlCopy)
+    }
+
     /// Type the given text as keyboard input
     pub fn type_text(&self, text: &str) -> Result<()> {
         if text.is_empty() {
             return Ok(());
         }
 
-        debug!("Typing text: {}", text);
+        debug!("Typing text: {}", crate::logging::text(text));
 
         match self.method {
             InputMethod::Wtype => self.type_with_wtype(text),
             InputMethod::Ydotool => self.type_with_ydotool(text),
             InputMethod::WlCopy => self.copy_to_clipboard(text),
+            InputMethod::Stdout => self.print(text, 0),
         }
     }
 
@@ -85,6 +100,17 @@ impl TextInputHandler {
         Ok(())
     }
 
+    fn print(&self, text: &str, backspaces: usize) -> Result<()> {
+        use std::io::Write;
+        let mut stdout = std::io::stdout().lock();
+        for _ in 0..backspaces {
+            stdout.write_all(b"\x08 \x08")?;
+        }
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()?;
+        Ok(())
+    }
+
     fn copy_to_clipboard(&self, text: &str) -> Result<()> {
         // Fallback: copy to clipboard and let user paste
         let mut child = Command::new("wl-copy")
@@ -174,6 +200,7 @@ impl TextInputHandler {
                 }
                 Ok(())
             }
+            InputMethod::Stdout => self.print("", count),
             InputMethod::WlCopy => {
                 // Can't delete with clipboard method
                 warn!("Cannot delete characters with clipboard method");
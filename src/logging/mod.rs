@@ -0,0 +1,166 @@
+use anyhow::{Context, Result};
+use std::fmt;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// Days of JSON logs kept
+const MAX_LOG_FILES: usize = 14;
+
+/// Log transcript text and secrets as they are
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// Values replaced by `<redacted>` in every log line
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Log to stderr and, with `json`, also to a daily rotating file of JSON
+/// lines in `log_dir()`
+///
+/// Unless `verbose`, secrets registered with `add_secret()` and text wrapped
+/// in `text()` are left out of the logs.
+pub fn init(json: bool, verbose: bool) -> Result<()> {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+
+    let json_layer = if json {
+        let dir = log_dir().context("No state directory to write logs to")?;
+        let appender = RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix("audio2text")
+            .filename_suffix("jsonl")
+            .max_log_files(MAX_LOG_FILES)
+            .build(&dir)
+            .with_context(|| format!("Failed to open logs in {}", dir.display()))?;
+        Some(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .with_writer(Redacting(appender)),
+        )
+    } else {
+        None
+    };
+
+    // stderr keeps stdout free for replayed text
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env().add_directive(tracing::Level::INFO.into()))
+        .with(tracing_subscriber::fmt::layer().with_writer(Redacting(io::stderr)))
+        .with(json_layer)
+        .init();
+    Ok(())
+}
+
+/// Where JSON logs go: `~/.local/state/audio2text/logs`
+pub fn log_dir() -> Option<PathBuf> {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|dir| dir.join("audio2text").join("logs"))
+}
+
+/// Keep `secret` out of the logs
+pub fn add_secret(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+    let mut secrets = SECRETS.write().unwrap();
+    if !secrets.iter().any(|known| known == secret) {
+        secrets.push(secret.to_string());
+    }
+}
+
+/// Record the task the current session is on in its span (see `run_session`)
+pub fn record_task(backend: &str, model: &str, task_id: &str) {
+    let span = tracing::Span::current();
+    span.record("backend", backend);
+    span.record("model", model);
+    span.record("task_id", task_id);
+}
+
+/// Transcript text to log; only its length is shown unless verbose
+pub fn text(text: &str) -> Text<'_> {
+    Text(text)
+}
+
+pub struct Text<'a>(&'a str);
+
+impl fmt::Display for Text<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if VERBOSE.load(Ordering::Relaxed) {
+            f.write_str(self.0)
+        } else {
+            write!(f, "<{} chars>", self.0.chars().count())
+        }
+    }
+}
+
+/// `line` with every registered secret replaced, unless verbose
+fn redact(line: &str) -> Option<String> {
+    if VERBOSE.load(Ordering::Relaxed) {
+        return None;
+    }
+    let secrets = SECRETS.read().unwrap();
+    let found: Vec<&String> = secrets
+        .iter()
+        .filter(|secret| line.contains(secret.as_str()))
+        .collect();
+    if found.is_empty() {
+        return None;
+    }
+    Some(found.into_iter().fold(line.to_string(), |line, secret| {
+        line.replace(secret.as_str(), "<redacted>")
+    }))
+}
+
+/// Writers of a log layer, with secrets redacted from what they write
+struct Redacting<M>(M);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redacting<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+}
+
+/// Each log line arrives in a single `write`
+struct RedactingWriter<W>(W);
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match std::str::from_utf8(buf).ok().and_then(redact) {
+            Some(line) => self.0.write_all(line.as_bytes()),
+            None => self.0.write_all(buf),
+        }?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_secrets_and_text() {
+        add_secret("sk-logging-test");
+        let mut out = Vec::new();
+        RedactingWriter(&mut out)
+            .write_all(b"rejected sk-logging-test for \"sk-logging-test\"\n")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "rejected <redacted> for \"<redacted>\"\n"
+        );
+
+        assert_eq!(text("你好, world").to_string(), "<9 chars>");
+    }
+}
@@ -1,17 +1,234 @@
 mod audio;
+mod backend;
+mod config;
 mod input;
+mod logging;
+mod notify;
+mod output;
+mod transcript;
+mod typing;
+mod usage;
+mod vocab;
 mod websocket;
 
 use anyhow::{Context, Result};
+use clap::Parser;
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::signal;
-use tracing::{error, info, warn};
+use tokio::signal::unix::{signal as unix_signal, SignalKind};
+use tokio::sync::mpsc;
+use tracing::{error, info, instrument, warn, Instrument};
 
-use audio::AudioCapture;
+use audio::{AudioCapture, AudioChunk};
+use backend::{Backend, BackendChain, BackendKind};
+use config::{Config, Profile};
 use input::TextInputHandler;
-use websocket::{AsrClient, AsrEvent};
+use notify::notify;
+use output::{Content, Route, Router, StreamSelector, Template};
+use transcript::TranscriptWriter;
+use typing::TypingMode;
+use usage::{Ledger, Period, UsageConfig, UsageTable};
+use vocab::VocabularyClient;
+use websocket::{
+    AsrClient, AsrError, AsrEvent, BatchClient, CassetteRecorder, ConnectionConfig,
+    KeepalivePolicy, KeyPool, LatencyTracker, MockServer, RealtimeClient, RecognitionOptions,
+    WarmConnection,
+};
+
+/// Real-time speech recognition that types into the focused window
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Stay running in the background and toggle recording on SIGUSR1,
+    /// keeping a pre-warmed connection ready so recording starts instantly
+    #[arg(long)]
+    daemon: bool,
+
+    /// When to type recognized text [default: final]
+    #[arg(long, value_enum)]
+    typing: Option<TypingMode>,
+
+    /// Recognition service protocol [default: dashscope]
+    #[arg(long, value_enum)]
+    backend: Option<BackendKind>,
+
+    /// Backends to switch to when the active one fails, in order
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "BACKEND")]
+    fallback: Vec<BackendKind>,
+
+    /// After failing over, go back to the primary backend for the next
+    /// recording once it is reachable again
+    #[arg(long)]
+    failback: bool,
+
+    /// Recognition model [default: gummy-realtime-v1, gpt-4o-transcribe with
+    /// the openai backend, whisper-1 with the batch backend]
+    #[arg(long)]
+    model: Option<String>,
+
+    /// Profile from the config file (model, typing and recognition parameters)
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Config file [default: ~/.config/audio2text/config.toml]
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Separate speakers and label transcript lines with "Speaker N:"
+    /// (needs a model that supports diarization)
+    #[arg(long)]
+    diarize: bool,
+
+    /// Translate into this language, or with several (e.g. `zh,en`) show
+    /// each sentence in the first one that was not spoken
+    /// [default: en, on models that translate]
+    #[arg(long, value_delimiter = ',', value_name = "LANG")]
+    translate: Vec<String>,
+
+    /// What to type and write to transcripts of each sentence
+    /// [default: translation]
+    #[arg(long, value_enum)]
+    content: Option<Content>,
+
+    /// How bilingual content is written, with `{source}` and `{translation}`
+    /// [default: "{source} ({translation})"]
+    #[arg(long, value_name = "TEMPLATE")]
+    content_template: Option<Template>,
+
+    /// Append final sentences to this transcript file
+    #[arg(long, value_name = "PATH")]
+    transcript: Option<PathBuf>,
+
+    /// How to keep the task open while nobody speaks [default: off]
+    #[arg(long, value_enum)]
+    keepalive: Option<KeepalivePolicy>,
+
+    /// Stop after this many seconds without speech, 0 to keep recording [default: 60]
+    #[arg(long, value_name = "SECONDS")]
+    max_silence: Option<u64>,
+
+    /// Meeting mode: never auto-stop, rotate to a new task before the
+    /// server's task limit, and write a timestamped transcript
+    #[arg(long)]
+    meeting: bool,
+
+    /// Rotate to a new task after this many minutes [default: 30 in meeting mode]
+    #[arg(long, value_name = "MINUTES")]
+    rotate_after: Option<u64>,
+
+    /// Hotword vocabulary to bias recognition, by name (see `vocab`) or id
+    #[arg(long, value_name = "NAME")]
+    vocabulary: Option<String>,
+
+    /// Record all protocol traffic to a JSONL cassette for debugging
+    #[arg(long, value_name = "PATH")]
+    record: Option<PathBuf>,
+
+    /// Also write JSON logs to a daily rotating file in
+    /// ~/.local/state/audio2text/logs
+    #[arg(long)]
+    log_json: bool,
+
+    /// Log transcript text and secrets, which are redacted by default
+    #[arg(long, short)]
+    verbose: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Work with saved transcripts
+    #[command(subcommand)]
+    Transcript(TranscriptCommand),
+    /// Feed a recorded cassette through event handling and the output
+    /// sinks, without network or microphone
+    Replay {
+        /// Cassette written with --record (or a capture of server events)
+        cassette: PathBuf,
+        /// Keep the recorded timing instead of replaying as fast as possible
+        #[arg(long)]
+        realtime: bool,
+        /// Type into the focused window instead of printing to stdout
+        #[arg(long = "type")]
+        type_text: bool,
+    },
+    /// Manage hotword vocabularies on DashScope
+    #[command(subcommand)]
+    Vocab(VocabCommand),
+    /// Stream a WAV file in real time and report end-to-end latency
+    Bench {
+        /// 16kHz mono 16-bit WAV file
+        wav: PathBuf,
+        /// Answer from a local mock server with the results of this cassette
+        /// (or fixture) instead of DashScope
+        #[arg(long, value_name = "CASSETTE")]
+        mock: Option<PathBuf>,
+        /// Type into the focused window instead of printing to stdout
+        #[arg(long = "type")]
+        type_text: bool,
+    },
+    /// Summarize the usage ledger with estimated costs
+    Usage {
+        /// Length of the periods to sum up
+        #[arg(long, value_enum, default_value_t = Period::Day)]
+        by: Period,
+        /// Only count sessions started on or after this date (YYYY-MM-DD)
+        #[arg(long, value_name = "DATE")]
+        since: Option<chrono::NaiveDate>,
+        /// Split each period by API key label
+        #[arg(long)]
+        per_key: bool,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum TranscriptCommand {
+    /// Replace a speaker label, e.g. `rename-speaker meeting.txt 1 Alice`
+    RenameSpeaker {
+        /// Transcript file
+        file: PathBuf,
+        /// Speaker number or current label
+        from: String,
+        /// New label
+        to: String,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum VocabCommand {
+    /// Upload a hotword file (TOML or CSV) as a new vocabulary
+    Create {
+        /// Up to 10 lowercase letters and digits
+        name: String,
+        /// Hotword file, `.csv` with `text,weight,lang` columns or TOML
+        file: PathBuf,
+    },
+    /// List vocabularies, optionally only those starting with a prefix
+    List { prefix: Option<String> },
+    /// Show the hotwords of a vocabulary
+    Show {
+        /// Vocabulary name or id
+        name: String,
+    },
+    /// Replace the hotwords of a vocabulary
+    Update {
+        /// Vocabulary name or id
+        name: String,
+        /// Hotword file, `.csv` with `text,weight,lang` columns or TOML
+        file: PathBuf,
+    },
+    /// Delete a vocabulary
+    Delete {
+        /// Vocabulary name or id
+        name: String,
+    },
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum AppState {
@@ -19,108 +236,197 @@ enum AppState {
     Recording,
 }
 
-/// Maximum silence duration before auto-stop (60 seconds of no speech detected by ASR)
-const MAX_SILENCE_SECONDS: u64 = 60;
+/// Commands delivered to the main loop by the signal handlers
+#[derive(Debug, Clone, Copy)]
+enum Control {
+    /// Start or stop recording (SIGUSR1)
+    Toggle,
+    /// Stop recording and exit (Ctrl+C, SIGTERM)
+    Shutdown,
+}
+
+/// Why `run_session` returned
+#[derive(Debug)]
+enum SessionStop {
+    /// Recording was toggled off
+    Toggled,
+    /// Ctrl+C or SIGTERM was received
+    Shutdown,
+    /// No speech was detected for `App::max_silence_seconds`
+    Silence,
+    /// The server finished the task on its own
+    Finished,
+    /// The session failed and could not be restarted
+    Failed(AsrError),
+}
+
+/// How a recording session ended, reported by the event handler task
+#[derive(Debug, Clone)]
+enum SessionEnd {
+    /// The server finished the task normally
+    Finished,
+    /// The task failed or the connection was lost
+    Failed(AsrError),
+}
+
+/// Task age after which meeting mode rotates to a new task, well below the
+/// server's maximum task duration
+const DEFAULT_ROTATE_AFTER_MINUTES: u64 = 30;
+
+/// Default silence duration before auto-stop (60 seconds of no speech detected by ASR)
+const DEFAULT_MAX_SILENCE_SECONDS: u64 = 60;
+
+/// Restart attempts for retryable failures before giving up
+const MAX_RESTARTS: u32 = 3;
+
+/// Exit codes so hotkey scripts can tell why the process stopped
+const EXIT_FAILURE: u8 = 1;
+const EXIT_AUTH_ERROR: u8 = 2;
+const EXIT_NETWORK_ERROR: u8 = 3;
+const EXIT_QUOTA_ERROR: u8 = 4;
+
+/// Exit code when a second Ctrl+C forces quit (128 + SIGINT, as shells report it)
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// How long to wait for the final results after stopping capture
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Audio chunks (100ms each) queued while the connection and task are being
+/// set up, so speech is not lost when capture starts before the server is ready
+const AUDIO_QUEUE_CHUNKS: usize = 300;
 
 struct App {
     state: AppState,
     audio_capture: AudioCapture,
-    text_input: TextInputHandler,
-    api_key: String,
+    backend: BackendChain,
+    /// Where each stream of the results goes
+    routes: Vec<Route>,
+    /// Auto-stop after this many seconds without speech; 0 never stops
+    max_silence_seconds: u64,
     current_text: String,
-    audio_tx: Option<tokio::sync::mpsc::Sender<Vec<u8>>>,
+    audio_tx: Option<tokio::sync::mpsc::Sender<AudioChunk>>,
     last_asr_result: Arc<AtomicBool>,
+    warm: Option<WarmConnection>,
+    latency: Arc<LatencyTracker>,
+    usage: UsageConfig,
+    ledger: Option<Arc<Ledger>>,
 }
 
 impl App {
-    fn new(api_key: String) -> Self {
+    fn new(backend: BackendChain, routes: Vec<Route>) -> Self {
+        let latency = Arc::new(LatencyTracker::default());
         Self {
             state: AppState::Idle,
             audio_capture: AudioCapture::new(),
-            text_input: TextInputHandler::new(),
-            api_key,
+            backend: backend.with_latency(latency.clone()),
+            routes,
+            max_silence_seconds: DEFAULT_MAX_SILENCE_SECONDS,
             current_text: String::new(),
             audio_tx: None,
             last_asr_result: Arc::new(AtomicBool::new(false)),
+            warm: None,
+            latency,
+            usage: UsageConfig::default(),
+            ledger: None,
         }
     }
 
-    async fn start_recording(&mut self) -> Result<()> {
+    /// Record every task in `ledger`, enforcing the spending cap of `usage`
+    fn set_ledger(&mut self, usage: UsageConfig, ledger: Arc<Ledger>) {
+        self.backend = self.backend.clone().with_ledger(ledger.clone());
+        self.usage = usage;
+        self.ledger = Some(ledger);
+    }
+
+    /// The error to stop with if this month's spending has reached the cap
+    fn spending_cap_reached(&self) -> Option<AsrError> {
+        let ledger = self.ledger.as_ref()?;
+        self.usage.monthly_cap?;
+        match ledger.read() {
+            Ok(entries) => self.usage.check_cap(&entries, chrono::Local::now()).err(),
+            Err(e) => {
+                warn!("Not checking the spending cap: {:#}", e);
+                None
+            }
+        }
+    }
+
+    /// Keep a connection open in the background for the next session
+    fn enable_prewarm(&mut self) {
+        match self.backend.dashscope() {
+            Some(client) => self.warm = Some(WarmConnection::spawn(client.clone())),
+            None => info!("Pre-warmed connections are only available with the dashscope backend"),
+        }
+    }
+
+    /// Start capturing audio and streaming it to the ASR service
+    /// Returns a receiver that yields once the ASR session has ended
+    async fn start_recording(&mut self) -> Result<mpsc::Receiver<SessionEnd>> {
         info!("Starting recording...");
 
         // Reset ASR result flag
         self.last_asr_result.store(false, Ordering::SeqCst);
 
         // Create channels
-        let (audio_tx, audio_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(100);
+        let (audio_tx, audio_rx) = tokio::sync::mpsc::channel::<AudioChunk>(AUDIO_QUEUE_CHUNKS);
         let (event_tx, event_rx) = tokio::sync::mpsc::channel::<AsrEvent>(100);
 
         // Store audio sender for later use
         self.audio_tx = Some(audio_tx.clone());
 
-        // Share the ASR result flag with the event handler
-        let last_asr_result = self.last_asr_result.clone();
-
-        // Start audio capture
+        // Start audio capture; chunks queue up until the ASR task has started
         self.audio_capture.start(audio_tx)?;
 
+        let warm_connection = match &self.warm {
+            Some(warm) => warm.take().await,
+            None => None,
+        };
+
         // Start ASR client
-        let api_key = self.api_key.clone();
-        tokio::spawn(async move {
-            let mut client = AsrClient::new(api_key);
-            if let Err(e) = client.start_recognition(audio_rx, event_tx).await {
-                error!("ASR client error: {}", e);
+        info!("Recognizing with {}", self.backend.active());
+        let mut backend = self.backend.clone();
+        tokio::spawn(
+            async move {
+                let result = backend
+                    .run(warm_connection, audio_rx, event_tx.clone())
+                    .await;
+                if let Err(e) = result {
+                    error!("ASR client error: {:#}", e);
+                    let error = AsrError::from_anyhow(&e);
+                    let _ = event_tx.send(AsrEvent::TaskFailed { error }).await;
+                }
             }
-        });
+            .in_current_span(),
+        );
 
         // Handle ASR events in a separate task
-        let text_input = self.text_input.clone();
-        tokio::spawn(async move {
-            let mut event_rx = event_rx;
-            while let Some(event) = event_rx.recv().await {
-                match event {
-                    AsrEvent::TaskStarted => {
-                        info!("ASR task started");
-                    }
-                    AsrEvent::ResultGenerated { text, is_final } => {
-                        // Update flag when we receive any ASR result (speech detected)
-                        last_asr_result.store(true, Ordering::SeqCst);
-
-                        if is_final {
-                            // Type the final text
-                            if let Err(e) = text_input.type_text(&text) {
-                                error!("Failed to type text: {}", e);
-                            }
-                            info!("Final: {}", text);
-                        } else {
-                            // Partial result
-                            info!("Partial: {}", text);
-                        }
-                    }
-                    AsrEvent::TaskFinished => {
-                        info!("ASR task finished");
-                        break;
-                    }
-                    AsrEvent::TaskFailed { error } => {
-                        error!("ASR task failed: {}", error);
-                        break;
-                    }
-                }
-            }
-        });
+        let end_rx = spawn_event_handler(
+            event_rx,
+            Router::open(&self.routes, Some(self.latency.clone())),
+            self.last_asr_result.clone(),
+        );
 
         self.state = AppState::Recording;
-        info!("Recording started. Will auto-stop after {} seconds of silence.", MAX_SILENCE_SECONDS);
+        if self.max_silence_seconds > 0 {
+            info!(
+                "Recording started. Will auto-stop after {} seconds of silence.",
+                self.max_silence_seconds
+            );
+        } else {
+            info!("Recording started.");
+        }
         info!("Press Ctrl+C to stop manually.");
 
-        Ok(())
+        Ok(end_rx)
     }
 
     async fn stop_recording(&mut self) -> Result<()> {
         info!("Stopping recording...");
 
         // Stop audio capture (this will close the audio sender)
-        self.audio_capture.stop();
+        if self.audio_capture.is_recording() {
+            self.audio_capture.stop();
+        }
         self.audio_tx = None;
 
         // Reset state
@@ -131,6 +437,53 @@ impl App {
         Ok(())
     }
 
+    /// Stop capturing and wait for the ASR task to drain the queued audio,
+    /// send finish-task and type the remaining final results
+    async fn finish_recording(
+        &mut self,
+        mut session_end: mpsc::Receiver<SessionEnd>,
+    ) -> Result<()> {
+        self.stop_recording().await?;
+
+        info!(
+            "Waiting up to {}s for final results...",
+            FLUSH_TIMEOUT.as_secs()
+        );
+        match tokio::time::timeout(FLUSH_TIMEOUT, session_end.recv()).await {
+            Ok(Some(SessionEnd::Finished)) | Ok(None) => {
+                info!("Final results flushed");
+            }
+            Ok(Some(SessionEnd::Failed(error))) => {
+                warn!("ASR session failed while flushing final results: {}", error);
+            }
+            Err(_) => {
+                warn!("Timed out waiting for final results");
+            }
+        }
+
+        let report = self.latency.take_report();
+        if !report.is_empty() {
+            info!("Latency of this session:\n{}", report);
+        }
+
+        let keys = self.backend.dashscope().map(|client| client.keys());
+        for (label, usage) in keys
+            .filter(|keys| keys.len() > 1)
+            .map(|keys| keys.usage())
+            .unwrap_or_default()
+        {
+            info!(
+                "API key {} so far: {} task(s), {:.1}s of audio, rate limited {}x, out of quota {}x",
+                label,
+                usage.tasks,
+                usage.audio_ms as f32 / 1000.0,
+                usage.rate_limited,
+                usage.quota_exceeded
+            );
+        }
+        Ok(())
+    }
+
     /// Check if ASR has detected any speech since the last check
     fn check_and_reset_asr_result(&self) -> bool {
         let result = self.last_asr_result.load(Ordering::SeqCst);
@@ -141,98 +494,861 @@ impl App {
     }
 }
 
-impl Clone for TextInputHandler {
-    fn clone(&self) -> Self {
-        TextInputHandler::new()
+/// Output the results of one session as they arrive
+/// Returns a receiver that yields once the session has ended
+fn spawn_event_handler(
+    mut event_rx: mpsc::Receiver<AsrEvent>,
+    mut router: Router,
+    last_asr_result: Arc<AtomicBool>,
+) -> mpsc::Receiver<SessionEnd> {
+    let (end_tx, end_rx) = mpsc::channel::<SessionEnd>(1);
+
+    tokio::spawn(
+        async move {
+            let mut end = SessionEnd::Failed(AsrError::Protocol(
+                "ASR client stopped without finishing the task".to_string(),
+            ));
+            while let Some(event) = event_rx.recv().await {
+                match event {
+                    AsrEvent::TaskStarted => {
+                        info!("ASR task started");
+                    }
+                    AsrEvent::ResultGenerated(result) => {
+                        // Update flag when we receive any ASR result (speech detected)
+                        last_asr_result.store(true, Ordering::SeqCst);
+
+                        if result.is_final
+                            && result.pre_end_failed
+                            && result.transcription.is_some()
+                        {
+                            warn!(
+                                "Translation of sentence {} incomplete, using transcription",
+                                result.sentence_id
+                            );
+                        }
+                        router.result(&result);
+
+                        let Some(stream) = StreamSelector::Preferred.select(&result) else {
+                            continue;
+                        };
+                        let text = logging::text(&stream.text);
+                        if result.is_final {
+                            info!(
+                                sentence_id = result.sentence_id,
+                                begin_ms = result.begin_time,
+                                end_ms = result.end_time,
+                                "Final [{}-{}ms]: {}",
+                                result.begin_time,
+                                result.end_time,
+                                text
+                            );
+                        } else {
+                            info!(sentence_id = result.sentence_id, "Partial: {}", text);
+                        }
+                    }
+                    AsrEvent::TaskFinished => {
+                        info!("ASR task finished");
+                        end = SessionEnd::Finished;
+                        break;
+                    }
+                    AsrEvent::TaskFailed { error } => {
+                        error!(
+                            "ASR task failed [{}] (retryable: {}): {}",
+                            error.code().unwrap_or("-"),
+                            error.is_retryable(),
+                            error
+                        );
+                        end = SessionEnd::Failed(error);
+                        break;
+                    }
+                }
+            }
+            router.finish();
+            let _ = end_tx.send(end).await;
+        }
+        .in_current_span(),
+    );
+
+    end_rx
+}
+
+/// Outputs from the profile, or typing the content, plus the `transcript`
+/// of the content if given; `type` outputs type with `input`
+fn routes_for(
+    cli: &Cli,
+    profile: &Profile,
+    typing: TypingMode,
+    input: &TextInputHandler,
+    transcript: Option<TranscriptWriter>,
+) -> Result<Vec<Route>> {
+    let template = cli
+        .content_template
+        .clone()
+        .or_else(|| profile.content_template.clone())
+        .unwrap_or_default();
+    let content = cli
+        .content
+        .or(profile.content)
+        .unwrap_or_default()
+        .stream(&template);
+    let mut routes = match &profile.outputs {
+        Some(outputs) => outputs
+            .iter()
+            .map(|output| Route::from_config(output, &content, &template, input, typing))
+            .collect::<Result<Vec<_>>>()?,
+        None => vec![Route::typing(content.clone(), input.clone(), typing)],
+    };
+    if let Some(writer) = transcript {
+        routes.push(Route::transcript(content, writer));
     }
+    Ok(routes)
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize logging
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive(tracing::Level::INFO.into()),
-        )
-        .init();
+async fn main() -> Result<ExitCode> {
+    let mut cli = Cli::parse();
+
+    logging::init(cli.log_json, cli.verbose)?;
 
     // Load .env file if present
     let _ = dotenvy::dotenv();
 
-    // Get API key
-    let api_key = std::env::var("DASHSCOPE_API_KEY")
-        .context("DASHSCOPE_API_KEY environment variable not set")?;
+    // Command line flags override the selected profile
+    let config = Config::load(cli.config.as_deref())?;
+    let profile = config.profile(cli.profile.as_deref())?;
+    let typing = cli.typing.or(profile.typing).unwrap_or_default();
+
+    if let Some(command) = cli.command.take() {
+        return run_command(command, &cli, &config, &profile, typing).await;
+    }
+
+    let options = profile.recognition_options(cli.diarize, &cli.translate);
+
+    let meeting = cli.meeting || profile.meeting.unwrap_or(false);
+    let rotate_after = cli
+        .rotate_after
+        .or(profile.rotate_after)
+        .or(meeting.then_some(DEFAULT_ROTATE_AFTER_MINUTES))
+        .filter(|&minutes| minutes > 0);
+
+    let primary = cli.backend.or(profile.backend).unwrap_or_default();
+    if primary != BackendKind::Dashscope {
+        if cli.vocabulary.is_some() || profile.vocabulary.is_some() {
+            anyhow::bail!("Vocabularies are only supported by the dashscope backend");
+        }
+        if cli.record.is_some() {
+            warn!("--record only records DashScope traffic, ignoring it");
+        }
+    }
+    let model = cli.model.clone().or_else(|| profile.model.clone());
+    let mut backend = BackendChain::new(
+        primary,
+        backend_for(
+            primary,
+            &cli,
+            &profile,
+            model,
+            options.clone(),
+            rotate_after,
+        )
+        .await?,
+    );
+
+    let fallbacks = match (cli.fallback.is_empty(), &profile.fallback) {
+        (true, Some(fallbacks)) => fallbacks.clone(),
+        _ => cli.fallback.clone(),
+    };
+    let mut chain = vec![primary];
+    for kind in fallbacks {
+        if chain.contains(&kind) {
+            anyhow::bail!("Backend {} appears twice in the fallback chain", kind);
+        }
+        chain.push(kind);
+        // Fallbacks use their default model and whatever settings they support
+        let options = match kind {
+            BackendKind::Dashscope => options.clone(),
+            _ => RecognitionOptions {
+                max_sentence_silence: options.max_sentence_silence,
+                ..RecognitionOptions::default()
+            },
+        };
+        let fallback = backend_for(kind, &cli, &profile, None, options, rotate_after)
+            .await
+            .with_context(|| format!("Failed to set up fallback backend {}", kind))?;
+        backend = backend.with_fallback(kind, fallback);
+    }
+    if chain.len() > 1 {
+        info!(
+            "Backend chain: {}",
+            chain
+                .iter()
+                .map(BackendKind::to_string)
+                .collect::<Vec<_>>()
+                .join(" -> ")
+        );
+    }
+    let backend = backend.with_failback(cli.failback || profile.failback.unwrap_or(false));
 
-    info!("Audio2Text v{} - Real-time speech recognition", env!("CARGO_PKG_VERSION"));
-    info!("Will auto-stop after {} seconds of silence (no speech detected).", MAX_SILENCE_SECONDS);
+    info!(
+        "Audio2Text v{} - Real-time speech recognition",
+        env!("CARGO_PKG_VERSION")
+    );
     info!("Press Ctrl+C to stop manually.");
 
     // Check for required tools
     check_dependencies();
 
-    // Create app
-    let app = Arc::new(tokio::sync::Mutex::new(App::new(api_key)));
+    // Meetings always get a transcript, so a crash loses at most the current sentence
+    let transcript_path = cli.transcript.clone().or_else(|| {
+        meeting.then(|| {
+            let started = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            PathBuf::from(format!("meeting-{}.txt", started))
+        })
+    });
+    let transcript = match &transcript_path {
+        Some(path) => {
+            let writer = TranscriptWriter::create(path)?;
+            info!("Writing transcript to {}", path.display());
+            Some(if meeting {
+                writer.with_timestamps()
+            } else {
+                writer
+            })
+        }
+        None => None,
+    };
+    let text_input = TextInputHandler::new();
+    let routes = routes_for(&cli, &profile, typing, &text_input, transcript)?;
+
+    // Live correction backspaces over earlier hypotheses, which the clipboard
+    // fallback cannot do
+    if routes.iter().any(Route::deletes_text) && !text_input.can_delete() {
+        anyhow::bail!("Live typing needs wtype or ydotool; wl-copy cannot delete typed text");
+    }
+
+    let mut app = App::new(backend, routes);
+    match config.usage.ledger_path() {
+        Some(path) => {
+            info!("Recording usage to {}", path.display());
+            app.set_ledger(config.usage.clone(), Arc::new(Ledger::new(path)));
+        }
+        None => warn!("No data directory, usage is not recorded"),
+    }
+    app.max_silence_seconds = cli
+        .max_silence
+        .or(profile.max_silence)
+        .unwrap_or(if meeting {
+            0
+        } else {
+            DEFAULT_MAX_SILENCE_SECONDS
+        });
+
+    // Handle shutdown and toggle signals
+    let (control_tx, mut control_rx) = mpsc::channel::<Control>(8);
+    spawn_signal_handlers(control_tx)?;
+
+    if cli.daemon {
+        return run_daemon(&mut app, &mut control_rx).await;
+    }
+
+    // Start recording immediately
+    let stop = run_session(&mut app, &mut control_rx).await?;
+
+    info!("Goodbye!");
+    Ok(match stop {
+        SessionStop::Failed(error) => exit_code_for(&error),
+        _ => ExitCode::SUCCESS,
+    })
+}
+
+/// Client for `kind`, using `model` or the backend's default
+async fn backend_for(
+    kind: BackendKind,
+    cli: &Cli,
+    profile: &Profile,
+    model: Option<String>,
+    options: RecognitionOptions,
+    rotate_after: Option<u64>,
+) -> Result<Backend> {
+    Ok(match kind {
+        BackendKind::Dashscope => {
+            Backend::DashScope(dashscope_client(cli, profile, model, options, rotate_after).await?)
+        }
+        BackendKind::Openai => Backend::OpenAi(openai_client(model, &options)?),
+        BackendKind::Batch => Backend::Batch(batch_client(model, &options)?),
+    })
+}
+
+/// DashScope client for the main session, from the flags and profile
+async fn dashscope_client(
+    cli: &Cli,
+    profile: &Profile,
+    model: Option<String>,
+    mut options: RecognitionOptions,
+    rotate_after: Option<u64>,
+) -> Result<AsrClient> {
+    let model = model.unwrap_or_else(|| websocket::DEFAULT_MODEL.to_string());
+
+    // Heartbeat keepalive relies on the model's heartbeat parameter
+    let keepalive = match cli.keepalive.or(profile.keepalive).unwrap_or_default() {
+        KeepalivePolicy::Auto if options.heartbeat == Some(false) => KeepalivePolicy::Comfort,
+        policy => policy.resolve(&model),
+    };
+    if keepalive == KeepalivePolicy::Heartbeat {
+        options.heartbeat = Some(true);
+    }
+    options
+        .validate(&model)
+        .with_context(|| format!("Recognition settings do not fit model {}", model))?;
+
+    // API keys, referred to by label from here on
+    let keys = Arc::new(KeyPool::from_env()?);
+
+    // Endpoint, proxy and CA bundle
+    let connection = ConnectionConfig::from_env()?;
+    info!("DashScope endpoint: {}", connection.endpoint);
+    if let Some(proxy) = &connection.proxy {
+        info!("Using proxy: {:?}", proxy);
+    }
+
+    // Vocabularies are referenced by name and sent to the server by id
+    let vocabulary_id = match cli.vocabulary.as_ref().or(profile.vocabulary.as_ref()) {
+        Some(name) => {
+            let api_key = keys.acquire()?.key.expose().to_string();
+            let id = VocabularyClient::from_connection(api_key, &connection)?
+                .resolve(name)
+                .await
+                .with_context(|| format!("Failed to look up vocabulary {:?}", name))?;
+            info!("Using vocabulary {} ({})", name, id);
+            Some(id)
+        }
+        None => None,
+    };
+
+    let client = AsrClient::from_keys(keys)
+        .with_connection(connection)
+        .with_vocabulary(vocabulary_id)
+        .with_model(model)
+        .with_options(options)
+        .with_keepalive(keepalive)
+        .with_rotation(rotate_after.map(|minutes| Duration::from_secs(minutes * 60)));
+    info!("Keepalive while silent: {:?}", keepalive);
+    Ok(match &cli.record {
+        Some(path) => {
+            info!("Recording protocol traffic to {}", path.display());
+            client.with_recorder(Arc::new(CassetteRecorder::create(path)?))
+        }
+        None => client,
+    })
+}
+
+/// OpenAI realtime client, configured from `OPENAI_REALTIME_URL` and `OPENAI_API_KEY`
+fn openai_client(model: Option<String>, options: &RecognitionOptions) -> Result<RealtimeClient> {
+    let model = model.unwrap_or_else(|| websocket::DEFAULT_OPENAI_MODEL.to_string());
+    let client = RealtimeClient::from_env()?
+        .with_model(model)
+        .with_options(options)
+        .context("Recognition settings do not fit the openai backend")?;
+    info!("Realtime endpoint: {}", client.endpoint());
+    Ok(client)
+}
+
+/// Batch transcription client, configured from `TRANSCRIPTION_URL` and `TRANSCRIPTION_API_KEY`
+fn batch_client(model: Option<String>, options: &RecognitionOptions) -> Result<BatchClient> {
+    let model = model.unwrap_or_else(|| websocket::DEFAULT_BATCH_MODEL.to_string());
+    let client = BatchClient::from_env()?
+        .with_model(model)
+        .with_options(options)
+        .context("Recognition settings do not fit the batch backend")?;
+    info!("Transcription endpoint: {}", client.endpoint());
+    Ok(client)
+}
+
+/// Model from the command line, the profile, or the default
+fn model_for(cli: &Cli, profile: &Profile) -> String {
+    cli.model
+        .clone()
+        .or_else(|| profile.model.clone())
+        .unwrap_or_else(|| websocket::DEFAULT_MODEL.to_string())
+}
+
+/// Run a subcommand that does not record
+async fn run_command(
+    command: Command,
+    cli: &Cli,
+    config: &Config,
+    profile: &Profile,
+    typing: TypingMode,
+) -> Result<ExitCode> {
+    match command {
+        Command::Transcript(TranscriptCommand::RenameSpeaker { file, from, to }) => {
+            let count = transcript::rename_speaker(&file, &from, &to)?;
+            if count == 0 {
+                anyhow::bail!("No lines labeled {:?} in {}", from, file.display());
+            }
+            info!("Renamed {} line(s) in {}", count, file.display());
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Replay {
+            cassette,
+            realtime,
+            type_text,
+        } => {
+            let entries = websocket::read_cassette(&cassette)?;
+            info!(
+                "Replaying {} entries from {}",
+                entries.len(),
+                cassette.display()
+            );
+
+            let input = if type_text {
+                TextInputHandler::new()
+            } else {
+                TextInputHandler::stdout()
+            };
+            let transcript = match &cli.transcript {
+                Some(path) => Some(TranscriptWriter::create(path)?),
+                None => None,
+            };
+            let routes = routes_for(cli, profile, typing, &input, transcript)?;
+
+            let (event_tx, event_rx) = mpsc::channel::<AsrEvent>(100);
+            let mut session_end = spawn_event_handler(
+                event_rx,
+                Router::open(&routes, None),
+                Arc::new(AtomicBool::new(false)),
+            );
+            websocket::replay(entries, event_tx, realtime).await?;
+
+            let end = session_end.recv().await;
+            if !type_text {
+                println!();
+            }
+            Ok(match end {
+                Some(SessionEnd::Failed(error)) => {
+                    warn!("Replayed session failed: {}", error);
+                    exit_code_for(&error)
+                }
+                _ => ExitCode::SUCCESS,
+            })
+        }
+        Command::Bench {
+            wav,
+            mock,
+            type_text,
+        } => run_bench(&wav, mock.as_deref(), type_text, cli, profile, typing).await,
+        Command::Usage { by, since, per_key } => {
+            run_usage_report(&config.usage, by, since, per_key)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Vocab(command) => {
+            let api_key = KeyPool::from_env()?.acquire()?.key.expose().to_string();
+            let client =
+                VocabularyClient::from_connection(api_key, &ConnectionConfig::from_env()?)?;
+            match run_vocab_command(command, &client, &model_for(cli, profile)).await {
+                Ok(()) => Ok(ExitCode::SUCCESS),
+                Err(e) => match e.downcast_ref::<AsrError>() {
+                    Some(error) => {
+                        error!("{:#}", e);
+                        Ok(exit_code_for(error))
+                    }
+                    None => Err(e),
+                },
+            }
+        }
+    }
+}
+
+/// Stream a WAV file in real time, as if spoken into the microphone, and
+/// print the latency report
+async fn run_bench(
+    wav: &std::path::Path,
+    mock: Option<&std::path::Path>,
+    type_text: bool,
+    cli: &Cli,
+    profile: &Profile,
+    typing: TypingMode,
+) -> Result<ExitCode> {
+    let chunks = audio::read_wav_chunks(wav)?;
+    info!(
+        "Benchmarking with {} ({:.1}s)",
+        wav.display(),
+        chunks.len() as f32 / 10.0
+    );
 
-    // Handle shutdown signal
-    let running = Arc::new(AtomicBool::new(true));
-    let running_clone = running.clone();
+    // Keep the server alive until the session is over
+    let (_server, client) = match mock {
+        Some(cassette) => {
+            let server = MockServer::start(websocket::read_cassette(cassette)?).await?;
+            let client = AsrClient::new("mock".to_string()).with_connection(server.connection());
+            info!("Mock server answering from {}", cassette.display());
+            (Some(server), client)
+        }
+        None => (
+            None,
+            AsrClient::from_keys(Arc::new(KeyPool::from_env()?))
+                .with_connection(ConnectionConfig::from_env()?),
+        ),
+    };
+
+    let model = model_for(cli, profile);
+    let options = profile.recognition_options(cli.diarize, &cli.translate);
+    options
+        .validate(&model)
+        .with_context(|| format!("Recognition settings do not fit model {}", model))?;
+    let latency = Arc::new(LatencyTracker::default());
+    let mut client = client
+        .with_model(model)
+        .with_options(options)
+        .with_latency(latency.clone());
+
+    let input = if type_text {
+        TextInputHandler::new()
+    } else {
+        TextInputHandler::stdout()
+    };
+    let routes = routes_for(cli, profile, typing, &input, None)?;
+    let (audio_tx, audio_rx) = mpsc::channel::<AudioChunk>(AUDIO_QUEUE_CHUNKS);
+    let (event_tx, event_rx) = mpsc::channel::<AsrEvent>(100);
+    let mut session_end = spawn_event_handler(
+        event_rx,
+        Router::open(&routes, Some(latency.clone())),
+        Arc::new(AtomicBool::new(false)),
+    );
 
-    // Spawn a separate task for Ctrl+C handling
+    // Release one chunk per 100ms, stamped like the microphone would
     tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(100));
+        for data in chunks {
+            interval.tick().await;
+            if audio_tx.send(AudioChunk::new(data)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    if let Err(e) = client.start_recognition(audio_rx, event_tx).await {
+        error!("ASR client error: {:#}", e);
+    }
+    let end = session_end.recv().await;
+    if !type_text {
+        println!();
+    }
+    println!("{}", latency.take_report());
+
+    Ok(match end {
+        Some(SessionEnd::Failed(error)) => exit_code_for(&error),
+        _ => ExitCode::SUCCESS,
+    })
+}
+
+/// Print the usage ledger summed up by period, and this month's spending
+/// against the cap
+fn run_usage_report(
+    config: &UsageConfig,
+    by: Period,
+    since: Option<chrono::NaiveDate>,
+    per_key: bool,
+) -> Result<()> {
+    let path = config
+        .ledger_path()
+        .context("No data directory for the usage ledger")?;
+    let entries = Ledger::new(path.clone()).read()?;
+    let selected: Vec<_> = entries
+        .iter()
+        .filter(|entry| since.is_none_or(|since| entry.started_at.date_naive() >= since))
+        .cloned()
+        .collect();
+    if selected.is_empty() {
+        info!("No sessions recorded in {}", path.display());
+        return Ok(());
+    }
+
+    let rows = usage::summarize(&selected, by, per_key, config);
+    print!(
+        "{}",
+        UsageTable {
+            rows: &rows,
+            config,
+        }
+    );
+    if let Some(cap) = config.monthly_cap {
+        let month = usage::summarize(&entries, Period::Month, false, config);
+        let spent = month
+            .last()
+            .filter(|row| row.period == chrono::Local::now().format("%Y-%m").to_string())
+            .map_or(0.0, |row| row.cost);
+        println!(
+            "This month: {:.2} of {:.2} {}",
+            spent,
+            cap,
+            config.currency.as_deref().unwrap_or("")
+        );
+    }
+    Ok(())
+}
+
+/// Run a `vocab` subcommand; new vocabularies are created for `model`
+async fn run_vocab_command(
+    command: VocabCommand,
+    client: &VocabularyClient,
+    model: &str,
+) -> Result<()> {
+    match command {
+        VocabCommand::Create { name, file } => {
+            let words = vocab::load_hotwords(&file)?;
+            let id = client.create(&name, model, &words).await?;
+            info!(
+                "Created vocabulary {} with {} hotword(s) for {}",
+                name,
+                words.len(),
+                model
+            );
+            println!("{}", id);
+        }
+        VocabCommand::List { prefix } => {
+            for vocabulary in client.list(prefix.as_deref()).await? {
+                println!(
+                    "{}\t{}\t{}",
+                    vocabulary.vocabulary_id, vocabulary.status, vocabulary.gmt_modified
+                );
+            }
+        }
+        VocabCommand::Show { name } => {
+            let vocabulary = client.show(&name).await?;
+            info!(
+                "Vocabulary for {} ({}, modified {})",
+                vocabulary.target_model, vocabulary.status, vocabulary.gmt_modified
+            );
+            for word in vocabulary.vocabulary {
+                println!(
+                    "{}\t{}\t{}",
+                    word.text,
+                    word.weight,
+                    word.lang.unwrap_or_default()
+                );
+            }
+        }
+        VocabCommand::Update { name, file } => {
+            let words = vocab::load_hotwords(&file)?;
+            let id = client.update(&name, &words).await?;
+            info!("Updated {} with {} hotword(s)", id, words.len());
+        }
+        VocabCommand::Delete { name } => {
+            let id = client.delete(&name).await?;
+            info!("Deleted {}", id);
+        }
+    }
+    Ok(())
+}
+
+/// Forward Ctrl+C, SIGTERM and SIGUSR1 to the main loop
+fn spawn_signal_handlers(control_tx: mpsc::Sender<Control>) -> Result<()> {
+    // The first Ctrl+C starts a graceful shutdown, a second one forces quit
+    let tx = control_tx.clone();
+    tokio::spawn(async move {
+        signal::ctrl_c().await.ok();
+        info!("\nShutting down... (press Ctrl+C again to force quit)");
+        let _ = tx.send(Control::Shutdown).await;
+
         signal::ctrl_c().await.ok();
-        info!("\nShutting down...");
-        running_clone.store(false, Ordering::SeqCst);
+        warn!("Forced quit, final results may be lost");
+        std::process::exit(EXIT_INTERRUPTED);
     });
 
-    // Start recording immediately
-    {
-        let mut app = app.lock().await;
-        app.start_recording().await?;
+    let mut sigterm =
+        unix_signal(SignalKind::terminate()).context("Failed to listen for SIGTERM")?;
+    let tx = control_tx.clone();
+    tokio::spawn(async move {
+        while sigterm.recv().await.is_some() {
+            info!("Received SIGTERM, shutting down...");
+            let _ = tx.send(Control::Shutdown).await;
+        }
+    });
+
+    let mut sigusr1 =
+        unix_signal(SignalKind::user_defined1()).context("Failed to listen for SIGUSR1")?;
+    tokio::spawn(async move {
+        while sigusr1.recv().await.is_some() {
+            let _ = control_tx.send(Control::Toggle).await;
+        }
+    });
+
+    Ok(())
+}
+
+/// Stay resident and start/stop a session on every toggle
+async fn run_daemon(app: &mut App, control_rx: &mut mpsc::Receiver<Control>) -> Result<ExitCode> {
+    app.enable_prewarm();
+    info!("Daemon mode: toggle recording with `pkill -USR1 -x audio2text`");
+
+    loop {
+        match control_rx.recv().await {
+            Some(Control::Toggle) => {}
+            Some(Control::Shutdown) | None => break,
+        }
+
+        notify("Audio2Text Started", "Recording...");
+        let stop = match run_session(app, control_rx).await {
+            Ok(stop) => stop,
+            Err(e) => {
+                error!("Session failed: {:#}", e);
+                notify("Audio2Text stopped", &format!("{:#}", e));
+                SessionStop::Failed(AsrError::from_anyhow(&e))
+            }
+        };
+        match stop {
+            SessionStop::Shutdown => break,
+            // A rejected key or the spending cap will not go away on the next toggle
+            SessionStop::Failed(error) if is_fatal(&error) => {
+                info!("Goodbye!");
+                return Ok(exit_code_for(&error));
+            }
+            // Anything else may work next time: stay resident
+            SessionStop::Failed(error) => {
+                info!("Waiting for the next toggle after: {}", error);
+                if let Err(e) = app.stop_recording().await {
+                    warn!("Failed to stop recording: {:#}", e);
+                }
+            }
+            SessionStop::Toggled | SessionStop::Silence | SessionStop::Finished => {
+                notify("Audio2Text Stopped", "Recording finished");
+            }
+        }
+    }
+
+    info!("Goodbye!");
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Record until toggled off, interrupted, silent for too long or the ASR
+/// session ends. Retryable failures restart the session transparently.
+///
+/// Everything logged meanwhile is in a `session` span with the input device
+/// and the backend, model and id of the current task.
+#[instrument(name = "session", skip_all, fields(device, backend, model, task_id))]
+async fn run_session(
+    app: &mut App,
+    control_rx: &mut mpsc::Receiver<Control>,
+) -> Result<SessionStop> {
+    if let Some(error) = app.spending_cap_reached() {
+        error!("Not recording: {}", error);
+        notify("Audio2Text spending cap reached", &error.to_string());
+        return Ok(SessionStop::Failed(error));
     }
+    let mut session_end = app.start_recording().await?;
+    let mut restarts = 0;
 
-    // Main event loop - monitors for silence timeout
+    // Main event loop - monitors for silence timeout and ASR session end
     let mut last_speech_time = Instant::now();
     let mut check_interval = tokio::time::interval(Duration::from_millis(100));
 
-    while running.load(Ordering::SeqCst) {
-        check_interval.tick().await;
+    let stop = loop {
+        tokio::select! {
+            _ = check_interval.tick() => {}
+            control = control_rx.recv() => {
+                match control.unwrap_or(Control::Shutdown) {
+                    Control::Toggle => break SessionStop::Toggled,
+                    Control::Shutdown => break SessionStop::Shutdown,
+                }
+            }
+            end = session_end.recv() => {
+                let end = end.unwrap_or(SessionEnd::Finished);
+                app.stop_recording().await?;
+
+                match end {
+                    SessionEnd::Finished => {
+                        info!("ASR session finished");
+                        return Ok(SessionStop::Finished);
+                    }
+                    SessionEnd::Failed(error) if error.is_retryable() && restarts < MAX_RESTARTS => {
+                        restarts += 1;
+                        let backoff = Duration::from_secs(1 << (restarts - 1));
+                        warn!(
+                            "ASR session failed, restarting in {}s ({}/{}): {}",
+                            backoff.as_secs(),
+                            restarts,
+                            MAX_RESTARTS,
+                            error
+                        );
+                        notify("Audio2Text reconnecting", &error.to_string());
+                        tokio::time::sleep(backoff).await;
+                        session_end = app.start_recording().await?;
+                        last_speech_time = Instant::now();
+                        continue;
+                    }
+                    SessionEnd::Failed(error) => {
+                        error!("ASR session failed: {}", error);
+                        notify("Audio2Text stopped", &error.to_string());
+                        return Ok(SessionStop::Failed(error));
+                    }
+                }
+            }
+        }
 
         // Check if ASR has detected any speech
-        let has_speech = {
-            let app = app.lock().await;
-            app.check_and_reset_asr_result()
-        };
+        let has_speech = app.check_and_reset_asr_result();
 
         if has_speech {
             last_speech_time = Instant::now();
+            restarts = 0;
         }
 
-        // Check if we've exceeded the silence timeout
+        // Check if we've exceeded the silence timeout (0 disables it)
+        let max_silence = app.max_silence_seconds;
+        if max_silence == 0 {
+            continue;
+        }
         let silence_duration = last_speech_time.elapsed().as_secs();
-        if silence_duration >= MAX_SILENCE_SECONDS {
-            info!("No speech detected for {} seconds. Auto-stopping...", MAX_SILENCE_SECONDS);
-            running.store(false, Ordering::SeqCst);
-            break;
+        if silence_duration >= max_silence {
+            info!(
+                "No speech detected for {} seconds. Auto-stopping...",
+                max_silence
+            );
+            break SessionStop::Silence;
         }
 
         // Optional: Log silence progress every 10 seconds
-        if silence_duration > 0 && silence_duration % 10 == 0 && silence_duration < MAX_SILENCE_SECONDS {
+        if silence_duration > 0
+            && silence_duration.is_multiple_of(10)
+            && silence_duration < max_silence
+        {
             let prev_check = last_speech_time.elapsed().as_secs();
             if prev_check == silence_duration {
-                info!("Silence duration: {} seconds / {} maximum", silence_duration, MAX_SILENCE_SECONDS);
+                info!(
+                    "Silence duration: {} seconds / {} maximum",
+                    silence_duration, max_silence
+                );
             }
         }
-    }
+    };
+
+    // Stop recording, then let the ASR task flush the last sentences
+    app.finish_recording(session_end).await?;
+    Ok(stop)
+}
 
-    // Stop recording if active
-    let mut app = app.lock().await;
-    if app.state == AppState::Recording {
-        let _ = app.stop_recording().await;
+/// Whether the daemon should exit rather than wait for the next toggle
+fn is_fatal(error: &AsrError) -> bool {
+    match error {
+        AsrError::Auth { .. } => true,
+        AsrError::QuotaExceeded { code, .. } => code == "MonthlyCap",
+        _ => false,
     }
+}
 
-    info!("Goodbye!");
-    Ok(())
+/// Map a fatal ASR error to the process exit code
+fn exit_code_for(error: &AsrError) -> ExitCode {
+    let code = match error {
+        AsrError::Auth { .. } => EXIT_AUTH_ERROR,
+        AsrError::Network(_) | AsrError::Transport(_) => EXIT_NETWORK_ERROR,
+        AsrError::QuotaExceeded { .. } | AsrError::RateLimited { .. } => EXIT_QUOTA_ERROR,
+        _ => EXIT_FAILURE,
+    };
+    ExitCode::from(code)
 }
 
 fn check_dependencies() {
@@ -0,0 +1,27 @@
+use std::process::Command;
+use tracing::{debug, info};
+
+/// Show a desktop notification via notify-send
+/// Falls back to logging when no notification daemon is available
+pub fn notify(summary: &str, body: &str) {
+    info!("{}: {}", summary, body);
+
+    let result = Command::new("notify-send")
+        .arg("--app-name=Audio2Text")
+        .arg(summary)
+        .arg(body)
+        .output();
+
+    match result {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => {
+            debug!(
+                "notify-send failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Err(e) => {
+            debug!("notify-send not available: {}", e);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use tracing::{info, warn};
+
+/// Lines waiting for the command before new ones are dropped
+const QUEUED_LINES: usize = 64;
+
+/// A long-running command fed one line of text per update, such as a
+/// caption overlay reading stdin
+///
+/// The command is started with the first line and kept across sessions. If
+/// it exits, it is started again with the next line. Lines are written from
+/// a thread of their own, so a command that stops reading never holds up the
+/// event loop.
+pub struct CommandOutput {
+    command: String,
+    /// Also write partial results, each on its own line
+    partials: bool,
+    lines: Option<SyncSender<String>>,
+}
+
+impl CommandOutput {
+    pub fn new(command: String, partials: bool) -> Self {
+        Self {
+            command,
+            partials,
+            lines: None,
+        }
+    }
+
+    /// Queue the text of a result, if this output takes it
+    pub fn write(&mut self, text: &str, is_final: bool) -> Result<()> {
+        if !is_final && !self.partials {
+            return Ok(());
+        }
+        let line = format!("{}\n", text.replace('\n', " "));
+        let lines = self.lines.get_or_insert_with(|| {
+            let (tx, rx) = mpsc::sync_channel(QUEUED_LINES);
+            let command = self.command.clone();
+            std::thread::spawn(move || feed(&command, rx));
+            tx
+        });
+        match lines.try_send(line) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                anyhow::bail!(
+                    "`{}` is not reading its input, dropped a line",
+                    self.command
+                )
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.lines = None;
+                anyhow::bail!("Writer for `{}` stopped", self.command)
+            }
+        }
+    }
+}
+
+/// Write every queued line to the command, starting it as needed, until the
+/// output is dropped
+fn feed(command: &str, lines: Receiver<String>) {
+    let mut running: Option<(Child, ChildStdin)> = None;
+    for line in lines {
+        if let Some((child, _)) = &mut running {
+            if let Ok(Some(status)) = child.try_wait() {
+                warn!("`{}` exited ({}), starting it again", command, status);
+                running = None;
+            }
+        }
+        let (_, stdin) = match &mut running {
+            Some(running) => running,
+            None => match start(command) {
+                Ok(started) => running.insert(started),
+                Err(e) => {
+                    warn!("{:#}", e);
+                    continue;
+                }
+            },
+        };
+        if let Err(e) = stdin.write_all(line.as_bytes()).and_then(|_| stdin.flush()) {
+            warn!("Failed to write to `{}`: {}", command, e);
+            stop(running.take());
+        }
+    }
+    stop(running);
+}
+
+fn start(command: &str) -> Result<(Child, ChildStdin)> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to start `{}`", command))?;
+    let stdin = child
+        .stdin
+        .take()
+        .context("No stdin for the output command")?;
+    info!("Started output command `{}`", command);
+    Ok((child, stdin))
+}
+
+/// Close stdin, which tells the command to exit
+fn stop(running: Option<(Child, ChildStdin)>) {
+    if let Some((mut child, stdin)) = running {
+        drop(stdin);
+        // Reap it if it is already gone, without waiting for it
+        let _ = child.try_wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn writes_lines_in_order() {
+        let path = std::env::temp_dir().join(format!("command-output-{}", uuid::Uuid::new_v4()));
+        let mut output = CommandOutput::new(format!("cat > {}", path.display()), false);
+        output.write("first", true).unwrap();
+        output.write("ignored partial", false).unwrap();
+        output.write("second\nline", true).unwrap();
+        drop(output);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut written = String::new();
+        while Instant::now() < deadline {
+            written = std::fs::read_to_string(&path).unwrap_or_default();
+            if written.lines().count() == 2 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(written, "first\nsecond line\n");
+    }
+
+    #[test]
+    fn does_not_block_on_a_command_that_stops_reading() {
+        let mut output = CommandOutput::new("sleep 2".to_string(), true);
+        let line = "x".repeat(4096);
+        let started = Instant::now();
+        let mut dropped = 0;
+        // Far more than the pipe buffer holds
+        for _ in 0..200 {
+            if output.write(&line, false).is_err() {
+                dropped += 1;
+            }
+        }
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(dropped > 0);
+    }
+}
@@ -0,0 +1,554 @@
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tracing::{error, info};
+
+use crate::input::TextInputHandler;
+use crate::transcript::TranscriptWriter;
+use crate::typing::{LiveTyper, StreamingCommitter, TypingMode};
+use crate::websocket::{LatencyTracker, RecognitionResult, SentenceText, Word};
+
+mod command;
+mod template;
+
+use command::CommandOutput;
+pub use template::Template;
+
+/// What the default outputs receive of each sentence (`--content`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Content {
+    /// Only the transcription, in the language spoken
+    Source,
+    /// The translation, or the transcription where there is none
+    #[default]
+    Translation,
+    /// Both, written with the content template
+    Bilingual,
+}
+
+impl Content {
+    /// Stream for this content; `template` is used in bilingual mode
+    pub fn stream(self, template: &Template) -> StreamSelector {
+        match self {
+            Self::Source => StreamSelector::Transcription,
+            Self::Translation => StreamSelector::Preferred,
+            Self::Bilingual => StreamSelector::Bilingual(template.clone()),
+        }
+    }
+}
+
+/// Which text of a result an output receives
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StreamSelector {
+    /// The translation shown for the sentence, or the transcription if the
+    /// translation is missing or incomplete
+    #[default]
+    Preferred,
+    /// The source transcription
+    Transcription,
+    /// The translation into one language
+    Translation(String),
+    /// The transcription and the preferred translation, combined with the
+    /// template; whichever exists when the other is missing
+    Bilingual(Template),
+}
+
+impl StreamSelector {
+    /// The text of `result` this selects, `None` if the result lacks it
+    pub fn select<'a>(&self, result: &'a RecognitionResult) -> Option<Cow<'a, SentenceText>> {
+        match self {
+            Self::Preferred => match &result.transcription {
+                Some(transcription) if result.pre_end_failed => Some(transcription),
+                _ => result.preferred(),
+            }
+            .map(Cow::Borrowed),
+            Self::Transcription => result.transcription.as_ref().map(Cow::Borrowed),
+            Self::Translation(lang) => result.translations.get(lang).map(Cow::Borrowed),
+            Self::Bilingual(template) => {
+                let source = result.transcription.as_ref();
+                let translation = result
+                    .preferred()
+                    .filter(|stream| stream.lang.is_some() && !result.pre_end_failed);
+                let text = match (source, translation) {
+                    (Some(source), Some(translation)) => {
+                        template.render(&source.text, &translation.text)
+                    }
+                    (Some(stream), None) | (None, Some(stream)) => stream.text.clone(),
+                    (None, None) => return None,
+                };
+                Some(Cow::Owned(combined(source.or(translation)?, text)))
+            }
+        }
+    }
+}
+
+/// `text` with the timing and speakers of `stream`
+///
+/// No word counts as fixed, since either part may still change: streaming
+/// mode types the sentence once it has ended.
+fn combined(stream: &SentenceText, text: String) -> SentenceText {
+    SentenceText {
+        text,
+        lang: None,
+        words: stream
+            .words
+            .iter()
+            .map(|word| Word {
+                fixed: false,
+                ..word.clone()
+            })
+            .collect(),
+        ..stream.clone()
+    }
+}
+
+/// `bilingual` parses with the default template; `Route::from_config()`
+/// replaces it with the content template of the profile
+impl FromStr for StreamSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "preferred" => Ok(Self::Preferred),
+            "transcription" => Ok(Self::Transcription),
+            "bilingual" => Ok(Self::Bilingual(Template::default())),
+            _ => match s.strip_prefix("translation:") {
+                Some(lang) if !lang.is_empty() => Ok(Self::Translation(lang.to_string())),
+                _ => bail!(
+                    "unknown stream {:?}, expected preferred, transcription, bilingual or translation:<lang>",
+                    s
+                ),
+            },
+        }
+    }
+}
+
+impl fmt::Display for StreamSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Preferred => f.write_str("preferred"),
+            Self::Transcription => f.write_str("transcription"),
+            Self::Translation(lang) => write!(f, "translation:{}", lang),
+            Self::Bilingual(_) => f.write_str("bilingual"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for StreamSelector {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// One entry of a profile's `outputs`: a sink and the stream it receives,
+/// by default the one chosen by the content mode
+///
+/// ```toml
+/// [[profiles.meeting.outputs]]
+/// sink = "type"
+/// stream = "transcription"
+///
+/// [[profiles.meeting.outputs]]
+/// sink = "file"
+/// stream = "translation:en"
+/// path = "meeting-en.txt"
+///
+/// [[profiles.meeting.outputs]]
+/// sink = "command"
+/// stream = "translation:ja"
+/// command = "caption-overlay"
+/// partials = true
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "sink", rename_all = "lowercase", deny_unknown_fields)]
+pub enum OutputConfig {
+    /// Type into the focused window
+    Type {
+        stream: Option<StreamSelector>,
+        /// [default: the profile's typing mode]
+        typing: Option<TypingMode>,
+    },
+    /// Append final sentences to a file
+    File {
+        stream: Option<StreamSelector>,
+        path: PathBuf,
+        #[serde(default)]
+        timestamps: bool,
+    },
+    /// Write lines to the stdin of a command
+    Command {
+        stream: Option<StreamSelector>,
+        command: String,
+        /// Also write every partial result
+        #[serde(default)]
+        partials: bool,
+    },
+}
+
+/// A sink bound to the stream it receives, kept across sessions
+#[derive(Clone)]
+pub struct Route {
+    stream: StreamSelector,
+    target: Target,
+}
+
+#[derive(Clone)]
+enum Target {
+    Type {
+        input: TextInputHandler,
+        mode: TypingMode,
+    },
+    File(Arc<Mutex<TranscriptWriter>>),
+    Command(Arc<Mutex<CommandOutput>>),
+}
+
+impl Route {
+    /// Type `stream` into the focused window (or wherever `input` types)
+    pub fn typing(stream: StreamSelector, input: TextInputHandler, mode: TypingMode) -> Self {
+        Self {
+            stream,
+            target: Target::Type { input, mode },
+        }
+    }
+
+    /// Append the final sentences of `stream` to a transcript
+    pub fn transcript(stream: StreamSelector, writer: TranscriptWriter) -> Self {
+        Self {
+            stream,
+            target: Target::File(Arc::new(Mutex::new(writer))),
+        }
+    }
+
+    /// Route from the config; outputs without a stream receive `content`,
+    /// bilingual ones are written with `template`, and `type` sinks type with
+    /// `input`, in `typing` mode unless they set their own
+    pub fn from_config(
+        config: &OutputConfig,
+        content: &StreamSelector,
+        template: &Template,
+        input: &TextInputHandler,
+        typing: TypingMode,
+    ) -> Result<Self> {
+        let stream = |stream: &Option<StreamSelector>| match stream {
+            Some(StreamSelector::Bilingual(_)) => StreamSelector::Bilingual(template.clone()),
+            Some(stream) => stream.clone(),
+            None => content.clone(),
+        };
+        Ok(match config {
+            OutputConfig::Type {
+                stream: selected,
+                typing: mode,
+            } => Self::typing(stream(selected), input.clone(), mode.unwrap_or(typing)),
+            OutputConfig::File {
+                stream: selected,
+                path,
+                timestamps,
+            } => {
+                let stream = stream(selected);
+                let writer = TranscriptWriter::create(path)?;
+                info!("Writing {} to {}", stream, path.display());
+                Self::transcript(
+                    stream,
+                    if *timestamps {
+                        writer.with_timestamps()
+                    } else {
+                        writer
+                    },
+                )
+            }
+            OutputConfig::Command {
+                stream: selected,
+                command,
+                partials,
+            } => Self {
+                stream: stream(selected),
+                target: Target::Command(Arc::new(Mutex::new(CommandOutput::new(
+                    command.clone(),
+                    *partials,
+                )))),
+            },
+        })
+    }
+
+    /// Whether this route corrects typed text, which needs an input method
+    /// that can delete
+    pub fn deletes_text(&self) -> bool {
+        matches!(
+            self.target,
+            Target::Type {
+                mode: TypingMode::Live,
+                ..
+            }
+        )
+    }
+}
+
+/// The sinks of one session, each fed the stream it is routed
+pub struct Router {
+    sinks: Vec<(StreamSelector, Sink)>,
+    /// Records when final sentences have been typed
+    latency: Option<Arc<LatencyTracker>>,
+}
+
+/// A route's sink with the state of the current session
+enum Sink {
+    Type {
+        input: TextInputHandler,
+        mode: TypingMode,
+        committer: StreamingCommitter,
+        live: LiveTyper,
+    },
+    File(Arc<Mutex<TranscriptWriter>>),
+    Command(Arc<Mutex<CommandOutput>>),
+}
+
+impl Router {
+    pub fn open(routes: &[Route], latency: Option<Arc<LatencyTracker>>) -> Self {
+        let sinks = routes
+            .iter()
+            .map(|route| {
+                let sink = match &route.target {
+                    Target::Type { input, mode } => Sink::Type {
+                        input: input.clone(),
+                        mode: *mode,
+                        committer: StreamingCommitter::new(),
+                        live: LiveTyper::new(),
+                    },
+                    Target::File(writer) => Sink::File(writer.clone()),
+                    Target::Command(command) => Sink::Command(command.clone()),
+                };
+                (route.stream.clone(), sink)
+            })
+            .collect();
+        Self { sinks, latency }
+    }
+
+    /// Hand every sink its stream of `result`
+    pub fn result(&mut self, result: &RecognitionResult) {
+        for (stream, sink) in &mut self.sinks {
+            let Some(text) = stream.select(result) else {
+                continue;
+            };
+            if let Err(e) = sink.update(result.sentence_id, &text, result.is_final) {
+                error!("Failed to output {}: {:#}", stream, e);
+                continue;
+            }
+            if let (Sink::Type { .. }, Some(latency)) = (&*sink, &self.latency) {
+                if result.is_final {
+                    latency.typed(result.sentence_id);
+                }
+            }
+        }
+    }
+
+    /// The session has ended
+    pub fn finish(&mut self) {
+        for (stream, sink) in &mut self.sinks {
+            if let Sink::File(writer) = sink {
+                if let Err(e) = writer.lock().unwrap().finish() {
+                    error!("Failed to output {}: {:#}", stream, e);
+                }
+            }
+        }
+    }
+}
+
+impl Sink {
+    fn update(&mut self, sentence_id: i32, text: &SentenceText, is_final: bool) -> Result<()> {
+        match self {
+            Self::Type {
+                input,
+                mode,
+                committer,
+                live,
+            } => match mode {
+                TypingMode::Final if is_final => input.type_text(&text.text),
+                TypingMode::Final => Ok(()),
+                TypingMode::Streaming => {
+                    input.type_text(&committer.update(sentence_id, text, is_final))
+                }
+                TypingMode::Live => match live.update(sentence_id, &text.text, is_final) {
+                    Some(edit) => input.update_text(&edit.previous, &edit.new),
+                    None => Ok(()),
+                },
+            },
+            Self::File(writer) if is_final => writer.lock().unwrap().write_sentence(text),
+            Self::File(_) => Ok(()),
+            Self::Command(command) => command.lock().unwrap().write(&text.text, is_final),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::{Event, Shown};
+
+    fn result(line: &str) -> RecognitionResult {
+        let event: Event = serde_json::from_str(line).unwrap();
+        RecognitionResult::from_output(event.payload.output.as_ref().unwrap()).unwrap()
+    }
+
+    const BILINGUAL: &str = r#"{"header":{"task_id":"t","event":"result-generated"},"payload":{"output":{"transcription":{"sentence_id":0,"begin_time":0,"end_time":900,"text":"你好。","sentence_end":true},"translations":[{"sentence_id":0,"begin_time":0,"end_time":900,"text":"Hello.","lang":"en","sentence_end":true},{"sentence_id":0,"begin_time":0,"end_time":900,"text":"こんにちは。","lang":"ja","sentence_end":true}]}}}"#;
+
+    #[test]
+    fn parses_stream_names() {
+        assert_eq!(
+            "translation:ja".parse::<StreamSelector>().unwrap(),
+            StreamSelector::Translation("ja".to_string())
+        );
+        assert_eq!(
+            "transcription".parse::<StreamSelector>().unwrap(),
+            StreamSelector::Transcription
+        );
+        assert!("translation:".parse::<StreamSelector>().is_err());
+        assert!("subtitles".parse::<StreamSelector>().is_err());
+        assert_eq!(
+            StreamSelector::Translation("en".to_string()).to_string(),
+            "translation:en"
+        );
+    }
+
+    #[test]
+    fn selects_streams_of_a_result() {
+        let mut result = result(BILINGUAL);
+        let text = |stream: &str| {
+            stream
+                .parse::<StreamSelector>()
+                .unwrap()
+                .select(&result)
+                .map(|text| text.text.clone())
+        };
+        assert_eq!(text("transcription").as_deref(), Some("你好。"));
+        assert_eq!(text("translation:ja").as_deref(), Some("こんにちは。"));
+        assert_eq!(text("translation:de"), None);
+        assert_eq!(text("preferred").as_deref(), Some("Hello."));
+        assert_eq!(text("bilingual").as_deref(), Some("你好。 (Hello.)"));
+
+        result.shown = Shown::Translation("ja".to_string());
+        assert_eq!(
+            StreamSelector::Preferred.select(&result).unwrap().text,
+            "こんにちは。"
+        );
+        // Spoken in the target language: no translation to add
+        result.shown = Shown::Transcription;
+        let bilingual = StreamSelector::Bilingual(Template::default());
+        assert_eq!(bilingual.select(&result).unwrap().text, "你好。");
+
+        // An incomplete translation falls back to the transcription
+        result.shown = Shown::default();
+        result.pre_end_failed = true;
+        assert_eq!(
+            StreamSelector::Preferred.select(&result).unwrap().text,
+            "你好。"
+        );
+        assert_eq!(bilingual.select(&result).unwrap().text, "你好。");
+    }
+
+    #[test]
+    fn routes_each_stream_to_its_sink() {
+        let dir = std::env::temp_dir().join(format!("routes-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name);
+
+        let outputs: Vec<OutputConfig> = toml::from_str::<toml::Table>(&format!(
+            r#"
+            [[outputs]]
+            sink = "file"
+            stream = "translation:en"
+            path = "{}"
+
+            [[outputs]]
+            sink = "file"
+            stream = "transcription"
+            path = "{}"
+
+            [[outputs]]
+            sink = "command"
+            stream = "translation:ja"
+            command = "cat >> {}"
+            "#,
+            path("en.txt").display(),
+            path("source.txt").display(),
+            path("ja.txt").display(),
+        ))
+        .unwrap()["outputs"]
+            .clone()
+            .try_into()
+            .unwrap();
+
+        let routes: Vec<Route> = outputs
+            .iter()
+            .map(|output| {
+                Route::from_config(
+                    output,
+                    &StreamSelector::Preferred,
+                    &Template::default(),
+                    &TextInputHandler::stdout(),
+                    TypingMode::Final,
+                )
+            })
+            .collect::<Result<_>>()
+            .unwrap();
+        let mut router = Router::open(&routes, None);
+        router.result(&result(BILINGUAL));
+        router.finish();
+        // Closing stdin ends the command
+        drop(router);
+        drop(routes);
+
+        let read = |name: &str| std::fs::read_to_string(path(name)).unwrap();
+        assert_eq!(read("en.txt"), "Hello.\n");
+        assert_eq!(read("source.txt"), "你好。\n");
+        for _ in 0..50 {
+            if std::fs::read_to_string(path("ja.txt")).is_ok_and(|text| !text.is_empty()) {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        assert_eq!(read("ja.txt"), "こんにちは。\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bilingual_outputs_use_the_content_template() {
+        let path = std::env::temp_dir().join(format!("bilingual-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let output = OutputConfig::File {
+            stream: Some("bilingual".parse().unwrap()),
+            path: path.clone(),
+            timestamps: false,
+        };
+        let template: Template = "{translation} / {source}".parse().unwrap();
+
+        let route = Route::from_config(
+            &output,
+            &StreamSelector::Preferred,
+            &template,
+            &TextInputHandler::stdout(),
+            TypingMode::Final,
+        )
+        .unwrap();
+        let mut router = Router::open(&[route], None);
+        router.result(&result(BILINGUAL));
+        router.finish();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "Hello. / 你好。\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_unknown_sinks_and_fields() {
+        let parse = |toml: &str| toml::from_str::<OutputConfig>(toml);
+        assert!(parse("sink = \"type\"\ntyping = \"live\"").is_ok());
+        assert!(parse("sink = \"overlay\"").is_err());
+        assert!(parse("sink = \"file\"\npath = \"x.txt\"\nformat = \"srt\"").is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// How a sentence is written in bilingual mode, e.g. `{source} ({translation})`
+///
+/// `{source}` is replaced by the transcription and `{translation}` by the
+/// translation shown for the sentence. `{{` and `}}` stand for literal braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template(String);
+
+impl Default for Template {
+    fn default() -> Self {
+        Self("{source} ({translation})".to_string())
+    }
+}
+
+impl Template {
+    /// The text of a sentence
+    pub fn render(&self, source: &str, translation: &str) -> String {
+        let mut out = String::new();
+        let mut rest = self.0.as_str();
+        while let Some(pos) = rest.find(['{', '}']) {
+            out.push_str(&rest[..pos]);
+            rest = &rest[pos..];
+            let (token, value) = [
+                ("{{", "{"),
+                ("}}", "}"),
+                ("{source}", source),
+                ("{translation}", translation),
+            ]
+            .into_iter()
+            .find(|(token, _)| rest.starts_with(token))
+            // Checked when parsing; a lone brace is kept as it is
+            .unwrap_or((&rest[..1], &rest[..1]));
+            out.push_str(value);
+            rest = &rest[token.len()..];
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut rest = s;
+        let mut placeholders = 0;
+        while let Some(pos) = rest.find(['{', '}']) {
+            rest = &rest[pos..];
+            let token = ["{{", "}}", "{source}", "{translation}"]
+                .into_iter()
+                .find(|token| rest.starts_with(token));
+            match token {
+                Some(token) => {
+                    if token.len() > 2 {
+                        placeholders += 1;
+                    }
+                    rest = &rest[token.len()..];
+                }
+                None => bail!(
+                    "unknown placeholder in template {:?}, expected {{source}} or {{translation}}",
+                    s
+                ),
+            }
+        }
+        if placeholders == 0 {
+            bail!(
+                "template {:?} has neither {{source}} nor {{translation}}",
+                s
+            );
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_placeholders() {
+        assert_eq!(
+            Template::default().render("你好。", "Hello."),
+            "你好。 (Hello.)"
+        );
+        let template: Template = "{translation}\n  {{{source}}}".parse().unwrap();
+        assert_eq!(template.render("你好", "Hello"), "Hello\n  {你好}");
+
+        assert!("{source} [{lang}]".parse::<Template>().is_err());
+        assert!("{source".parse::<Template>().is_err());
+        assert!("no placeholders".parse::<Template>().is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use crate::websocket::SentenceText;
+
+/// Consecutive words of one sentence spoken by the same speaker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpeakerTurn {
+    /// Speaker reported by the server, `None` without diarization
+    pub speaker_id: Option<i32>,
+    /// Start of the turn in milliseconds
+    pub begin_time: i64,
+    pub text: String,
+}
+
+/// Label used for a speaker in transcripts ("Speaker 1" for id 0)
+pub fn speaker_label(speaker_id: i32) -> String {
+    format!("Speaker {}", speaker_id + 1)
+}
+
+/// Split a sentence into speaker turns using the `speaker_id` of its words
+///
+/// The text is cut right before the first word of each new speaker, so
+/// punctuation stays with the turn it follows.
+pub fn speaker_turns(stream: &SentenceText) -> Vec<SpeakerTurn> {
+    let text = stream.text.as_str();
+    let mut turns = Vec::new();
+
+    let mut speaker = stream.words.first().and_then(|word| word.speaker_id);
+    let mut begin_time = stream.begin_time;
+    let mut start = 0;
+    let mut end = 0;
+
+    for word in &stream.words {
+        let word_text = word.text.trim();
+        let Some(pos) = text[end..]
+            .find(word_text)
+            .filter(|_| !word_text.is_empty())
+        else {
+            continue;
+        };
+        let word_start = end + pos;
+        end = word_start + word_text.len();
+
+        if word.speaker_id != speaker {
+            push_turn(&mut turns, speaker, begin_time, &text[start..word_start]);
+            speaker = word.speaker_id;
+            begin_time = word.begin_time;
+            start = word_start;
+        }
+    }
+    push_turn(&mut turns, speaker, begin_time, &text[start..]);
+
+    turns
+}
+
+fn push_turn(turns: &mut Vec<SpeakerTurn>, speaker_id: Option<i32>, begin_time: i64, text: &str) {
+    let text = text.trim();
+    if !text.is_empty() {
+        turns.push(SpeakerTurn {
+            speaker_id,
+            begin_time,
+            text: text.to_string(),
+        });
+    }
+}
+
+/// Appends final sentences to a transcript file, one speaker turn per line
+///
+/// With diarization, lines are prefixed with the speaker label
+/// ("Speaker 1: …") and consecutive sentences of the same speaker are joined
+/// on one line. Without it, every sentence gets its own line.
+///
+/// Every sentence is synced to disk as soon as it is written, so a crash
+/// loses at most the sentence in progress.
+pub struct TranscriptWriter {
+    file: File,
+    /// Prefix lines with the time of the turn (`[01:02:03] `)
+    timestamps: bool,
+    /// Speaker of the line that is still open, if any
+    open_line: Option<Option<i32>>,
+}
+
+impl TranscriptWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open transcript {}", path.display()))?;
+
+        Ok(Self {
+            file,
+            timestamps: false,
+            open_line: None,
+        })
+    }
+
+    /// Prefix every line with the session time it starts at
+    pub fn with_timestamps(mut self) -> Self {
+        self.timestamps = true;
+        self
+    }
+
+    /// Write a final sentence
+    pub fn write_sentence(&mut self, stream: &SentenceText) -> Result<()> {
+        let mut out = String::new();
+
+        for turn in speaker_turns(stream) {
+            match (self.open_line, turn.speaker_id) {
+                (Some(open), Some(speaker)) if open == Some(speaker) => {
+                    out.push(' ');
+                }
+                _ => {
+                    if self.open_line.is_some() {
+                        out.push('\n');
+                    }
+                    if self.timestamps {
+                        out.push_str(&format!("[{}] ", format_time(turn.begin_time)));
+                    }
+                    if let Some(speaker) = turn.speaker_id {
+                        out.push_str(&speaker_label(speaker));
+                        out.push_str(": ");
+                    }
+                }
+            }
+            out.push_str(&turn.text);
+            self.open_line = Some(turn.speaker_id);
+        }
+
+        self.file
+            .write_all(out.as_bytes())
+            .and_then(|_| self.file.sync_data())
+            .context("Failed to write transcript")
+    }
+
+    /// Terminate the open line, e.g. when a session ends
+    pub fn finish(&mut self) -> Result<()> {
+        if self.open_line.take().is_some() {
+            self.file
+                .write_all(b"\n")
+                .context("Failed to write transcript")?;
+        }
+        Ok(())
+    }
+}
+
+/// `HH:MM:SS` for a time in milliseconds
+fn format_time(ms: i64) -> String {
+    let seconds = ms.max(0) / 1000;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Rename a speaker in a saved transcript, returning the number of lines changed
+///
+/// `from` is either a full label ("Speaker 1", or a name given earlier) or
+/// just the speaker number.
+pub fn rename_speaker(path: &Path, from: &str, to: &str) -> Result<usize> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read transcript {}", path.display()))?;
+
+    let (renamed, count) = rename_in(&content, from, to);
+    if count > 0 {
+        std::fs::write(path, renamed)
+            .with_context(|| format!("Failed to write transcript {}", path.display()))?;
+    }
+    Ok(count)
+}
+
+fn rename_in(content: &str, from: &str, to: &str) -> (String, usize) {
+    let from = match from.parse::<i32>() {
+        Ok(number) => speaker_label(number - 1),
+        Err(_) => from.to_string(),
+    };
+    let prefix = format!("{}: ", from);
+
+    let mut count = 0;
+    let lines: Vec<String> = content
+        .split('\n')
+        .map(|line| {
+            // Keep the timestamp of meeting transcripts
+            let (timestamp, labeled) = match line.split_once("] ") {
+                Some((time, rest)) if line.starts_with('[') => (&line[..time.len() + 2], rest),
+                _ => ("", line),
+            };
+            match labeled.strip_prefix(&prefix) {
+                Some(rest) => {
+                    count += 1;
+                    format!("{}{}: {}", timestamp, to, rest)
+                }
+                None => line.to_string(),
+            }
+        })
+        .collect();
+
+    (lines.join("\n"), count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::Word;
+
+    fn word(text: &str, speaker_id: Option<i32>) -> Word {
+        Word {
+            begin_time: 0,
+            end_time: 0,
+            text: text.to_string(),
+            punctuation: None,
+            fixed: true,
+            speaker_id,
+        }
+    }
+
+    fn sentence(text: &str, words: Vec<Word>) -> SentenceText {
+        SentenceText {
+            text: text.to_string(),
+            lang: None,
+            begin_time: 0,
+            end_time: 0,
+            words,
+            sentence_end: true,
+        }
+    }
+
+    #[test]
+    fn splits_sentence_at_speaker_changes() {
+        let stream = sentence(
+            "Are you ready? Yes, let's go.",
+            vec![
+                word("Are", Some(0)),
+                word("you", Some(0)),
+                word("ready", Some(0)),
+                Word {
+                    begin_time: 1400,
+                    ..word("Yes", Some(1))
+                },
+                word("let's", Some(1)),
+                word("go", Some(1)),
+            ],
+        );
+
+        assert_eq!(
+            speaker_turns(&stream),
+            vec![
+                SpeakerTurn {
+                    speaker_id: Some(0),
+                    begin_time: 0,
+                    text: "Are you ready?".to_string()
+                },
+                SpeakerTurn {
+                    speaker_id: Some(1),
+                    begin_time: 1400,
+                    text: "Yes, let's go.".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_turns_and_joins_same_speaker() {
+        let path = std::env::temp_dir().join(format!("transcript-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut writer = TranscriptWriter::create(&path).unwrap();
+        writer
+            .write_sentence(&sentence("Hello.", vec![word("Hello", Some(0))]))
+            .unwrap();
+        writer
+            .write_sentence(&sentence("How are you?", vec![word("How", Some(0))]))
+            .unwrap();
+        writer
+            .write_sentence(&sentence("Fine.", vec![word("Fine", Some(1))]))
+            .unwrap();
+        writer.finish().unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            content,
+            "Speaker 1: Hello. How are you?\nSpeaker 2: Fine.\n"
+        );
+
+        assert_eq!(rename_speaker(&path, "2", "Bob").unwrap(), 1);
+        assert_eq!(rename_speaker(&path, "Speaker 1", "Alice").unwrap(), 1);
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content, "Alice: Hello. How are you?\nBob: Fine.\n");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn timestamps_lines_and_keeps_them_on_rename() {
+        let path = std::env::temp_dir().join(format!("transcript-ts-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut writer = TranscriptWriter::create(&path).unwrap().with_timestamps();
+        let mut late = sentence("Next item.", vec![word("Next", Some(1))]);
+        late.begin_time = 3_725_000;
+        writer
+            .write_sentence(&sentence("Hello.", vec![word("Hello", Some(0))]))
+            .unwrap();
+        writer.write_sentence(&late).unwrap();
+        writer.finish().unwrap();
+
+        rename_speaker(&path, "2", "Bob").unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            content,
+            "[00:00:00] Speaker 1: Hello.\n[01:02:05] Bob: Next item.\n"
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rename_only_touches_labels() {
+        let (renamed, count) = rename_in(
+            "Speaker 1: ask Speaker 1: now\nSpeaker 10: hi\n",
+            "1",
+            "Ann",
+        );
+        assert_eq!(count, 1);
+        assert_eq!(renamed, "Ann: ask Speaker 1: now\nSpeaker 10: hi\n");
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::{HashMap, HashSet};
+use tracing::{debug, warn};
+
+use crate::websocket::SentenceText;
+
+/// When recognized text is typed into the focused window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TypingMode {
+    /// Type each sentence once it has ended
+    #[default]
+    Final,
+    /// Type the words the server has marked as fixed as soon as they
+    /// stabilize, and the rest of the sentence when it ends
+    Streaming,
+    /// Type every partial result as it arrives and correct it in place
+    /// (backspacing) as the hypothesis changes
+    Live,
+}
+
+/// Tracks what has been typed per sentence in streaming mode
+///
+/// Only text that will not change any more is typed, so nothing ever has to
+/// be deleted: the fixed prefix while the sentence is in progress, and the
+/// remainder once the final result arrives.
+#[derive(Debug, Default)]
+pub struct StreamingCommitter {
+    /// Text already typed for sentences that have not ended yet
+    committed: HashMap<i32, String>,
+    /// Sentences that have been typed completely
+    finished: HashSet<i32>,
+}
+
+impl StreamingCommitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next result for `sentence_id` and get the text to type now
+    /// (empty if nothing new has stabilized)
+    pub fn update(&mut self, sentence_id: i32, stream: &SentenceText, is_final: bool) -> String {
+        if self.finished.contains(&sentence_id) {
+            debug!(
+                "Ignoring result for already finished sentence {}",
+                sentence_id
+            );
+            return String::new();
+        }
+
+        let committed = self.committed.entry(sentence_id).or_default();
+
+        if is_final {
+            let text = stream.text.as_str();
+            let remainder = if let Some(rest) = text.strip_prefix(committed.as_str()) {
+                rest.to_string()
+            } else {
+                // The server revised words it had marked as fixed. We cannot
+                // delete what is already typed, so type what follows the
+                // part that still matches.
+                let common = common_prefix_len(committed, text);
+                warn!(
+                    "Sentence {} changed after it was partially typed: {} -> {}",
+                    sentence_id,
+                    crate::logging::text(committed),
+                    crate::logging::text(text)
+                );
+                text[common..].to_string()
+            };
+
+            self.committed.remove(&sentence_id);
+            self.finished.insert(sentence_id);
+            return remainder;
+        }
+
+        let fixed = &stream.text[..fixed_prefix_len(stream)];
+        match fixed.strip_prefix(committed.as_str()) {
+            Some(new) if !new.is_empty() => {
+                let new = new.to_string();
+                committed.push_str(&new);
+                new
+            }
+            Some(_) => String::new(),
+            None => {
+                // The fixed prefix no longer extends what we typed; wait for
+                // the final result to reconcile
+                debug!(
+                    "Fixed prefix of sentence {} diverged from typed text, waiting for final",
+                    sentence_id
+                );
+                String::new()
+            }
+        }
+    }
+}
+
+/// A correction to apply with `TextInputHandler::update_text`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveEdit {
+    pub previous: String,
+    pub new: String,
+}
+
+/// Tracks the text typed for the sentence currently being edited in live mode
+///
+/// Only the current sentence can be corrected: once a new `sentence_id`
+/// starts, the cursor has moved on and the previous region is left as is.
+#[derive(Debug, Default)]
+pub struct LiveTyper {
+    /// Sentence being typed and the text currently shown for it
+    current: Option<(i32, String)>,
+    /// Sentences whose region has been closed
+    closed: HashSet<i32>,
+}
+
+impl LiveTyper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next result and get the edit that turns the typed text into
+    /// the new hypothesis, or `None` if nothing changes
+    pub fn update(&mut self, sentence_id: i32, text: &str, is_final: bool) -> Option<LiveEdit> {
+        if self.closed.contains(&sentence_id) {
+            debug!("Ignoring result for closed sentence {}", sentence_id);
+            return None;
+        }
+
+        // A new sentence starts a fresh region after whatever was typed before
+        let previous = match self.current.take() {
+            Some((id, typed)) if id == sentence_id => typed,
+            Some((id, _)) => {
+                self.closed.insert(id);
+                String::new()
+            }
+            None => String::new(),
+        };
+
+        if is_final {
+            self.closed.insert(sentence_id);
+        } else {
+            self.current = Some((sentence_id, text.to_string()));
+        }
+
+        (previous != text).then(|| LiveEdit {
+            previous,
+            new: text.to_string(),
+        })
+    }
+}
+
+/// Byte length of the prefix of `stream.text` covered by its leading fixed words
+///
+/// Punctuation after a fixed word is only included when the next word is
+/// fixed too, since trailing punctuation may still be revised.
+fn fixed_prefix_len(stream: &SentenceText) -> usize {
+    let text = stream.text.as_str();
+    let mut end = 0;
+
+    let fixed_count = stream.fixed_word_count();
+    for (i, word) in stream.words[..fixed_count].iter().enumerate() {
+        let word_text = word.text.trim();
+        if !word_text.is_empty() {
+            let Some(pos) = text[end..].find(word_text) else {
+                break;
+            };
+            end += pos + word_text.len();
+        }
+
+        let next_fixed = i + 1 < fixed_count;
+        if let Some(punctuation) = word.punctuation.as_deref().filter(|_| next_fixed) {
+            if text[end..].starts_with(punctuation) {
+                end += punctuation.len();
+            }
+        }
+    }
+
+    end
+}
+
+/// Byte length of the longest common prefix, on a char boundary
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .take_while(|((_, ca), cb)| ca == cb)
+        .last()
+        .map(|((i, c), _)| i + c.len_utf8())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::{Event, RecognitionResult};
+
+    /// Replay a captured JSONL sequence of server events through the committer,
+    /// returning each piece of typed text and the full final text
+    fn replay(cassette: &str) -> (Vec<String>, String) {
+        let mut committer = StreamingCommitter::new();
+        let mut typed = Vec::new();
+        let mut finals = String::new();
+        let mut finished = HashSet::new();
+
+        for line in cassette.lines().filter(|line| !line.trim().is_empty()) {
+            let event: Event = serde_json::from_str(line).expect("valid event");
+            let Some(result) = event
+                .payload
+                .output
+                .as_ref()
+                .and_then(RecognitionResult::from_output)
+            else {
+                continue;
+            };
+            let stream = result.preferred().expect("result has text");
+
+            if result.is_final && finished.insert(result.sentence_id) {
+                finals.push_str(&stream.text);
+            }
+
+            let text = committer.update(result.sentence_id, stream, result.is_final);
+            if !text.is_empty() {
+                typed.push(text);
+            }
+        }
+
+        (typed, finals)
+    }
+
+    #[test]
+    fn types_fixed_words_before_sentence_end() {
+        let (typed, finals) = replay(include_str!("../../tests/fixtures/streaming_en.jsonl"));
+
+        assert_eq!(typed.concat(), finals);
+        assert_eq!(
+            typed,
+            vec![
+                "Today",
+                " the weather",
+                " is nice",
+                ", so let's",
+                " go hiking.",
+                "We leave",
+                " at nine.",
+            ]
+        );
+    }
+
+    #[test]
+    fn replayed_transcription_is_typed_exactly_once() {
+        let (typed, finals) = replay(include_str!("../../tests/fixtures/streaming_zh.jsonl"));
+
+        // The cassette repeats the final result of sentence 0
+        assert_eq!(typed.concat(), finals);
+        assert_eq!(finals, "今天天气很好，我们去爬山吧。明天见。");
+    }
+
+    #[test]
+    fn revised_fixed_prefix_is_not_typed_twice() {
+        let (typed, _) = replay(include_str!("../../tests/fixtures/streaming_revised.jsonl"));
+
+        // "I red" was fixed and typed, then revised to "I read"; only the
+        // part after the matching prefix "I re" is typed at the end
+        assert_eq!(typed, vec!["I", " red", "ad the book."]);
+    }
+
+    #[test]
+    fn live_typer_corrects_current_sentence_only() {
+        let mut live = LiveTyper::new();
+
+        let edit = live.update(0, "I red", false).unwrap();
+        assert_eq!((edit.previous.as_str(), edit.new.as_str()), ("", "I red"));
+
+        let edit = live.update(0, "I read the", false).unwrap();
+        assert_eq!(
+            (edit.previous.as_str(), edit.new.as_str()),
+            ("I red", "I read the")
+        );
+
+        assert_eq!(live.update(0, "I read the", false), None);
+
+        // Sentence 1 starts before sentence 0 was finalized: new region
+        let edit = live.update(1, "Then", false).unwrap();
+        assert_eq!((edit.previous.as_str(), edit.new.as_str()), ("", "Then"));
+
+        // A late result for the closed sentence must not touch sentence 1
+        assert_eq!(live.update(0, "I read the book.", true), None);
+
+        let edit = live.update(1, "Then I slept.", true).unwrap();
+        assert_eq!(edit.previous, "Then");
+        assert_eq!(live.update(1, "Then I slept.", true), None);
+    }
+
+    #[test]
+    fn common_prefix_respects_char_boundaries() {
+        assert_eq!(common_prefix_len("今天好", "今天坏"), "今天".len());
+        assert_eq!(common_prefix_len("", "abc"), 0);
+        assert_eq!(common_prefix_len("abc", "abd"), 2);
+    }
+}
@@ -0,0 +1,485 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, error, warn};
+
+use crate::websocket::{AsrError, RecognitionResult};
+
+/// Bytes of 16kHz 16-bit mono PCM per millisecond
+const BYTES_PER_MS: u64 = 32;
+
+/// `[usage]` section of the config file
+///
+/// ```toml
+/// [usage]
+/// currency = "CNY"
+/// monthly_cap = 50.0
+///
+/// [usage.prices]
+/// "gummy-realtime-v1" = 0.00015
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UsageConfig {
+    /// Ledger file [default: ~/.local/share/audio2text/usage.jsonl]
+    pub ledger: Option<PathBuf>,
+    /// Currency of the prices, only used for display
+    pub currency: Option<String>,
+    /// Price per second of audio sent, by model
+    #[serde(default)]
+    pub prices: BTreeMap<String, f64>,
+    /// Refuse to start sessions once this month's estimated cost reaches this
+    pub monthly_cap: Option<f64>,
+}
+
+impl UsageConfig {
+    pub fn ledger_path(&self) -> Option<PathBuf> {
+        self.ledger
+            .clone()
+            .or_else(|| dirs::data_dir().map(|dir| dir.join("audio2text").join("usage.jsonl")))
+    }
+
+    fn currency(&self) -> &str {
+        self.currency.as_deref().unwrap_or("")
+    }
+
+    /// Estimated cost of an entry, `None` without a price for its model
+    pub fn cost(&self, entry: &LedgerEntry) -> Option<f64> {
+        self.prices
+            .get(&entry.model)
+            .map(|price| price * entry.audio_seconds)
+    }
+
+    /// Refuse a new session if this month's spending has reached the cap
+    pub fn check_cap(&self, entries: &[LedgerEntry], now: DateTime<Local>) -> Result<(), AsrError> {
+        let Some(cap) = self.monthly_cap else {
+            return Ok(());
+        };
+        let spent: f64 = entries
+            .iter()
+            .filter(|entry| {
+                entry.started_at.year() == now.year() && entry.started_at.month() == now.month()
+            })
+            .filter_map(|entry| self.cost(entry))
+            .sum();
+        if spent >= cap {
+            return Err(AsrError::QuotaExceeded {
+                code: "MonthlyCap".to_string(),
+                message: format!(
+                    "{:.2} {} spent this month, the cap is {:.2}",
+                    spent,
+                    self.currency(),
+                    cap
+                ),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// One recognition task in the ledger
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub task_id: String,
+    pub backend: String,
+    pub model: String,
+    /// Label of the API key used, with a key pool
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub started_at: DateTime<Local>,
+    pub ended_at: DateTime<Local>,
+    /// Audio sent to the server, which is what gets billed
+    pub audio_seconds: f64,
+    /// Silence held back locally instead of being sent
+    pub suppressed_seconds: f64,
+    /// Characters of final text
+    pub characters: u64,
+    #[serde(default)]
+    pub translation_languages: Vec<String>,
+}
+
+/// Append-only JSONL file of finished tasks
+pub struct Ledger {
+    path: PathBuf,
+    /// Serializes appends from concurrent tasks
+    lock: Mutex<()>,
+}
+
+impl Ledger {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn append(&self, entry: &LedgerEntry) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open ledger {}", self.path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// All entries; a missing ledger is empty and unreadable lines are skipped
+    pub fn read(&self) -> Result<Vec<LedgerEntry>> {
+        let file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", self.path.display()))
+            }
+        };
+        let mut entries = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!("Skipping ledger line {}: {}", number + 1, e),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Start metering a task; its entry is written once the meter is dropped
+    pub fn task(
+        self: &Arc<Self>,
+        task_id: &str,
+        backend: &str,
+        model: &str,
+        key: Option<&str>,
+    ) -> TaskMeter {
+        let now = Local::now();
+        TaskMeter {
+            ledger: self.clone(),
+            usage: Mutex::new(TaskUsage {
+                entry: LedgerEntry {
+                    task_id: task_id.to_string(),
+                    backend: backend.to_string(),
+                    model: model.to_string(),
+                    key: key.map(str::to_string),
+                    started_at: now,
+                    ended_at: now,
+                    audio_seconds: 0.0,
+                    suppressed_seconds: 0.0,
+                    characters: 0,
+                    translation_languages: Vec::new(),
+                },
+                audio_ms: 0,
+                suppressed_ms: 0,
+                suppressed_counter: None,
+                languages: BTreeSet::new(),
+            }),
+        }
+    }
+}
+
+/// Usage of a running task
+pub struct TaskMeter {
+    ledger: Arc<Ledger>,
+    usage: Mutex<TaskUsage>,
+}
+
+struct TaskUsage {
+    entry: LedgerEntry,
+    audio_ms: u64,
+    suppressed_ms: u64,
+    /// Milliseconds held back by the sender, and its value when the task began
+    suppressed_counter: Option<(Arc<AtomicU64>, u64)>,
+    languages: BTreeSet<String>,
+}
+
+impl TaskMeter {
+    /// PCM bytes sent to the server
+    pub fn audio_sent(&self, bytes: usize) {
+        self.usage.lock().unwrap().audio_ms += bytes as u64 / BYTES_PER_MS;
+    }
+
+    /// Audio held back instead of being sent
+    pub fn suppressed(&self, ms: u64) {
+        self.usage.lock().unwrap().suppressed_ms += ms;
+    }
+
+    /// Take the silence held back during the task from a running total
+    pub fn suppressed_from(&self, counter: Arc<AtomicU64>) {
+        let start = counter.load(Ordering::SeqCst);
+        self.usage.lock().unwrap().suppressed_counter = Some((counter, start));
+    }
+
+    /// Count the text of a final result
+    pub fn result(&self, result: &RecognitionResult) {
+        if !result.is_final {
+            return;
+        }
+        let mut usage = self.usage.lock().unwrap();
+        if let Some(stream) = result.preferred() {
+            usage.entry.characters += stream.text.chars().count() as u64;
+        }
+        usage.languages.extend(result.translations.keys().cloned());
+    }
+}
+
+impl Drop for TaskMeter {
+    fn drop(&mut self) {
+        let usage = self.usage.get_mut().unwrap();
+        if let Some((counter, start)) = &usage.suppressed_counter {
+            usage.suppressed_ms += counter.load(Ordering::SeqCst).saturating_sub(*start);
+        }
+        let mut entry = usage.entry.clone();
+        entry.ended_at = Local::now();
+        entry.audio_seconds = usage.audio_ms as f64 / 1000.0;
+        entry.suppressed_seconds = usage.suppressed_ms as f64 / 1000.0;
+        entry.translation_languages = usage.languages.iter().cloned().collect();
+        debug!(
+            "Task {} used {:.1}s of audio",
+            entry.task_id, entry.audio_seconds
+        );
+        if let Err(e) = self.ledger.append(&entry) {
+            error!("Failed to record usage: {:#}", e);
+        }
+    }
+}
+
+/// Length of the periods `audio2text usage` sums up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Period {
+    #[default]
+    Day,
+    /// ISO week, starting on Monday
+    Week,
+    Month,
+}
+
+impl Period {
+    fn label(self, date: NaiveDate) -> String {
+        match self {
+            Self::Day => date.format("%Y-%m-%d").to_string(),
+            Self::Week => date.format("%G-W%V").to_string(),
+            Self::Month => date.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+/// Totals of one period (and key, if split by key)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeriodUsage {
+    pub period: String,
+    pub key: Option<String>,
+    pub tasks: usize,
+    pub audio_seconds: f64,
+    pub suppressed_seconds: f64,
+    pub characters: u64,
+    /// Estimated cost of the entries with a known price
+    pub cost: f64,
+    /// Some entries have no price for their model
+    pub unpriced: bool,
+}
+
+/// Sum up entries by period, oldest first, optionally split by API key
+pub fn summarize(
+    entries: &[LedgerEntry],
+    period: Period,
+    per_key: bool,
+    config: &UsageConfig,
+) -> Vec<PeriodUsage> {
+    let mut totals: BTreeMap<(String, Option<String>), PeriodUsage> = BTreeMap::new();
+    for entry in entries {
+        let label = period.label(entry.started_at.date_naive());
+        let key = entry.key.clone().filter(|_| per_key);
+        let total = totals
+            .entry((label.clone(), key.clone()))
+            .or_insert_with(|| PeriodUsage {
+                period: label,
+                key,
+                ..PeriodUsage::default()
+            });
+        total.tasks += 1;
+        total.audio_seconds += entry.audio_seconds;
+        total.suppressed_seconds += entry.suppressed_seconds;
+        total.characters += entry.characters;
+        match config.cost(entry) {
+            Some(cost) => total.cost += cost,
+            None => total.unpriced = true,
+        }
+    }
+    totals.into_values().collect()
+}
+
+/// `audio2text usage` output
+pub struct UsageTable<'a> {
+    pub rows: &'a [PeriodUsage],
+    pub config: &'a UsageConfig,
+}
+
+impl fmt::Display for UsageTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let per_key = self.rows.iter().any(|row| row.key.is_some());
+        let key_column = |key: &str| {
+            if per_key {
+                format!(" {:<10}", key)
+            } else {
+                String::new()
+            }
+        };
+        writeln!(
+            f,
+            "{:<10}{} {:>6} {:>10} {:>10} {:>8} {:>12}",
+            "period",
+            key_column("key"),
+            "tasks",
+            "audio",
+            "suppressed",
+            "chars",
+            "cost"
+        )?;
+        for row in self.rows {
+            let cost = format!(
+                "{:.2}{}{}",
+                row.cost,
+                if row.unpriced { "+?" } else { "" },
+                match self.config.currency() {
+                    "" => String::new(),
+                    currency => format!(" {}", currency),
+                }
+            );
+            writeln!(
+                f,
+                "{:<10}{} {:>6} {:>9.1}s {:>9.1}s {:>8} {:>12}",
+                row.period,
+                key_column(row.key.as_deref().unwrap_or("-")),
+                row.tasks,
+                row.audio_seconds,
+                row.suppressed_seconds,
+                row.characters,
+                cost
+            )?;
+        }
+        if self.rows.iter().any(|row| row.unpriced) {
+            writeln!(f, "+? some models have no price in [usage.prices]")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn entry(day: u32, model: &str, key: &str, audio_seconds: f64) -> LedgerEntry {
+        let started_at = Local.with_ymd_and_hms(2026, 3, day, 10, 0, 0).unwrap();
+        LedgerEntry {
+            task_id: format!("task-{}", day),
+            backend: "dashscope".to_string(),
+            model: model.to_string(),
+            key: Some(key.to_string()),
+            started_at,
+            ended_at: started_at,
+            audio_seconds,
+            suppressed_seconds: 1.0,
+            characters: 10,
+            translation_languages: Vec::new(),
+        }
+    }
+
+    fn config() -> UsageConfig {
+        UsageConfig {
+            prices: BTreeMap::from([("gummy-realtime-v1".to_string(), 0.01)]),
+            monthly_cap: Some(5.0),
+            ..UsageConfig::default()
+        }
+    }
+
+    #[test]
+    fn summarizes_by_period_and_key() {
+        let entries = vec![
+            entry(2, "gummy-realtime-v1", "alice", 100.0),
+            entry(2, "gummy-realtime-v1", "bob", 50.0),
+            // 2026-03-09 is the Monday of the next ISO week
+            entry(9, "other-model", "alice", 10.0),
+        ];
+        let config = config();
+
+        let days = summarize(&entries, Period::Day, false, &config);
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].period, "2026-03-02");
+        assert_eq!(days[0].tasks, 2);
+        assert!((days[0].cost - 1.5).abs() < 1e-9);
+        assert!(days[1].unpriced);
+
+        let weeks = summarize(&entries, Period::Week, true, &config);
+        let labels: Vec<_> = weeks
+            .iter()
+            .map(|row| (row.period.as_str(), row.key.as_deref()))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                ("2026-W10", Some("alice")),
+                ("2026-W10", Some("bob")),
+                ("2026-W11", Some("alice")),
+            ]
+        );
+
+        let months = summarize(&entries, Period::Month, false, &config);
+        assert_eq!(months.len(), 1);
+        assert!((months[0].audio_seconds - 160.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn refuses_sessions_over_the_monthly_cap() {
+        let config = config();
+        let now = Local.with_ymd_and_hms(2026, 3, 20, 9, 0, 0).unwrap();
+        let mut entries = vec![entry(2, "gummy-realtime-v1", "alice", 400.0)];
+        assert!(config.check_cap(&entries, now).is_ok());
+
+        entries.push(entry(3, "gummy-realtime-v1", "alice", 100.0));
+        assert!(matches!(
+            config.check_cap(&entries, now),
+            Err(AsrError::QuotaExceeded { .. })
+        ));
+        // Last month's spending does not count
+        let april = Local.with_ymd_and_hms(2026, 4, 1, 9, 0, 0).unwrap();
+        assert!(config.check_cap(&entries, april).is_ok());
+    }
+
+    #[test]
+    fn meters_tasks_into_the_ledger() {
+        let path = std::env::temp_dir().join(format!("usage-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let ledger = Arc::new(Ledger::new(path.clone()));
+
+        let meter = ledger.task("t-1", "dashscope", "gummy-realtime-v1", Some("alice"));
+        meter.audio_sent(32_000);
+        let held_back = Arc::new(AtomicU64::new(4000));
+        meter.suppressed_from(held_back.clone());
+        held_back.fetch_add(2500, Ordering::SeqCst);
+        drop(meter);
+
+        let entries = ledger.read().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].task_id, "t-1");
+        assert_eq!(entries[0].key.as_deref(), Some("alice"));
+        assert_eq!(entries[0].audio_seconds, 1.0);
+        assert_eq!(entries[0].suppressed_seconds, 2.5);
+    }
+}
@@ -0,0 +1,531 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::Path;
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::websocket::{AsrError, ConnectionConfig};
+
+/// Path of the customization API on the DashScope host
+const CUSTOMIZATION_PATH: &str = "/api/v1/services/audio/asr/customization";
+
+/// Model name the customization API expects for hotword lists
+const CUSTOMIZATION_MODEL: &str = "speech-biasing";
+
+/// Weight DashScope gives a hotword when none is set
+const DEFAULT_WEIGHT: u8 = 4;
+
+/// Allowed hotword weights
+const WEIGHT_RANGE: std::ops::RangeInclusive<u8> = 1..=5;
+
+/// Most hotwords one vocabulary may hold
+const MAX_HOTWORDS: usize = 500;
+
+/// Longest vocabulary name (the `prefix` of the vocabulary id)
+const MAX_NAME_LEN: usize = 10;
+
+/// Vocabularies fetched per list request
+const PAGE_SIZE: usize = 100;
+
+/// One entry of a hotword list
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hotword {
+    pub text: String,
+    /// How strongly the word is preferred, 1 to 5
+    #[serde(default = "default_weight")]
+    pub weight: u8,
+    /// Language of the word, e.g. `en` or `zh`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+}
+
+fn default_weight() -> u8 {
+    DEFAULT_WEIGHT
+}
+
+/// Local hotword file in TOML
+///
+/// ```toml
+/// [[words]]
+/// text = "DashScope"
+/// weight = 5
+/// lang = "en"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HotwordFile {
+    words: Vec<Hotword>,
+}
+
+/// Row of a hotword CSV file with the header `text,weight,lang`
+#[derive(Debug, Deserialize)]
+struct HotwordRow {
+    text: String,
+    #[serde(default)]
+    weight: Option<u8>,
+    #[serde(default)]
+    lang: Option<String>,
+}
+
+/// Read hotwords from a `.csv` file, or TOML for any other extension
+pub fn load_hotwords(path: &Path) -> Result<Vec<Hotword>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let is_csv = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+
+    let words = if is_csv {
+        parse_csv(&content)
+    } else {
+        parse_toml(&content)
+    };
+    words.with_context(|| format!("Invalid hotword file {}", path.display()))
+}
+
+pub fn parse_toml(content: &str) -> Result<Vec<Hotword>> {
+    let file: HotwordFile = toml::from_str(content)?;
+    validate_hotwords(&file.words)?;
+    Ok(file.words)
+}
+
+pub fn parse_csv(content: &str) -> Result<Vec<Hotword>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(content.as_bytes());
+
+    let mut words = Vec::new();
+    for (line, row) in reader.deserialize::<HotwordRow>().enumerate() {
+        let row = row.with_context(|| format!("Row {}", line + 1))?;
+        words.push(Hotword {
+            text: row.text,
+            weight: row.weight.unwrap_or(DEFAULT_WEIGHT),
+            lang: row.lang.filter(|lang| !lang.is_empty()),
+        });
+    }
+    validate_hotwords(&words)?;
+    Ok(words)
+}
+
+fn validate_hotwords(words: &[Hotword]) -> Result<()> {
+    if words.is_empty() {
+        bail!("No hotwords");
+    }
+    if words.len() > MAX_HOTWORDS {
+        bail!(
+            "{} hotwords, at most {} are allowed",
+            words.len(),
+            MAX_HOTWORDS
+        );
+    }
+    for word in words {
+        if word.text.trim().is_empty() {
+            bail!("Empty hotword");
+        }
+        if !WEIGHT_RANGE.contains(&word.weight) {
+            bail!(
+                "Weight of {:?} must be between {} and {}, got {}",
+                word.text,
+                WEIGHT_RANGE.start(),
+                WEIGHT_RANGE.end(),
+                word.weight
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Check a vocabulary name: lowercase letters and digits, at most 10 characters
+pub fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit());
+    if !valid {
+        bail!(
+            "Invalid vocabulary name {:?}: use up to {} lowercase letters and digits",
+            name,
+            MAX_NAME_LEN
+        );
+    }
+    Ok(())
+}
+
+/// Whether `name` is already a vocabulary id rather than a name
+fn is_vocabulary_id(name: &str) -> bool {
+    name.starts_with("vocab-")
+}
+
+/// A vocabulary as listed by the server
+#[derive(Debug, Clone, Deserialize)]
+pub struct VocabularySummary {
+    pub vocabulary_id: String,
+    #[serde(default)]
+    pub gmt_modified: String,
+    #[serde(default)]
+    pub status: String,
+}
+
+/// A vocabulary with its hotwords
+#[derive(Debug, Clone, Deserialize)]
+pub struct Vocabulary {
+    pub vocabulary: Vec<Hotword>,
+    #[serde(default)]
+    pub target_model: String,
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub gmt_modified: String,
+}
+
+/// Client for DashScope's hotword customization API
+///
+/// Vocabularies are addressed by a short name, which DashScope uses as the
+/// prefix of the generated `vocab-<name>-<hash>` id.
+pub struct VocabularyClient {
+    http: reqwest::Client,
+    url: Url,
+    api_key: String,
+}
+
+impl VocabularyClient {
+    pub fn new(api_key: String, http: reqwest::Client, url: Url) -> Self {
+        Self { http, url, api_key }
+    }
+
+    /// Client for the API on the host of the WebSocket endpoint
+    pub fn from_connection(api_key: String, connection: &ConnectionConfig) -> Result<Self> {
+        Ok(Self::new(
+            api_key,
+            connection.http_client()?,
+            connection.http_url(CUSTOMIZATION_PATH),
+        ))
+    }
+
+    /// Create a vocabulary for `target_model` and return its id
+    pub async fn create(
+        &self,
+        name: &str,
+        target_model: &str,
+        words: &[Hotword],
+    ) -> Result<String> {
+        validate_name(name)?;
+        if let Some(existing) = self.find(name).await? {
+            bail!(
+                "Vocabulary {:?} already exists ({}); use `vocab update` to change it",
+                name,
+                existing.vocabulary_id
+            );
+        }
+
+        let output = self
+            .call(json!({
+                "action": "create_vocabulary",
+                "target_model": target_model,
+                "prefix": name,
+                "vocabulary": words,
+            }))
+            .await?;
+        output["vocabulary_id"]
+            .as_str()
+            .map(str::to_string)
+            .context("Response has no vocabulary_id")
+    }
+
+    /// All vocabularies, or those whose name starts with `prefix`
+    pub async fn list(&self, prefix: Option<&str>) -> Result<Vec<VocabularySummary>> {
+        let mut vocabularies = Vec::new();
+        for page_index in 0.. {
+            let mut input = json!({
+                "action": "list_vocabulary",
+                "page_index": page_index,
+                "page_size": PAGE_SIZE,
+            });
+            if let Some(prefix) = prefix {
+                input["prefix"] = json!(prefix);
+            }
+
+            let output = self.call(input).await?;
+            let page: Vec<VocabularySummary> =
+                serde_json::from_value(output["vocabulary_list"].clone())
+                    .context("Unexpected vocabulary list")?;
+            let last = page.len() < PAGE_SIZE;
+            vocabularies.extend(page);
+            if last {
+                break;
+            }
+        }
+        Ok(vocabularies)
+    }
+
+    /// Hotwords and details of a vocabulary, by name or id
+    pub async fn show(&self, name: &str) -> Result<Vocabulary> {
+        let id = self.resolve(name).await?;
+        let output = self
+            .call(json!({
+                "action": "query_vocabulary",
+                "vocabulary_id": id,
+            }))
+            .await?;
+        serde_json::from_value(output).context("Unexpected vocabulary")
+    }
+
+    /// Replace the hotwords of a vocabulary, by name or id
+    pub async fn update(&self, name: &str, words: &[Hotword]) -> Result<String> {
+        let id = self.resolve(name).await?;
+        self.call(json!({
+            "action": "update_vocabulary",
+            "vocabulary_id": id,
+            "vocabulary": words,
+        }))
+        .await?;
+        Ok(id)
+    }
+
+    /// Delete a vocabulary, by name or id
+    pub async fn delete(&self, name: &str) -> Result<String> {
+        let id = self.resolve(name).await?;
+        self.call(json!({
+            "action": "delete_vocabulary",
+            "vocabulary_id": id,
+        }))
+        .await?;
+        Ok(id)
+    }
+
+    /// The `vocabulary_id` for a name; ids are passed through unchanged
+    pub async fn resolve(&self, name: &str) -> Result<String> {
+        if is_vocabulary_id(name) {
+            return Ok(name.to_string());
+        }
+        validate_name(name)?;
+        match self.find(name).await? {
+            Some(vocabulary) => Ok(vocabulary.vocabulary_id),
+            None => bail!(
+                "No vocabulary named {:?}; create it with `audio2text vocab create {} <file>`",
+                name,
+                name
+            ),
+        }
+    }
+
+    /// The most recently modified vocabulary called exactly `name`
+    async fn find(&self, name: &str) -> Result<Option<VocabularySummary>> {
+        // The server matches prefixes, so "team" also lists "team2"
+        let id_prefix = format!("vocab-{}-", name);
+        let mut matches: Vec<VocabularySummary> = self
+            .list(Some(name))
+            .await?
+            .into_iter()
+            .filter(|vocabulary| vocabulary.vocabulary_id.starts_with(&id_prefix))
+            .collect();
+        matches.sort_by(|a, b| b.gmt_modified.cmp(&a.gmt_modified));
+
+        if matches.len() > 1 {
+            warn!(
+                "{} vocabularies named {:?}, using the newest ({})",
+                matches.len(),
+                name,
+                matches[0].vocabulary_id
+            );
+        }
+        Ok(matches.into_iter().next())
+    }
+
+    /// Send one request and return its `output`
+    async fn call(&self, input: Value) -> Result<Value, AsrError> {
+        debug!("Vocabulary request: {}", input["action"]);
+        let response = self
+            .http
+            .post(self.url.clone())
+            .bearer_auth(&self.api_key)
+            .json(&json!({
+                "model": CUSTOMIZATION_MODEL,
+                "input": input,
+            }))
+            .send()
+            .await
+            .map_err(|e| AsrError::Network(format!("vocabulary request failed: {}", e)))?;
+
+        let status = response.status();
+        let body: Value = response
+            .json()
+            .await
+            .map_err(|e| AsrError::Protocol(format!("invalid vocabulary response: {}", e)))?;
+
+        if !status.is_success() || body.get("code").is_some_and(|code| !code.is_null()) {
+            let status_code = status.as_u16().to_string();
+            return Err(AsrError::from_dashscope(
+                body["code"].as_str().or(Some(status_code.as_str())),
+                body["message"].as_str(),
+            ));
+        }
+        Ok(body["output"].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Local stand-in for the customization API
+    ///
+    /// Answers requests with the given `(status, body)` pairs in order and
+    /// records the JSON body of each request.
+    async fn stand_in(responses: Vec<(u16, Value)>) -> (VocabularyClient, Arc<Mutex<Vec<Value>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!(
+            "http://{}{}",
+            listener.local_addr().unwrap(),
+            CUSTOMIZATION_PATH
+        ))
+        .unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let request = read_request(&mut stream).await;
+                recorded.lock().unwrap().push(request);
+
+                let body = body.to_string();
+                let response = format!(
+                    "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let http = reqwest::Client::builder().no_proxy().build().unwrap();
+        (VocabularyClient::new("sk-test".into(), http, url), requests)
+    }
+
+    async fn read_request(stream: &mut tokio::net::TcpStream) -> Value {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            data.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&data);
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                assert!(head.contains("authorization: Bearer sk-test"));
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length: "))
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                if body.len() >= length {
+                    return serde_json::from_str(body).unwrap();
+                }
+            }
+        }
+    }
+
+    fn listed(ids: &[(&str, &str)]) -> Value {
+        let list: Vec<Value> = ids
+            .iter()
+            .map(|(id, modified)| json!({"vocabulary_id": id, "gmt_modified": modified, "status": "OK"}))
+            .collect();
+        json!({"output": {"vocabulary_list": list}, "request_id": "r"})
+    }
+
+    #[test]
+    fn parses_toml_and_csv() {
+        let toml = r#"
+[[words]]
+text = "DashScope"
+weight = 5
+lang = "en"
+
+[[words]]
+text = "tokio"
+"#;
+        let words = parse_toml(toml).unwrap();
+        assert_eq!(words[0].weight, 5);
+        assert_eq!(words[1].weight, DEFAULT_WEIGHT);
+        assert_eq!(words[1].lang, None);
+
+        let csv = "text,weight,lang\nDashScope,5,en\n\"serde, json\",,\ntokio\n";
+        let words = parse_csv(csv).unwrap();
+        assert_eq!(words.len(), 3);
+        assert_eq!(words[0].lang.as_deref(), Some("en"));
+        assert_eq!(words[1].text, "serde, json");
+        assert_eq!(words[1].weight, DEFAULT_WEIGHT);
+        assert_eq!(words[2].lang, None);
+
+        assert!(parse_csv("text,weight\ntokio,9\n").is_err());
+        assert!(validate_name("Team-Names").is_err());
+    }
+
+    #[tokio::test]
+    async fn creates_then_resolves_by_name() {
+        let (client, requests) = stand_in(vec![
+            (200, listed(&[("vocab-team2-aaa", "2026-01-01 10:00:00")])),
+            (200, json!({"output": {"vocabulary_id": "vocab-team-bbb"}})),
+            (
+                200,
+                listed(&[
+                    ("vocab-team-old", "2025-12-01 10:00:00"),
+                    ("vocab-team-bbb", "2026-02-01 10:00:00"),
+                    ("vocab-team2-aaa", "2026-03-01 10:00:00"),
+                ]),
+            ),
+        ])
+        .await;
+
+        let words = parse_toml("[[words]]\ntext = \"Qwen\"\n").unwrap();
+        let id = client
+            .create("team", "paraformer-realtime-v2", &words)
+            .await
+            .unwrap();
+        assert_eq!(id, "vocab-team-bbb");
+        assert_eq!(client.resolve("team").await.unwrap(), "vocab-team-bbb");
+        assert_eq!(
+            client.resolve("vocab-other-ccc").await.unwrap(),
+            "vocab-other-ccc"
+        );
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0]["input"]["action"], "list_vocabulary");
+        assert_eq!(requests[0]["input"]["prefix"], "team");
+        assert_eq!(
+            requests[1],
+            json!({
+                "model": "speech-biasing",
+                "input": {
+                    "action": "create_vocabulary",
+                    "target_model": "paraformer-realtime-v2",
+                    "prefix": "team",
+                    "vocabulary": [{"text": "Qwen", "weight": 4}],
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn maps_api_errors() {
+        let (client, _) = stand_in(vec![(
+            401,
+            json!({"code": "InvalidApiKey", "message": "Invalid API-key provided."}),
+        )])
+        .await;
+
+        let error = client.list(None).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<AsrError>(),
+            Some(AsrError::Auth { .. })
+        ));
+    }
+}
@@ -0,0 +1,628 @@
+use anyhow::{Context, Result};
+use reqwest::multipart::{Form, Part};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn, Instrument};
+use url::Url;
+use uuid::Uuid;
+
+use super::keepalive::is_speech;
+use super::{
+    AsrError, AsrEvent, ConnectionConfig, LatencyTracker, RecognitionOptions, RecognitionResult,
+    SentenceText, SharedTimeline, Shown,
+};
+use crate::audio::AudioChunk;
+use crate::usage::{Ledger, TaskMeter};
+
+/// Where whisper.cpp's server listens when started with
+/// `--inference-path /v1/audio/transcriptions`
+const DEFAULT_URL: &str = "http://127.0.0.1:8080/v1/audio/transcriptions";
+
+/// Model name sent when none is configured; local servers mostly ignore it
+pub const DEFAULT_BATCH_MODEL: &str = "whisper-1";
+
+/// Bytes of 16kHz 16-bit mono PCM per millisecond
+const BYTES_PER_MS: usize = 32;
+
+/// Silence that ends an utterance when the profile does not set one
+const DEFAULT_SILENCE_MS: u32 = 700;
+
+/// Audio kept ahead of speech so the first syllable is not cut off
+const PRE_ROLL_CHUNKS: usize = 3;
+
+/// Utterances with less speech than this are clicks or breaths, which
+/// whisper tends to "transcribe" as filler text
+const MIN_SPEECH_MS: i64 = 200;
+
+/// Long monologues are cut here, well below what the servers accept
+const MAX_UTTERANCE_MS: i64 = 30_000;
+
+/// Attempts per utterance when the server fails with a retryable error
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Client for non-streaming `/v1/audio/transcriptions` endpoints
+///
+/// A local energy VAD cuts the capture into utterances, each posted as a WAV
+/// file and reported as one final sentence. Uploads run on their own task in
+/// capture order, so a slow server delays results but never the capture.
+#[derive(Clone)]
+pub struct BatchClient {
+    http: reqwest::Client,
+    url: Url,
+    token: Option<String>,
+    model: String,
+    silence_ms: u32,
+    latency: Option<Arc<LatencyTracker>>,
+    ledger: Option<Arc<Ledger>>,
+    timeline: Option<SharedTimeline>,
+}
+
+impl BatchClient {
+    pub fn new(http: reqwest::Client, url: Url, token: Option<String>) -> Self {
+        Self {
+            http,
+            url,
+            token,
+            model: DEFAULT_BATCH_MODEL.to_string(),
+            silence_ms: DEFAULT_SILENCE_MS,
+            latency: None,
+            ledger: None,
+            timeline: None,
+        }
+    }
+
+    /// Read the endpoint and token from the environment
+    ///
+    /// - `TRANSCRIPTION_URL`: endpoint [default: a local whisper.cpp server]
+    /// - `TRANSCRIPTION_API_KEY`: bearer token, optional for local servers
+    pub fn from_env() -> Result<Self> {
+        let url = std::env::var("TRANSCRIPTION_URL")
+            .ok()
+            .filter(|url| !url.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_URL.to_string());
+        let token = std::env::var("TRANSCRIPTION_API_KEY")
+            .ok()
+            .filter(|token| !token.trim().is_empty());
+        if let Some(token) = &token {
+            crate::logging::add_secret(token);
+        }
+        let connection = ConnectionConfig::for_http_endpoint(&url)?;
+        Ok(Self::new(
+            connection.http_client()?,
+            connection.endpoint,
+            token,
+        ))
+    }
+
+    pub fn endpoint(&self) -> &Url {
+        &self.url
+    }
+
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = model.into();
+        self
+    }
+
+    /// Apply the recognition options the local VAD has an equivalent for
+    ///
+    /// Only `max_sentence_silence` maps (to the silence that ends an
+    /// utterance); anything else is rejected.
+    pub fn with_options(mut self, options: &RecognitionOptions) -> Result<Self, AsrError> {
+        let supported = RecognitionOptions {
+            max_sentence_silence: options.max_sentence_silence,
+            ..RecognitionOptions::default()
+        };
+        if *options != supported {
+            return Err(AsrError::InvalidParameter {
+                code: "InvalidParameter".to_string(),
+                message: "only max_sentence_silence is supported by the batch backend".to_string(),
+            });
+        }
+        self.silence_ms = options.max_sentence_silence.unwrap_or(DEFAULT_SILENCE_MS);
+        Ok(self)
+    }
+
+    /// Measure how long results take after the audio was captured
+    pub fn with_latency(mut self, latency: Arc<LatencyTracker>) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Record the usage of every session in `ledger`
+    pub fn with_ledger(mut self, ledger: Arc<Ledger>) -> Self {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    /// Stitch results onto `timeline`, numbered and timed as part of a longer session
+    pub fn with_timeline(mut self, timeline: SharedTimeline) -> Self {
+        self.timeline = Some(timeline);
+        self
+    }
+
+    /// Check the server transcribes a short silent clip
+    pub async fn probe(&self) -> Result<()> {
+        let utterance = Utterance {
+            begin_time: 0,
+            end_time: 100,
+            pcm: vec![0; 100 * BYTES_PER_MS],
+            spoken_at: Instant::now(),
+        };
+        self.transcribe(&utterance).await?;
+        Ok(())
+    }
+
+    /// Cut audio into utterances until the channel closes, then wait for the
+    /// queued ones to be transcribed
+    pub async fn start_recognition(
+        &mut self,
+        mut audio_rx: mpsc::Receiver<AudioChunk>,
+        event_tx: mpsc::Sender<AsrEvent>,
+    ) -> Result<()> {
+        info!("Transcribing utterances with {}", self.url);
+        event_tx.send(AsrEvent::TaskStarted).await?;
+        let task_id = Uuid::new_v4().to_string();
+        crate::logging::record_task("batch", &self.model, &task_id);
+        let meter = self
+            .ledger
+            .as_ref()
+            .map(|ledger| Arc::new(ledger.task(&task_id, "batch", &self.model, None)));
+
+        let (queue_tx, queue_rx) = mpsc::unbounded_channel();
+        let uploader = tokio::spawn(
+            self.clone()
+                .upload(queue_rx, event_tx.clone(), meter.clone())
+                .in_current_span(),
+        );
+
+        let mut segmenter = Segmenter::new(self.silence_ms);
+        let mut finished = false;
+        // Audio that is not part of an utterance is never uploaded
+        let mut captured = 0;
+        let mut queued = 0;
+        while let Some(chunk) = audio_rx.recv().await {
+            captured += chunk.data.len();
+            if let Some(utterance) = segmenter.push(chunk) {
+                queued += utterance.pcm.len();
+                debug!(
+                    "Queued utterance {}-{}ms",
+                    utterance.begin_time, utterance.end_time
+                );
+                if queue_tx.send(utterance).is_err() {
+                    // The uploader gave up and reported why
+                    finished = true;
+                    break;
+                }
+            }
+        }
+        if !finished {
+            if let Some(utterance) = segmenter.finish() {
+                queued += utterance.pcm.len();
+                let _ = queue_tx.send(utterance);
+            }
+        }
+        drop(queue_tx);
+        if let Some(meter) = &meter {
+            meter.suppressed((captured.saturating_sub(queued) / BYTES_PER_MS) as u64);
+        }
+
+        if uploader.await.context("Upload task panicked")?? {
+            event_tx.send(AsrEvent::TaskFinished).await?;
+        }
+        Ok(())
+    }
+
+    /// Transcribe queued utterances in order; `false` if one failed
+    async fn upload(
+        self,
+        mut queue_rx: mpsc::UnboundedReceiver<Utterance>,
+        event_tx: mpsc::Sender<AsrEvent>,
+        meter: Option<Arc<TaskMeter>>,
+    ) -> Result<bool> {
+        let mut sentence_id = 0;
+        let timeline = self
+            .timeline
+            .as_ref()
+            .map(|timeline| (timeline.clone(), timeline.begin_task(0)));
+        while let Some(utterance) = queue_rx.recv().await {
+            if let Some(meter) = &meter {
+                meter.audio_sent(utterance.pcm.len());
+            }
+            let text = match self.transcribe_with_retry(&utterance).await {
+                Ok(text) => text,
+                Err(error) => {
+                    event_tx.send(AsrEvent::TaskFailed { error }).await?;
+                    return Ok(false);
+                }
+            };
+            if text.is_empty() {
+                debug!(
+                    "No speech in utterance {}-{}ms",
+                    utterance.begin_time, utterance.end_time
+                );
+                continue;
+            }
+
+            let mut result = RecognitionResult {
+                sentence_id,
+                begin_time: utterance.begin_time,
+                end_time: utterance.end_time,
+                transcription: Some(SentenceText {
+                    text,
+                    lang: None,
+                    begin_time: utterance.begin_time,
+                    end_time: utterance.end_time,
+                    words: Vec::new(),
+                    sentence_end: true,
+                }),
+                translations: Default::default(),
+                is_final: true,
+                pre_end_failed: false,
+                shown: Shown::default(),
+            };
+            if let Some((timeline, task)) = &timeline {
+                timeline.stitch(*task, &mut result);
+            }
+            if let Some(latency) = &self.latency {
+                latency.result(
+                    result.sentence_id,
+                    true,
+                    utterance.spoken_at,
+                    Instant::now(),
+                );
+            }
+            if let Some(meter) = &meter {
+                meter.result(&result);
+            }
+            event_tx.send(AsrEvent::ResultGenerated(result)).await?;
+            sentence_id += 1;
+        }
+        Ok(true)
+    }
+
+    async fn transcribe_with_retry(&self, utterance: &Utterance) -> Result<String, AsrError> {
+        let mut attempt = 1;
+        loop {
+            match self.transcribe(utterance).await {
+                Err(e) if e.is_retryable() && attempt < MAX_ATTEMPTS => {
+                    warn!("Transcription attempt {} failed, retrying: {}", attempt, e);
+                    tokio::time::sleep(RETRY_DELAY * attempt).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Post one utterance and return its (trimmed) text
+    async fn transcribe(&self, utterance: &Utterance) -> Result<String, AsrError> {
+        let file = Part::bytes(wav(&utterance.pcm))
+            .file_name("utterance.wav")
+            .mime_str("audio/wav")
+            .expect("valid MIME type");
+        let form = Form::new()
+            .part("file", file)
+            .text("model", self.model.clone())
+            .text("response_format", "json");
+
+        let mut request = self.http.post(self.url.clone()).multipart(form);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| AsrError::Network(format!("transcription request failed: {}", e)))?;
+
+        let status = response.status();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| AsrError::Network(format!("transcription response failed: {}", e)))?;
+        let body: Value = serde_json::from_slice(&body)
+            .unwrap_or_else(|_| json!({"message": String::from_utf8_lossy(&body)}));
+        if !status.is_success() {
+            return Err(AsrError::from_http(status.as_u16(), &body));
+        }
+
+        body["text"]
+            .as_str()
+            .map(|text| text.trim().to_string())
+            .ok_or_else(|| AsrError::Protocol("transcription response has no text".to_string()))
+    }
+}
+
+/// Speech cut from the capture, with its position in the recording
+struct Utterance {
+    /// Start and end in milliseconds since the recording started
+    begin_time: i64,
+    end_time: i64,
+    pcm: Vec<u8>,
+    /// When the last speech of the utterance was captured
+    spoken_at: Instant,
+}
+
+/// Energy VAD that groups chunks into utterances
+struct Segmenter {
+    silence_ms: i64,
+    /// Audio seen so far
+    offset_ms: i64,
+    pre_roll: VecDeque<(i64, AudioChunk)>,
+    current: Option<Utterance>,
+    speech_ms: i64,
+    trailing_silence_ms: i64,
+}
+
+impl Segmenter {
+    fn new(silence_ms: u32) -> Self {
+        Self {
+            silence_ms: silence_ms as i64,
+            offset_ms: 0,
+            pre_roll: VecDeque::new(),
+            current: None,
+            speech_ms: 0,
+            trailing_silence_ms: 0,
+        }
+    }
+
+    /// Add a chunk, returning the utterance it completes
+    fn push(&mut self, chunk: AudioChunk) -> Option<Utterance> {
+        let start = self.offset_ms;
+        let duration = (chunk.data.len() / BYTES_PER_MS) as i64;
+        self.offset_ms += duration;
+        let speech = is_speech(&chunk.data);
+
+        let Some(utterance) = &mut self.current else {
+            if !speech {
+                self.pre_roll.push_back((start, chunk));
+                if self.pre_roll.len() > PRE_ROLL_CHUNKS {
+                    self.pre_roll.pop_front();
+                }
+                return None;
+            }
+            let begin_time = self.pre_roll.front().map_or(start, |(begin, _)| *begin);
+            let mut pcm: Vec<u8> = self
+                .pre_roll
+                .drain(..)
+                .flat_map(|(_, chunk)| chunk.data)
+                .collect();
+            pcm.extend_from_slice(&chunk.data);
+            self.current = Some(Utterance {
+                begin_time,
+                end_time: self.offset_ms,
+                pcm,
+                spoken_at: chunk.captured_at,
+            });
+            self.speech_ms = duration;
+            self.trailing_silence_ms = 0;
+            return None;
+        };
+
+        utterance.pcm.extend_from_slice(&chunk.data);
+        if speech {
+            utterance.end_time = self.offset_ms;
+            utterance.spoken_at = chunk.captured_at;
+            self.speech_ms += duration;
+            self.trailing_silence_ms = 0;
+        } else {
+            self.trailing_silence_ms += duration;
+        }
+
+        let length = self.offset_ms - utterance.begin_time;
+        if self.trailing_silence_ms >= self.silence_ms || length >= MAX_UTTERANCE_MS {
+            return self.finish();
+        }
+        None
+    }
+
+    /// End the current utterance, unless it is too short to be speech
+    fn finish(&mut self) -> Option<Utterance> {
+        let utterance = self.current.take()?;
+        if self.speech_ms < MIN_SPEECH_MS {
+            debug!(
+                "Dropping {}ms of noise at {}ms",
+                self.speech_ms, utterance.begin_time
+            );
+            return None;
+        }
+        Some(utterance)
+    }
+}
+
+/// 16kHz mono 16-bit PCM as a WAV file
+fn wav(pcm: &[u8]) -> Vec<u8> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 16000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut file = Cursor::new(Vec::with_capacity(pcm.len() + 44));
+    let mut writer = hound::WavWriter::new(&mut file, spec).expect("in-memory WAV");
+    for sample in pcm.chunks_exact(2) {
+        writer
+            .write_sample(i16::from_le_bytes([sample[0], sample[1]]))
+            .expect("in-memory WAV");
+    }
+    writer.finalize().expect("in-memory WAV");
+    file.into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::Notify;
+
+    /// 100ms chunk, loud enough to be speech or silent
+    fn chunk(speech: bool) -> AudioChunk {
+        let amplitude: i16 = if speech { 8000 } else { 0 };
+        AudioChunk::new(
+            (0..1600)
+                .flat_map(|i| (if i % 2 == 0 { amplitude } else { -amplitude }).to_le_bytes())
+                .collect(),
+        )
+    }
+
+    fn feed(segmenter: &mut Segmenter, pattern: &str) -> Vec<(i64, i64)> {
+        pattern
+            .chars()
+            .filter_map(|c| segmenter.push(chunk(c == 'S')))
+            .map(|u| (u.begin_time, u.end_time))
+            .collect()
+    }
+
+    #[test]
+    fn cuts_utterances_at_silence() {
+        let mut segmenter = Segmenter::new(300);
+        // Pre-roll of 3 chunks, a short pause that does not end the
+        // utterance, then a click too short to be speech
+        let utterances = feed(&mut segmenter, "_____SSS_SS___________S___");
+        assert_eq!(utterances, vec![(200, 1100)]);
+
+        assert_eq!(feed(&mut segmenter, "SSS"), vec![]);
+        let last = segmenter.finish().unwrap();
+        assert_eq!((last.begin_time, last.end_time), (2600, 2900));
+    }
+
+    /// Local transcription server answering every request with the next
+    /// text; the first answer waits for `release`
+    async fn stand_in(
+        texts: Vec<&'static str>,
+        release: Arc<Notify>,
+    ) -> (BatchClient, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!(
+            "http://{}/v1/audio/transcriptions",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            for (i, text) in texts.into_iter().enumerate() {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let request = read_request(&mut stream).await;
+                recorded.lock().unwrap().push(request);
+                if i == 0 {
+                    release.notified().await;
+                }
+
+                let body = json!({ "text": text }).to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let http = reqwest::Client::builder().no_proxy().build().unwrap();
+        (BatchClient::new(http, url, Some("local".into())), requests)
+    }
+
+    async fn read_request(stream: &mut TcpStream) -> String {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 65536];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            data.extend_from_slice(&buf[..n]);
+            let Some(split) = data.windows(4).position(|w| w == b"\r\n\r\n") else {
+                continue;
+            };
+            let head = String::from_utf8_lossy(&data[..split]).to_string();
+            let length: usize = head
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length: "))
+                .unwrap()
+                .parse()
+                .unwrap();
+            if data.len() >= split + 4 + length {
+                return String::from_utf8_lossy(&data).to_string();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn transcribes_utterances_without_blocking_capture() {
+        let release = Arc::new(Notify::new());
+        let (mut client, requests) = stand_in(vec!["Hello.", " World. "], release.clone()).await;
+
+        // Capacity 1: the capture only gets through if nothing waits on HTTP
+        let (audio_tx, audio_rx) = mpsc::channel(1);
+        let (event_tx, mut event_rx) = mpsc::channel(100);
+        let recognition =
+            tokio::spawn(async move { client.start_recognition(audio_rx, event_tx).await });
+
+        let capture = async {
+            for c in "SSSS__________SSSS".chars() {
+                audio_tx.send(chunk(c == 'S')).await.unwrap();
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), capture)
+            .await
+            .expect("capture blocked by a slow transcription");
+        drop(audio_tx);
+        release.notify_one();
+
+        let mut texts = Vec::new();
+        while let Some(event) = event_rx.recv().await {
+            match event {
+                AsrEvent::ResultGenerated(result) => {
+                    assert!(result.is_final);
+                    texts.push((result.sentence_id, result.preferred().unwrap().text.clone()));
+                }
+                AsrEvent::TaskFinished => break,
+                AsrEvent::TaskFailed { error } => panic!("task failed: {}", error),
+                AsrEvent::TaskStarted => {}
+            }
+        }
+        recognition.await.unwrap().unwrap();
+        assert_eq!(
+            texts,
+            vec![(0, "Hello.".to_string()), (1, "World.".to_string())]
+        );
+
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].contains("authorization: Bearer local"));
+        assert!(requests[0].contains("RIFF"));
+        assert!(requests[0].contains("whisper-1"));
+    }
+
+    #[tokio::test]
+    async fn maps_http_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_request(&mut stream).await;
+            let body = r#"{"error":{"message":"bad key","type":"invalid_request_error","code":"invalid_api_key"}}"#;
+            let response = format!(
+                "HTTP/1.1 401 Unauthorized\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let http = reqwest::Client::builder().no_proxy().build().unwrap();
+        let client = BatchClient::new(http, url, None);
+        let utterance = Utterance {
+            begin_time: 0,
+            end_time: 100,
+            pcm: chunk(true).data,
+            spoken_at: Instant::now(),
+        };
+        let error = client.transcribe(&utterance).await.unwrap_err();
+        assert!(matches!(error, AsrError::Auth { .. }), "{:?}", error);
+    }
+}
@@ -0,0 +1,247 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use super::{asr_event, AsrError, AsrEvent, Event};
+
+/// One line of a cassette
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CassetteEntry {
+    /// Milliseconds since the recorder was created
+    pub t_ms: u64,
+    #[serde(flatten)]
+    pub record: Record,
+}
+
+/// What happened on the wire
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "dir", rename_all = "snake_case")]
+pub enum Record {
+    /// A command sent to the server (run-task, finish-task)
+    Sent { frame: serde_json::Value },
+    /// An audio chunk sent to the server; only its size is kept
+    Audio { bytes: usize },
+    /// A text frame received from the server, as JSON if it parsed
+    Received { frame: serde_json::Value },
+    /// The server closed the connection
+    Closed,
+}
+
+/// Writes the protocol traffic of every session to a JSONL cassette
+///
+/// Each entry is flushed right away so the trace survives a crash. The API
+/// key is sent in the HTTP upgrade request only and never ends up here.
+pub struct CassetteRecorder {
+    file: Mutex<File>,
+    started: Instant,
+}
+
+impl CassetteRecorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open cassette {}", path.display()))?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+            started: Instant::now(),
+        })
+    }
+
+    pub fn sent(&self, json: &str) {
+        self.write(Record::Sent {
+            frame: parse_frame(json),
+        });
+    }
+
+    pub fn audio(&self, bytes: usize) {
+        self.write(Record::Audio { bytes });
+    }
+
+    pub fn received(&self, text: &str) {
+        self.write(Record::Received {
+            frame: parse_frame(text),
+        });
+    }
+
+    pub fn closed(&self) {
+        self.write(Record::Closed);
+    }
+
+    fn write(&self, record: Record) {
+        let entry = CassetteEntry {
+            t_ms: self.started.elapsed().as_millis() as u64,
+            record,
+        };
+        let result = serde_json::to_string(&entry)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| {
+                let mut file = self.file.lock().unwrap();
+                writeln!(file, "{}", line)?;
+                Ok(())
+            });
+        if let Err(e) = result {
+            warn!("Failed to write cassette entry: {:#}", e);
+        }
+    }
+}
+
+fn parse_frame(text: &str) -> serde_json::Value {
+    serde_json::from_str(text).unwrap_or_else(|_| serde_json::Value::String(text.to_string()))
+}
+
+/// Read a cassette
+///
+/// Lines without a `dir` field are taken as received server events, so
+/// plain captures of `result-generated` events replay as well.
+pub fn read_cassette(path: &Path) -> Result<Vec<CassetteEntry>> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to open cassette {}", path.display()))?;
+
+    let mut entries = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value: serde_json::Value = serde_json::from_str(&line)
+            .with_context(|| format!("{}:{}: invalid JSON", path.display(), number + 1))?;
+
+        let entry = if value.get("dir").is_some() {
+            serde_json::from_value(value)
+                .with_context(|| format!("{}:{}: invalid entry", path.display(), number + 1))?
+        } else {
+            CassetteEntry {
+                t_ms: 0,
+                record: Record::Received { frame: value },
+            }
+        };
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Feed the received frames of a cassette to `event_tx` as if they came
+/// from the server, without any network
+///
+/// With `realtime`, the recorded gaps between frames are kept.
+pub async fn replay(
+    entries: Vec<CassetteEntry>,
+    event_tx: mpsc::Sender<AsrEvent>,
+    realtime: bool,
+) -> Result<()> {
+    let replay_started = Instant::now();
+
+    for entry in entries {
+        if realtime {
+            let due = replay_started + Duration::from_millis(entry.t_ms);
+            tokio::time::sleep_until(due.into()).await;
+        }
+
+        let event = match entry.record {
+            Record::Received { frame } => match serde_json::from_value::<Event>(frame) {
+                Ok(event) => asr_event(&event),
+                Err(e) => {
+                    warn!("Skipping unparseable frame at {}ms: {}", entry.t_ms, e);
+                    None
+                }
+            },
+            Record::Closed => Some(AsrEvent::TaskFailed {
+                error: AsrError::Network("connection closed by server".to_string()),
+            }),
+            Record::Sent { .. } | Record::Audio { .. } => None,
+        };
+
+        let Some(event) = event else {
+            continue;
+        };
+        let done = matches!(event, AsrEvent::TaskFinished | AsrEvent::TaskFailed { .. });
+        event_tx.send(event).await?;
+        if done {
+            return Ok(());
+        }
+    }
+
+    // Let the sinks flush even if the capture was cut short
+    info!("Cassette ended before the task finished");
+    event_tx.send(AsrEvent::TaskFinished).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn recorded_session_replays_to_same_events() {
+        let path = std::env::temp_dir().join(format!("cassette-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let recorder = CassetteRecorder::create(&path).unwrap();
+        recorder.sent(
+            r#"{"header":{"action":"run-task","task_id":"t1","streaming":"duplex"},"payload":{}}"#,
+        );
+        recorder.received(r#"{"header":{"task_id":"t1","event":"task-started"},"payload":{}}"#);
+        recorder.audio(3200);
+        recorder.received(
+            r#"{"header":{"task_id":"t1","event":"result-generated"},"payload":{"output":{"transcription":{"sentence_id":0,"begin_time":0,"end_time":900,"text":"Hello.","sentence_end":true}}}}"#,
+        );
+        recorder.closed();
+        drop(recorder);
+
+        let entries = read_cassette(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 5);
+        assert!(matches!(entries[2].record, Record::Audio { bytes: 3200 }));
+
+        let (event_tx, mut event_rx) = mpsc::channel(16);
+        replay(entries, event_tx, false).await.unwrap();
+
+        assert!(matches!(event_rx.recv().await, Some(AsrEvent::TaskStarted)));
+        match event_rx.recv().await {
+            Some(AsrEvent::ResultGenerated(result)) => {
+                assert!(result.is_final);
+                assert_eq!(result.preferred().unwrap().text, "Hello.");
+            }
+            other => panic!("expected result, got {:?}", other),
+        }
+        assert!(matches!(
+            event_rx.recv().await,
+            Some(AsrEvent::TaskFailed {
+                error: AsrError::Network(_)
+            })
+        ));
+        assert!(event_rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn plain_event_capture_replays() {
+        let path = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/streaming_zh.jsonl"
+        ));
+        let entries = read_cassette(path).unwrap();
+
+        let (event_tx, mut event_rx) = mpsc::channel(64);
+        replay(entries, event_tx, false).await.unwrap();
+
+        let mut results = 0;
+        let mut last = None;
+        while let Some(event) = event_rx.recv().await {
+            if matches!(event, AsrEvent::ResultGenerated(_)) {
+                results += 1;
+            }
+            last = Some(event);
+        }
+        assert!(results > 0);
+        assert!(matches!(last, Some(AsrEvent::TaskFinished)));
+    }
+}
@@ -0,0 +1,311 @@
+use thiserror::Error;
+
+/// Errors reported by (or while talking to) the DashScope ASR service.
+///
+/// Task failures carry the `error_code` and `error_message` from the
+/// `task-failed` header so callers can tell an expired key from a
+/// temporary outage.
+#[derive(Debug, Clone, Error)]
+pub enum AsrError {
+    /// The API key is missing, invalid or not allowed to use the service
+    #[error("authentication failed ({code}): {message}")]
+    Auth { code: String, message: String },
+
+    /// The account has run out of quota or has an overdue bill
+    #[error("quota exceeded ({code}): {message}")]
+    QuotaExceeded { code: String, message: String },
+
+    /// Too many requests in a short period; retrying later may succeed
+    #[error("rate limited ({code}): {message}")]
+    RateLimited { code: String, message: String },
+
+    /// The run-task parameters were rejected
+    #[error("invalid parameter ({code}): {message}")]
+    InvalidParameter { code: String, message: String },
+
+    /// The requested model does not exist or is not enabled for the account
+    #[error("model unavailable ({code}): {message}")]
+    ModelUnavailable { code: String, message: String },
+
+    /// Internal server error or server-side timeout
+    #[error("server error ({code}): {message}")]
+    Server { code: String, message: String },
+
+    /// Any other `task-failed` code we do not know how to classify
+    #[error("task failed ({code}): {message}")]
+    Other { code: String, message: String },
+
+    /// Connection could not be established or was lost
+    #[error("network error: {0}")]
+    Network(String),
+
+    /// The proxy rejected our credentials for it, or the server's key matches
+    /// none of the pinned ones; retrying will not help and the API key is not
+    /// at fault
+    #[error("transport error: {0}")]
+    Transport(String),
+
+    /// The server sent something we could not understand, or closed the
+    /// connection at an unexpected point in the protocol
+    #[error("protocol error: {0}")]
+    Protocol(String),
+}
+
+impl AsrError {
+    /// Map a DashScope `error_code` / `error_message` pair to an error variant
+    pub fn from_dashscope(code: Option<&str>, message: Option<&str>) -> Self {
+        let code = code.unwrap_or("Unknown").to_string();
+        let message = message.unwrap_or("Unknown error").to_string();
+
+        // Codes are hierarchical ("Throttling.RateQuota"), so match on the
+        // top-level prefix first and refine where the suffix matters
+        let (prefix, suffix) = match code.split_once('.') {
+            Some((prefix, suffix)) => (prefix, Some(suffix)),
+            None => (code.as_str(), None),
+        };
+
+        match (prefix, suffix) {
+            ("InvalidApiKey" | "Unauthorized" | "AuthenticationFailed", _) => {
+                Self::Auth { code, message }
+            }
+            ("Model", Some("AccessDenied")) => Self::ModelUnavailable { code, message },
+            ("AccessDenied" | "Workspace", _) => Self::Auth { code, message },
+            ("Throttling", Some("AllocationQuota" | "FreeTierOnly")) => {
+                Self::QuotaExceeded { code, message }
+            }
+            ("Throttling", _) => Self::RateLimited { code, message },
+            (
+                "Arrearage"
+                | "QuotaExhausted"
+                | "PrepaidBillOverdue"
+                | "PostpaidBillOverdue"
+                | "CommodityNotPurchased",
+                _,
+            ) => Self::QuotaExceeded { code, message },
+            ("InvalidParameter" | "BadRequest" | "DataInspectionFailed", _) => {
+                Self::InvalidParameter { code, message }
+            }
+            ("ModelNotFound" | "ModelUnavailable" | "ModelNotSupported", _) => {
+                Self::ModelUnavailable { code, message }
+            }
+            (
+                "InternalError" | "ServiceUnavailable" | "SystemError" | "RequestTimeOut"
+                | "ResponseTimeout",
+                _,
+            ) => Self::Server { code, message },
+            _ => Self::Other { code, message },
+        }
+    }
+
+    /// Map the `error` object of an OpenAI realtime `error` event
+    pub fn from_openai(kind: Option<&str>, code: Option<&str>, message: Option<&str>) -> Self {
+        let code = code.or(kind).unwrap_or("unknown").to_string();
+        let message = message.unwrap_or("Unknown error").to_string();
+
+        match (kind.unwrap_or_default(), code.as_str()) {
+            (_, "invalid_api_key") | ("authentication_error" | "permission_error", _) => {
+                Self::Auth { code, message }
+            }
+            (_, "insufficient_quota") => Self::QuotaExceeded { code, message },
+            (_, "rate_limit_exceeded") | ("rate_limit_error", _) => {
+                Self::RateLimited { code, message }
+            }
+            (_, "model_not_found") => Self::ModelUnavailable { code, message },
+            ("invalid_request_error", _) => Self::InvalidParameter { code, message },
+            ("server_error", _) => Self::Server { code, message },
+            _ => Self::Other { code, message },
+        }
+    }
+
+    /// Map a failed HTTP response, using its OpenAI-style `error` object if it has one
+    pub fn from_http(status: u16, body: &serde_json::Value) -> Self {
+        let error = &body["error"];
+        if error.is_object() {
+            return Self::from_openai(
+                error["type"].as_str(),
+                error["code"].as_str(),
+                error["message"].as_str(),
+            );
+        }
+
+        let code = status.to_string();
+        let message = error
+            .as_str()
+            .or(body["detail"].as_str())
+            .or(body["message"].as_str())
+            .unwrap_or("Unknown error")
+            .to_string();
+        match status {
+            401 | 403 => Self::Auth { code, message },
+            429 => Self::RateLimited { code, message },
+            400 | 413 | 422 => Self::InvalidParameter { code, message },
+            500.. => Self::Server { code, message },
+            _ => Self::Other { code, message },
+        }
+    }
+
+    /// The `AsrError` behind `error`, or a `Protocol` error for a failure we
+    /// cannot classify, which is not worth retrying
+    pub fn from_anyhow(error: &anyhow::Error) -> Self {
+        error
+            .downcast_ref::<AsrError>()
+            .cloned()
+            .unwrap_or_else(|| Self::Protocol(format!("{:#}", error)))
+    }
+
+    /// Whether retrying the same request (possibly after a backoff) may succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::RateLimited { .. } | Self::Server { .. } | Self::Network(_) => true,
+            Self::Auth { .. }
+            | Self::QuotaExceeded { .. }
+            | Self::InvalidParameter { .. }
+            | Self::ModelUnavailable { .. }
+            | Self::Other { .. }
+            | Self::Transport(_)
+            | Self::Protocol(_) => false,
+        }
+    }
+
+    /// The DashScope error code, if this error came from a `task-failed` event
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Self::Auth { code, .. }
+            | Self::QuotaExceeded { code, .. }
+            | Self::RateLimited { code, .. }
+            | Self::InvalidParameter { code, .. }
+            | Self::ModelUnavailable { code, .. }
+            | Self::Server { code, .. }
+            | Self::Other { code, .. } => Some(code),
+            Self::Network(_) | Self::Transport(_) | Self::Protocol(_) => None,
+        }
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for AsrError {
+    fn from(err: tokio_tungstenite::tungstenite::Error) -> Self {
+        use tokio_tungstenite::tungstenite::Error as WsError;
+
+        match err {
+            WsError::Http(response) if response.status() == http::StatusCode::UNAUTHORIZED => {
+                Self::Auth {
+                    code: "Unauthorized".to_string(),
+                    message: "WebSocket upgrade rejected with 401".to_string(),
+                }
+            }
+            WsError::Http(response) if response.status() == http::StatusCode::FORBIDDEN => {
+                Self::Auth {
+                    code: "AccessDenied".to_string(),
+                    message: "WebSocket upgrade rejected with 403".to_string(),
+                }
+            }
+            WsError::Http(response) if response.status() == http::StatusCode::TOO_MANY_REQUESTS => {
+                Self::RateLimited {
+                    code: "Throttling".to_string(),
+                    message: "WebSocket upgrade rejected with 429".to_string(),
+                }
+            }
+            WsError::Protocol(e) => Self::Protocol(e.to_string()),
+            WsError::Utf8 => Self::Protocol("invalid UTF-8 in text frame".to_string()),
+            other => Self::Network(other.to_string()),
+        }
+    }
+}
+
+impl From<serde_json::Error> for AsrError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Protocol(format!("invalid JSON frame: {}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(error: &AsrError) -> &'static str {
+        match error {
+            AsrError::Auth { .. } => "Auth",
+            AsrError::QuotaExceeded { .. } => "QuotaExceeded",
+            AsrError::RateLimited { .. } => "RateLimited",
+            AsrError::InvalidParameter { .. } => "InvalidParameter",
+            AsrError::ModelUnavailable { .. } => "ModelUnavailable",
+            AsrError::Server { .. } => "Server",
+            AsrError::Other { .. } => "Other",
+            AsrError::Network(_) => "Network",
+            AsrError::Transport(_) => "Transport",
+            AsrError::Protocol(_) => "Protocol",
+        }
+    }
+
+    #[test]
+    fn maps_dashscope_codes() {
+        let table = [
+            ("InvalidApiKey", "Auth", false),
+            ("Unauthorized", "Auth", false),
+            ("AuthenticationFailed", "Auth", false),
+            ("AccessDenied", "Auth", false),
+            ("AccessDenied.Unpurchased", "Auth", false),
+            ("Workspace.AccessDenied", "Auth", false),
+            ("Model.AccessDenied", "ModelUnavailable", false),
+            ("Throttling", "RateLimited", true),
+            ("Throttling.RateQuota", "RateLimited", true),
+            ("Throttling.AllocationQuota", "QuotaExceeded", false),
+            ("Throttling.FreeTierOnly", "QuotaExceeded", false),
+            ("Arrearage", "QuotaExceeded", false),
+            ("QuotaExhausted", "QuotaExceeded", false),
+            ("PrepaidBillOverdue", "QuotaExceeded", false),
+            ("PostpaidBillOverdue", "QuotaExceeded", false),
+            ("CommodityNotPurchased", "QuotaExceeded", false),
+            ("InvalidParameter", "InvalidParameter", false),
+            ("InvalidParameter.Format", "InvalidParameter", false),
+            ("BadRequest", "InvalidParameter", false),
+            ("DataInspectionFailed", "InvalidParameter", false),
+            ("ModelNotFound", "ModelUnavailable", false),
+            ("ModelUnavailable", "ModelUnavailable", false),
+            ("ModelNotSupported", "ModelUnavailable", false),
+            ("InternalError", "Server", true),
+            ("InternalError.Algo", "Server", true),
+            ("ServiceUnavailable", "Server", true),
+            ("SystemError", "Server", true),
+            ("RequestTimeOut", "Server", true),
+            ("ResponseTimeout", "Server", true),
+            ("SomethingNew", "Other", false),
+            ("Model.Unknown", "Other", false),
+        ];
+        for (code, expected, retryable) in table {
+            let error = AsrError::from_dashscope(Some(code), Some("message"));
+            assert_eq!(kind(&error), expected, "{}", code);
+            assert_eq!(error.is_retryable(), retryable, "{}", code);
+            assert_eq!(error.code(), Some(code));
+            assert!(error.to_string().contains(code));
+        }
+
+        let error = AsrError::from_dashscope(None, None);
+        assert_eq!(kind(&error), "Other");
+        assert_eq!(error.code(), Some("Unknown"));
+        assert_eq!(error.to_string(), "task failed (Unknown): Unknown error");
+    }
+
+    #[test]
+    fn connection_errors() {
+        let network = AsrError::Network("reset".to_string());
+        assert!(network.is_retryable());
+        assert_eq!(network.code(), None);
+
+        let transport = AsrError::Transport("proxy rejected credentials".to_string());
+        assert!(!transport.is_retryable());
+        assert_eq!(transport.code(), None);
+
+        let protocol = AsrError::Protocol("bad frame".to_string());
+        assert!(!protocol.is_retryable());
+        assert_eq!(protocol.code(), None);
+
+        let json = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert_eq!(kind(&AsrError::from(json)), "Protocol");
+
+        let wrapped = anyhow::Error::from(network).context("Failed to connect");
+        assert_eq!(kind(&AsrError::from_anyhow(&wrapped)), "Network");
+        let local = anyhow::anyhow!("No such file");
+        assert_eq!(kind(&AsrError::from_anyhow(&local)), "Protocol");
+    }
+}
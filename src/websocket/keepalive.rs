@@ -0,0 +1,348 @@
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::debug;
+
+use super::ModelCapabilities;
+use crate::audio::AudioChunk;
+
+/// Length of one audio chunk from the capture
+const CHUNK_MS: u32 = 100;
+
+/// Bytes of 16kHz 16-bit mono PCM per millisecond
+const BYTES_PER_MS: u64 = 32;
+
+/// Chunks quieter than this are treated as silence
+const SPEECH_THRESHOLD_DBFS: f32 = -45.0;
+
+/// Audio kept while gated and sent ahead of resumed speech, so the first
+/// syllable is not cut off
+const PRE_ROLL_CHUNKS: usize = 3;
+
+/// Silence sent after the last speech on top of the sentence silence, so the
+/// server still sees the pause that ends the sentence
+const HANGOVER_MARGIN_MS: u32 = 700;
+
+/// Sentence silence assumed when the profile does not set one
+const DEFAULT_SENTENCE_SILENCE_MS: u32 = 800;
+
+/// How often a silent frame is sent while gated
+const COMFORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Comfort frame: 20ms of 16kHz 16-bit silence
+const COMFORT_FRAME_BYTES: usize = 640;
+
+/// How the task is kept open while nobody speaks
+///
+/// Except for `Off`, the default, silence is not streamed to the server (and
+/// not billed): a local energy gate holds it back and only real speech is sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeepalivePolicy {
+    /// `heartbeat` if the model supports it, otherwise `comfort`
+    Auto,
+    /// Set the `heartbeat` parameter and send a 20ms silent frame every 5s
+    /// while nobody speaks, which the server then accepts as keepalive
+    Heartbeat,
+    /// Send a 20ms silent frame every 5s while nobody speaks
+    Comfort,
+    /// Let the server close the idle task and open a new one when speech resumes
+    Reconnect,
+    /// Stream all audio, including silence
+    #[default]
+    Off,
+}
+
+impl KeepalivePolicy {
+    /// Pick the concrete policy for `model`
+    pub fn resolve(self, model: &str) -> Self {
+        match self {
+            Self::Auto => {
+                if ModelCapabilities::of(model).is_some_and(|caps| caps.heartbeat) {
+                    Self::Heartbeat
+                } else {
+                    Self::Comfort
+                }
+            }
+            policy => policy,
+        }
+    }
+}
+
+/// Holds back silent audio between the capture and the WebSocket
+pub struct AudioGate {
+    audio_rx: mpsc::Receiver<AudioChunk>,
+    policy: KeepalivePolicy,
+    /// Silent chunks still sent after speech before the gate closes
+    hangover_chunks: usize,
+    silent_chunks: usize,
+    gated: Arc<AtomicBool>,
+    /// Most recent silent chunks while gated
+    pre_roll: VecDeque<AudioChunk>,
+    /// Chunks ready to be sent
+    pending: VecDeque<AudioChunk>,
+    next_comfort: Instant,
+    /// Milliseconds of captured audio passed on, not counting comfort frames
+    forwarded_ms: u64,
+    /// Milliseconds held back since the gate was created
+    skipped_ms: Arc<AtomicU64>,
+}
+
+impl AudioGate {
+    pub fn new(
+        audio_rx: mpsc::Receiver<AudioChunk>,
+        policy: KeepalivePolicy,
+        sentence_silence_ms: Option<u32>,
+    ) -> Self {
+        let hangover_ms =
+            sentence_silence_ms.unwrap_or(DEFAULT_SENTENCE_SILENCE_MS) + HANGOVER_MARGIN_MS;
+
+        Self {
+            audio_rx,
+            policy,
+            hangover_chunks: hangover_ms.div_ceil(CHUNK_MS) as usize,
+            silent_chunks: 0,
+            gated: Arc::new(AtomicBool::new(false)),
+            pre_roll: VecDeque::with_capacity(PRE_ROLL_CHUNKS + 1),
+            pending: VecDeque::new(),
+            next_comfort: Instant::now(),
+            forwarded_ms: 0,
+            skipped_ms: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Shared flag that is set while silence is being held back
+    pub fn gated_flag(&self) -> Arc<AtomicBool> {
+        self.gated.clone()
+    }
+
+    /// Seconds of audio that were not sent
+    pub fn skipped_seconds(&self) -> f32 {
+        self.skipped_ms.load(Ordering::SeqCst) as f32 / 1000.0
+    }
+
+    /// Shared total of the milliseconds held back so far
+    pub fn skipped_counter(&self) -> Arc<AtomicU64> {
+        self.skipped_ms.clone()
+    }
+
+    /// Milliseconds of capture the gate has passed on or held back, which is
+    /// where the audio of the next frame starts
+    pub fn position_ms(&self) -> i64 {
+        (self.forwarded_ms + self.skipped_ms.load(Ordering::SeqCst)) as i64
+    }
+
+    /// Put a chunk back so the next call returns it first
+    pub fn unread(&mut self, chunk: AudioChunk) {
+        self.forwarded_ms -= chunk.data.len() as u64 / BYTES_PER_MS;
+        self.pending.push_front(chunk);
+    }
+
+    /// Pass on a captured chunk
+    fn forward(&mut self, chunk: AudioChunk) -> Option<AudioChunk> {
+        self.forwarded_ms += chunk.data.len() as u64 / BYTES_PER_MS;
+        Some(chunk)
+    }
+
+    /// Next frame to send, or `None` when the capture has stopped
+    ///
+    /// While gated this waits for speech, yielding silent frames in between
+    /// with the `Comfort` and `Heartbeat` policies: a heartbeat task still
+    /// times out if no audio arrives at all.
+    pub async fn next(&mut self) -> Option<AudioChunk> {
+        loop {
+            if let Some(chunk) = self.pending.pop_front() {
+                return self.forward(chunk);
+            }
+
+            let comfort = matches!(
+                self.policy,
+                KeepalivePolicy::Comfort | KeepalivePolicy::Heartbeat
+            ) && self.is_gated();
+            let chunk = tokio::select! {
+                chunk = self.audio_rx.recv() => chunk?,
+                _ = tokio::time::sleep_until(self.next_comfort), if comfort => {
+                    self.next_comfort = Instant::now() + COMFORT_INTERVAL;
+                    return Some(AudioChunk::new(vec![0; COMFORT_FRAME_BYTES]));
+                }
+            };
+
+            if self.policy == KeepalivePolicy::Off {
+                return self.forward(chunk);
+            }
+
+            if is_speech(&chunk.data) {
+                self.silent_chunks = 0;
+                if self.is_gated() {
+                    debug!(
+                        "Speech resumed after {:.1}s held back",
+                        self.skipped_seconds()
+                    );
+                    self.gated.store(false, Ordering::SeqCst);
+                    self.pending.extend(self.pre_roll.drain(..));
+                }
+                self.pending.push_back(chunk);
+                continue;
+            }
+
+            self.silent_chunks += 1;
+            if !self.is_gated() && self.silent_chunks > self.hangover_chunks {
+                debug!("Silence, holding back audio");
+                self.gated.store(true, Ordering::SeqCst);
+                self.next_comfort = Instant::now() + COMFORT_INTERVAL;
+            }
+
+            if !self.is_gated() {
+                return self.forward(chunk);
+            }
+
+            self.pre_roll.push_back(chunk);
+            if self.pre_roll.len() > PRE_ROLL_CHUNKS {
+                self.pre_roll.pop_front();
+                self.skipped_ms.fetch_add(CHUNK_MS as u64, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Whether silence is currently being held back
+    pub fn is_gated(&self) -> bool {
+        self.gated.load(Ordering::SeqCst)
+    }
+}
+
+/// Whether a chunk of 16-bit little-endian PCM is loud enough to be speech
+pub(super) fn is_speech(chunk: &[u8]) -> bool {
+    let samples = chunk.len() / 2;
+    if samples == 0 {
+        return false;
+    }
+
+    let sum_squares: f64 = chunk
+        .chunks_exact(2)
+        .map(|bytes| {
+            let sample = i16::from_le_bytes([bytes[0], bytes[1]]) as f64 / 32768.0;
+            sample * sample
+        })
+        .sum();
+    let rms = (sum_squares / samples as f64).sqrt();
+
+    20.0 * (rms.max(1e-9)).log10() as f32 > SPEECH_THRESHOLD_DBFS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 100ms chunk of a constant-amplitude signal, tagged in its first sample
+    fn chunk(amplitude: i16, tag: i16) -> AudioChunk {
+        let mut samples = vec![amplitude; 1600];
+        samples[0] = tag;
+        AudioChunk::new(samples.iter().flat_map(|s| s.to_le_bytes()).collect())
+    }
+
+    fn tag(chunk: &AudioChunk) -> i16 {
+        i16::from_le_bytes([chunk.data[0], chunk.data[1]])
+    }
+
+    #[test]
+    fn energy_threshold() {
+        assert!(!is_speech(&chunk(0, 0).data));
+        assert!(!is_speech(&chunk(50, 0).data));
+        assert!(is_speech(&chunk(3000, 0).data));
+    }
+
+    #[tokio::test]
+    async fn holds_back_silence_and_sends_pre_roll() {
+        let (tx, rx) = mpsc::channel(100);
+        let mut gate = AudioGate::new(rx, KeepalivePolicy::Reconnect, Some(200));
+        // Hangover: (200 + 700) / 100 = 9 chunks
+
+        tx.send(chunk(3000, 1)).await.unwrap();
+        for i in 0..20 {
+            tx.send(chunk(0, 100 + i)).await.unwrap();
+        }
+        tx.send(chunk(3000, 2)).await.unwrap();
+        drop(tx);
+
+        let mut sent = Vec::new();
+        while let Some(frame) = gate.next().await {
+            sent.push(tag(&frame));
+        }
+
+        // Speech, 9 hangover chunks, then the last 3 silent chunks as pre-roll
+        let mut expected = vec![1];
+        expected.extend(100..109);
+        expected.extend([117, 118, 119, 2]);
+        assert_eq!(sent, expected);
+        assert!((gate.skipped_seconds() - 0.8).abs() < 1e-3);
+        assert_eq!(gate.position_ms(), 2200);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sends_comfort_frames_while_gated() {
+        let (tx, rx) = mpsc::channel(100);
+        let mut gate = AudioGate::new(rx, KeepalivePolicy::Comfort, Some(200));
+
+        for i in 0..10 {
+            tx.send(chunk(0, i)).await.unwrap();
+        }
+        for _ in 0..9 {
+            gate.next().await.unwrap();
+        }
+
+        // Now gated: the next frame is a comfort frame after the interval
+        let started = Instant::now();
+        let frame = gate.next().await.unwrap();
+        assert_eq!(frame.data.len(), COMFORT_FRAME_BYTES);
+        assert!(frame.data.iter().all(|&b| b == 0));
+        assert!(started.elapsed() >= COMFORT_INTERVAL);
+        drop(tx);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn heartbeat_sends_frames_during_long_silence() {
+        let (tx, rx) = mpsc::channel(100);
+        let mut gate = AudioGate::new(rx, KeepalivePolicy::Heartbeat, Some(200));
+
+        for i in 0..10 {
+            tx.send(chunk(0, i)).await.unwrap();
+        }
+        for _ in 0..9 {
+            gate.next().await.unwrap();
+        }
+
+        // A minute of silence still yields a frame at every interval
+        let started = Instant::now();
+        for n in 1..=12 {
+            let frame = gate.next().await.unwrap();
+            assert_eq!(frame.data.len(), COMFORT_FRAME_BYTES);
+            assert_eq!(started.elapsed(), COMFORT_INTERVAL * n);
+        }
+        drop(tx);
+    }
+
+    #[test]
+    fn gate_is_opt_in() {
+        assert_eq!(KeepalivePolicy::default(), KeepalivePolicy::Off);
+        assert_eq!(
+            KeepalivePolicy::default().resolve("paraformer-realtime-v2"),
+            KeepalivePolicy::Off
+        );
+    }
+
+    #[test]
+    fn auto_prefers_heartbeat() {
+        assert_eq!(
+            KeepalivePolicy::Auto.resolve("paraformer-realtime-v2"),
+            KeepalivePolicy::Heartbeat
+        );
+        assert_eq!(
+            KeepalivePolicy::Auto.resolve("gummy-realtime-v1"),
+            KeepalivePolicy::Comfort
+        );
+    }
+}
@@ -0,0 +1,371 @@
+use anyhow::{bail, Result};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use super::AsrError;
+
+/// How long a rate-limited key is left alone
+const RATE_LIMIT_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// How long a key whose quota ran out is left alone
+const QUOTA_COOLDOWN: Duration = Duration::from_secs(60 * 60);
+
+/// Bytes of 16kHz 16-bit mono PCM per millisecond
+const BYTES_PER_MS: u64 = 32;
+
+/// An API key; formatting never shows its value
+#[derive(Clone)]
+pub struct ApiKey(String);
+
+impl ApiKey {
+    pub fn new(key: String) -> Self {
+        crate::logging::add_secret(&key);
+        Self(key)
+    }
+
+    /// The key itself, for the request that authenticates with it
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ApiKey(***)")
+    }
+}
+
+/// What a key has been used for in this run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyUsage {
+    pub tasks: u64,
+    pub audio_ms: u64,
+    pub rate_limited: u32,
+    pub quota_exceeded: u32,
+}
+
+/// A key handed out for one connection
+#[derive(Debug, Clone)]
+pub struct KeyLease {
+    pub index: usize,
+    pub label: String,
+    pub key: ApiKey,
+}
+
+/// Labelled API keys used in turn as they hit rate limits or run out of quota
+///
+/// Read from `DASHSCOPE_API_KEYS` (`label=key,label=key`), or the single
+/// `DASHSCOPE_API_KEY`. Keys are referred to by label everywhere else.
+pub struct KeyPool {
+    keys: Vec<(String, ApiKey)>,
+    state: Mutex<PoolState>,
+}
+
+struct PoolState {
+    current: usize,
+    keys: Vec<KeyState>,
+    /// Failures that cooled down or disabled a key
+    set_aside: u64,
+}
+
+#[derive(Default)]
+struct KeyState {
+    usage: KeyUsage,
+    cooling_until: Option<Instant>,
+    /// Rejected by the server; not tried again in this run
+    disabled: bool,
+}
+
+impl KeyState {
+    fn available(&self, now: Instant) -> bool {
+        !self.disabled && self.cooling_until.is_none_or(|until| until <= now)
+    }
+}
+
+impl KeyPool {
+    fn new(keys: Vec<(String, ApiKey)>) -> Self {
+        let state = PoolState {
+            current: 0,
+            keys: keys.iter().map(|_| KeyState::default()).collect(),
+            set_aside: 0,
+        };
+        Self {
+            keys,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// A pool of one key
+    pub fn single(key: String) -> Self {
+        Self::new(vec![("default".to_string(), ApiKey::new(key))])
+    }
+
+    /// Parse `label=key` pairs separated by commas; unlabelled keys are
+    /// called `key1`, `key2`, ...
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut keys: Vec<(String, ApiKey)> = Vec::new();
+        for (i, entry) in spec.split(',').map(str::trim).enumerate() {
+            if entry.is_empty() {
+                continue;
+            }
+            let (label, key) = match entry.split_once('=') {
+                Some((label, key)) => (label.trim().to_string(), key.trim()),
+                None => (format!("key{}", i + 1), entry),
+            };
+            if label.is_empty() || key.is_empty() {
+                bail!("Key #{} needs the form label=key", i + 1);
+            }
+            if keys.iter().any(|(existing, _)| *existing == label) {
+                bail!("Key label {:?} is used twice", label);
+            }
+            keys.push((label, ApiKey::new(key.to_string())));
+        }
+        if keys.is_empty() {
+            bail!("No API keys given");
+        }
+        Ok(Self::new(keys))
+    }
+
+    /// Keys from `DASHSCOPE_API_KEYS`, or else `DASHSCOPE_API_KEY`
+    pub fn from_env() -> Result<Self> {
+        let env = |name| {
+            std::env::var(name)
+                .ok()
+                .filter(|v: &String| !v.trim().is_empty())
+        };
+        if let Some(spec) = env("DASHSCOPE_API_KEYS") {
+            let pool = Self::parse(&spec).map_err(|e| e.context("Invalid DASHSCOPE_API_KEYS"))?;
+            info!(
+                "Using {} API keys: {}",
+                pool.len(),
+                pool.labels().join(", ")
+            );
+            return Ok(pool);
+        }
+        match env("DASHSCOPE_API_KEY") {
+            Some(key) => Ok(Self::single(key)),
+            None => bail!("Neither DASHSCOPE_API_KEYS nor DASHSCOPE_API_KEY is set"),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn labels(&self) -> Vec<&str> {
+        self.keys.iter().map(|(label, _)| label.as_str()).collect()
+    }
+
+    /// Label of the key at `index`
+    pub fn label(&self, index: usize) -> &str {
+        &self.keys[index].0
+    }
+
+    /// The current key, or the next one that is not cooling down
+    pub fn acquire(&self) -> Result<KeyLease, AsrError> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let count = self.keys.len();
+        let Some(index) = (0..count)
+            .map(|offset| (state.current + offset) % count)
+            .find(|&index| state.keys[index].available(now))
+        else {
+            return Err(self.exhausted(&state));
+        };
+        state.current = index;
+
+        let (label, key) = &self.keys[index];
+        Ok(KeyLease {
+            index,
+            label: label.clone(),
+            key: key.clone(),
+        })
+    }
+
+    /// Whether any key can be used right now
+    pub fn has_available(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.keys.iter().any(|key| key.available(now))
+    }
+
+    /// Record a failure of the key at `index`, cooling it down (or dropping
+    /// it) if the error is about the key
+    pub fn report(&self, index: usize, error: &AsrError) {
+        let mut state = self.state.lock().unwrap();
+        let key = &mut state.keys[index];
+        let label = &self.keys[index].0;
+        match error {
+            AsrError::RateLimited { .. } => {
+                key.usage.rate_limited += 1;
+                key.cooling_until = Some(Instant::now() + RATE_LIMIT_COOLDOWN);
+                warn!(
+                    "API key {} is rate limited, cooling down for {}s",
+                    label,
+                    RATE_LIMIT_COOLDOWN.as_secs()
+                );
+            }
+            AsrError::QuotaExceeded { .. } => {
+                key.usage.quota_exceeded += 1;
+                key.cooling_until = Some(Instant::now() + QUOTA_COOLDOWN);
+                warn!(
+                    "API key {} is out of quota, cooling down for {}min",
+                    label,
+                    QUOTA_COOLDOWN.as_secs() / 60
+                );
+            }
+            AsrError::Auth { .. } => {
+                key.disabled = true;
+                warn!("API key {} was rejected, not using it again", label);
+            }
+            _ => return,
+        }
+        state.set_aside += 1;
+        if self.keys.len() > 1 {
+            state.current = (index + 1) % self.keys.len();
+        }
+    }
+
+    /// How many failures so far cooled down or disabled a key; a failure
+    /// that did not change this was not about the key
+    pub fn set_aside_count(&self) -> u64 {
+        self.state.lock().unwrap().set_aside
+    }
+
+    /// A task started on the key at `index`
+    pub fn task_started(&self, index: usize) {
+        self.state.lock().unwrap().keys[index].usage.tasks += 1;
+    }
+
+    /// Audio was sent on the key at `index`
+    pub fn audio_sent(&self, index: usize, bytes: usize) {
+        self.state.lock().unwrap().keys[index].usage.audio_ms += bytes as u64 / BYTES_PER_MS;
+    }
+
+    /// Usage of every key by label
+    pub fn usage(&self) -> Vec<(String, KeyUsage)> {
+        let state = self.state.lock().unwrap();
+        self.keys
+            .iter()
+            .zip(&state.keys)
+            .map(|((label, _), key)| (label.clone(), key.usage.clone()))
+            .collect()
+    }
+
+    /// Replace every key value in `text` by its label
+    pub fn redact(&self, text: &str) -> String {
+        self.keys
+            .iter()
+            .fold(text.to_string(), |text, (label, key)| {
+                text.replace(key.expose(), &format!("<key {}>", label))
+            })
+    }
+
+    /// Remove key values from a server error message
+    pub fn redact_error(&self, error: &mut AsrError) {
+        match error {
+            AsrError::Auth { message, .. }
+            | AsrError::QuotaExceeded { message, .. }
+            | AsrError::RateLimited { message, .. }
+            | AsrError::InvalidParameter { message, .. }
+            | AsrError::ModelUnavailable { message, .. }
+            | AsrError::Server { message, .. }
+            | AsrError::Other { message, .. }
+            | AsrError::Network(message)
+            | AsrError::Transport(message)
+            | AsrError::Protocol(message) => *message = self.redact(message),
+        }
+    }
+
+    /// Error for when no key can be used
+    fn exhausted(&self, state: &PoolState) -> AsrError {
+        let message = format!(
+            "all {} API keys are cooling down or were rejected",
+            self.keys.len()
+        );
+        let code = "KeysExhausted".to_string();
+        if state.keys.iter().any(|key| key.usage.quota_exceeded > 0) {
+            AsrError::QuotaExceeded { code, message }
+        } else if state.keys.iter().all(|key| key.disabled) {
+            AsrError::Auth { code, message }
+        } else {
+            AsrError::RateLimited { code, message }
+        }
+    }
+}
+
+impl fmt::Debug for KeyPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyPool")
+            .field("labels", &self.labels())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate_limited() -> AsrError {
+        AsrError::RateLimited {
+            code: "Throttling".to_string(),
+            message: "slow down".to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_labels_and_hides_values() {
+        let pool = KeyPool::parse("team=sk-team, sk-other").unwrap();
+        assert_eq!(pool.labels(), vec!["team", "key2"]);
+
+        let debug = format!("{:?} {:?}", pool, pool.acquire().unwrap());
+        assert!(!debug.contains("sk-"), "{}", debug);
+        assert_eq!(pool.redact("Invalid key sk-team"), "Invalid key <key team>");
+
+        assert!(KeyPool::parse("a=sk-1,a=sk-2").is_err());
+        assert!(KeyPool::parse(" , ").is_err());
+    }
+
+    #[test]
+    fn rotates_away_from_cooling_keys() {
+        let pool = KeyPool::parse("a=sk-a,b=sk-b").unwrap();
+        let first = pool.acquire().unwrap();
+        assert_eq!(first.label, "a");
+        pool.task_started(first.index);
+        pool.audio_sent(first.index, 3200);
+
+        pool.report(first.index, &rate_limited());
+        assert_eq!(pool.acquire().unwrap().label, "b");
+        // Errors that are not about the key keep it
+        pool.report(1, &AsrError::Network("reset".to_string()));
+        assert_eq!(pool.acquire().unwrap().label, "b");
+
+        pool.report(
+            1,
+            &AsrError::QuotaExceeded {
+                code: "Arrearage".to_string(),
+                message: "out of quota".to_string(),
+            },
+        );
+        assert!(!pool.has_available());
+        assert!(matches!(
+            pool.acquire(),
+            Err(AsrError::QuotaExceeded { .. })
+        ));
+
+        let usage = pool.usage();
+        assert_eq!(usage[0].0, "a");
+        assert_eq!(
+            usage[0].1,
+            KeyUsage {
+                tasks: 1,
+                audio_ms: 100,
+                rate_limited: 1,
+                quota_exceeded: 0,
+            }
+        );
+        assert_eq!(usage[1].1.quota_exceeded, 1);
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::RecognitionResult;
+
+/// Bytes of 16kHz 16-bit mono PCM per millisecond
+const BYTES_PER_MS: i64 = 32;
+
+/// Capture time of the audio sent to one task
+///
+/// Server times are offsets into the audio the task received, which skips
+/// whatever the gate held back, so they are mapped back through the chunks
+/// actually sent.
+#[derive(Default)]
+pub(super) struct AudioClock {
+    /// End offset in milliseconds and capture time of every chunk sent
+    chunks: Vec<(i64, Instant)>,
+    sent_ms: i64,
+}
+
+impl AudioClock {
+    pub fn sent(&mut self, bytes: usize, captured_at: Instant) {
+        self.sent_ms += bytes as i64 / BYTES_PER_MS;
+        self.chunks.push((self.sent_ms, captured_at));
+    }
+
+    /// When the audio at `offset_ms` into the task was captured
+    pub fn spoken_at(&self, offset_ms: i64) -> Option<Instant> {
+        let index = self.chunks.partition_point(|&(end, _)| end < offset_ms);
+        let &(end, captured_at) = self.chunks.get(index).or(self.chunks.last())?;
+        let behind = Duration::from_millis((end - offset_ms).max(0) as u64);
+        Some(captured_at.checked_sub(behind).unwrap_or(captured_at))
+    }
+}
+
+/// Newest audio a result covers, in milliseconds into its task
+pub(super) fn heard_until(result: &RecognitionResult) -> i64 {
+    result
+        .transcription
+        .iter()
+        .chain(result.translations.values())
+        .flat_map(|stream| stream.words.iter().map(|word| word.end_time))
+        .max()
+        .unwrap_or(result.end_time)
+}
+
+/// Latency samples of a session
+///
+/// - first partial: speech to the first result of a sentence
+/// - final: end of a sentence's speech to its final result
+/// - typing: end of a sentence's speech to its text being typed
+#[derive(Default)]
+pub struct LatencyTracker {
+    inner: Mutex<Samples>,
+}
+
+#[derive(Default)]
+struct Samples {
+    first_partial: Vec<Duration>,
+    final_result: Vec<Duration>,
+    typing: Vec<Duration>,
+    seen: HashSet<i32>,
+    /// When the speech of finished, not yet typed sentences ended
+    awaiting_typing: HashMap<i32, Instant>,
+}
+
+impl LatencyTracker {
+    /// A result for `sentence_id` covering speech up to `spoken_at` arrived at `arrived`
+    pub(super) fn result(
+        &self,
+        sentence_id: i32,
+        is_final: bool,
+        spoken_at: Instant,
+        arrived: Instant,
+    ) {
+        let latency = arrived.saturating_duration_since(spoken_at);
+        let mut samples = self.inner.lock().unwrap();
+        if samples.seen.insert(sentence_id) {
+            samples.first_partial.push(latency);
+        }
+        if is_final {
+            samples.final_result.push(latency);
+            samples.awaiting_typing.insert(sentence_id, spoken_at);
+        }
+    }
+
+    /// The final text of `sentence_id` has been typed
+    pub fn typed(&self, sentence_id: i32) {
+        let mut samples = self.inner.lock().unwrap();
+        if let Some(spoken_at) = samples.awaiting_typing.remove(&sentence_id) {
+            samples.typing.push(spoken_at.elapsed());
+        }
+    }
+
+    /// Report of the samples so far, starting over for the next session
+    pub fn take_report(&self) -> LatencyReport {
+        let samples = std::mem::take(&mut *self.inner.lock().unwrap());
+        LatencyReport {
+            first_partial: Percentiles::of(samples.first_partial),
+            final_result: Percentiles::of(samples.final_result),
+            typing: Percentiles::of(samples.typing),
+        }
+    }
+}
+
+/// Latency percentiles of one session
+#[derive(Debug, Clone)]
+pub struct LatencyReport {
+    pub first_partial: Option<Percentiles>,
+    pub final_result: Option<Percentiles>,
+    pub typing: Option<Percentiles>,
+}
+
+impl LatencyReport {
+    pub fn is_empty(&self) -> bool {
+        self.first_partial.is_none() && self.final_result.is_none() && self.typing.is_none()
+    }
+}
+
+impl fmt::Display for LatencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<14} {:>5} {:>7} {:>7} {:>7} {:>7}",
+            "latency", "n", "p50", "p90", "p99", "max"
+        )?;
+        for (name, percentiles) in [
+            ("first partial", &self.first_partial),
+            ("final", &self.final_result),
+            ("typing", &self.typing),
+        ] {
+            match percentiles {
+                Some(p) => writeln!(
+                    f,
+                    "{:<14} {:>5} {:>7} {:>7} {:>7} {:>7}",
+                    name,
+                    p.count,
+                    ms(p.p50),
+                    ms(p.p90),
+                    ms(p.p99),
+                    ms(p.max)
+                )?,
+                None => writeln!(f, "{:<14} {:>5}", name, 0)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn ms(duration: Duration) -> String {
+    format!("{}ms", duration.as_millis())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Percentiles {
+    pub count: usize,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Percentiles {
+    /// Nearest-rank percentiles, or `None` without samples
+    fn of(mut samples: Vec<Duration>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort();
+        let rank = |p: usize| samples[(samples.len() * p).div_ceil(100).max(1) - 1];
+        Some(Self {
+            count: samples.len(),
+            p50: rank(50),
+            p90: rank(90),
+            p99: rank(99),
+            max: samples[samples.len() - 1],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_offsets_to_capture_time() {
+        let start = Instant::now();
+        let mut clock = AudioClock::default();
+        for i in 1..=3 {
+            // 100ms chunks captured 100ms apart, then a gap the gate held back
+            clock.sent(3200, start + Duration::from_millis(100 * i));
+        }
+        clock.sent(3200, start + Duration::from_secs(10));
+
+        assert_eq!(
+            clock.spoken_at(250),
+            Some(start + Duration::from_millis(250))
+        );
+        assert_eq!(
+            clock.spoken_at(300),
+            Some(start + Duration::from_millis(300))
+        );
+        // 350ms into the task is the second half of the chunk after the gap
+        assert_eq!(
+            clock.spoken_at(350),
+            Some(start + Duration::from_millis(9950))
+        );
+        // Beyond the audio sent: the newest chunk
+        assert_eq!(clock.spoken_at(900), Some(start + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn percentiles_use_nearest_rank() {
+        let samples = (1..=10).map(Duration::from_millis).collect();
+        let p = Percentiles::of(samples).unwrap();
+        assert_eq!(
+            (p.count, p.p50, p.p90, p.p99, p.max),
+            (
+                10,
+                Duration::from_millis(5),
+                Duration::from_millis(9),
+                Duration::from_millis(10),
+                Duration::from_millis(10)
+            )
+        );
+        assert!(Percentiles::of(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn tracks_first_partial_final_and_typing() {
+        let tracker = LatencyTracker::default();
+        let spoken = Instant::now();
+        let later = |ms| spoken + Duration::from_millis(ms);
+
+        tracker.result(0, false, spoken, later(300));
+        tracker.result(0, false, spoken, later(500));
+        tracker.result(0, true, spoken, later(800));
+        tracker.typed(0);
+        tracker.typed(0);
+
+        let report = tracker.take_report();
+        assert_eq!(
+            report.first_partial.unwrap().p50,
+            Duration::from_millis(300)
+        );
+        assert_eq!(report.final_result.unwrap().p50, Duration::from_millis(800));
+        assert_eq!(report.typing.unwrap().count, 1);
+        assert!(tracker.take_report().is_empty());
+    }
+}
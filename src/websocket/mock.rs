@@ -0,0 +1,302 @@
+use anyhow::{Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, warn};
+use url::Url;
+
+use super::cassette::{CassetteEntry, Record};
+use super::latency::heard_until;
+use super::{ConnectionConfig, Event, RecognitionResult};
+
+/// Bytes of 16kHz 16-bit mono PCM per millisecond
+const BYTES_PER_MS: usize = 32;
+
+/// Local stand-in for the DashScope endpoint
+///
+/// Answers every task with the `result-generated` frames of a cassette (or
+/// fixture), each released once the audio received covers the speech it
+/// describes, so results arrive roughly when a real server would send them.
+pub struct MockServer {
+    addr: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+/// A recorded result and the audio offset it becomes due at
+#[derive(Clone)]
+struct ScriptedResult {
+    due_ms: i64,
+    frame: Value,
+}
+
+impl MockServer {
+    pub async fn start(entries: Vec<CassetteEntry>) -> Result<Self> {
+        let script = script(entries);
+        if script.is_empty() {
+            anyhow::bail!("No result-generated frames to answer with");
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .context("Failed to start mock server")?;
+        let addr = listener.local_addr()?;
+
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let script = script.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve(stream, script).await {
+                        warn!("Mock server connection failed: {:#}", e);
+                    }
+                });
+            }
+        });
+
+        Ok(Self { addr, handle })
+    }
+
+    /// Connection settings pointing at this server
+    pub fn connection(&self) -> ConnectionConfig {
+        ConnectionConfig {
+            endpoint: Url::parse(&format!("ws://{}/", self.addr)).expect("valid mock URL"),
+            proxy: None,
+            ca_bundle: None,
+            pins: Vec::new(),
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Received `result-generated` frames in order, with the audio they need
+fn script(entries: Vec<CassetteEntry>) -> Vec<ScriptedResult> {
+    entries
+        .into_iter()
+        .filter_map(|entry| match entry.record {
+            Record::Received { frame } => Some(frame),
+            _ => None,
+        })
+        .filter_map(|frame| {
+            let event: Event = serde_json::from_value(frame.clone()).ok()?;
+            if event.header.event.as_deref() != Some("result-generated") {
+                return None;
+            }
+            let result = RecognitionResult::from_output(event.payload.output.as_ref()?)?;
+            Some(ScriptedResult {
+                due_ms: heard_until(&result),
+                frame,
+            })
+        })
+        .collect()
+}
+
+/// Run the protocol on one connection
+async fn serve(stream: TcpStream, script: Vec<ScriptedResult>) -> Result<()> {
+    let mut ws = tokio_tungstenite::accept_async(stream).await?;
+    let mut task_id = String::new();
+    let mut received_ms = 0;
+    let mut next = 0;
+
+    while let Some(message) = ws.next().await {
+        match message? {
+            Message::Text(text) => {
+                let command: Value = serde_json::from_str(&text)?;
+                match command["header"]["action"].as_str() {
+                    Some("run-task") => {
+                        task_id = command["header"]["task_id"]
+                            .as_str()
+                            .unwrap_or_default()
+                            .to_string();
+                        debug!("Mock task {} started", task_id);
+                        ws.send(server_event(&task_id, "task-started")).await?;
+                    }
+                    Some("finish-task") => {
+                        for result in &script[next..] {
+                            ws.send(result_frame(result, &task_id)).await?;
+                        }
+                        ws.send(server_event(&task_id, "task-finished")).await?;
+                        ws.close(None).await?;
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            Message::Binary(audio) => {
+                received_ms += (audio.len() / BYTES_PER_MS) as i64;
+                while let Some(result) = script.get(next).filter(|r| r.due_ms <= received_ms) {
+                    ws.send(result_frame(result, &task_id)).await?;
+                    next += 1;
+                }
+            }
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn server_event(task_id: &str, event: &str) -> Message {
+    let frame = json!({
+        "header": {"task_id": task_id, "event": event, "attributes": {}},
+        "payload": {},
+    });
+    Message::Text(frame.to_string())
+}
+
+fn result_frame(result: &ScriptedResult, task_id: &str) -> Message {
+    let mut frame = result.frame.clone();
+    frame["header"]["task_id"] = json!(task_id);
+    Message::Text(frame.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::AudioChunk;
+    use crate::usage::Ledger;
+    use crate::websocket::{read_cassette, AsrClient, AsrEvent, KeepalivePolicy, LatencyTracker};
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    /// 100ms chunk loud enough to pass the audio gate
+    fn speech() -> AudioChunk {
+        AudioChunk::new(
+            [3000i16; 1600]
+                .iter()
+                .flat_map(|s| s.to_le_bytes())
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn serves_fixture_results_and_measures_latency() {
+        let entries = read_cassette(Path::new("tests/fixtures/streaming_en.jsonl")).unwrap();
+        let server = MockServer::start(entries).await.unwrap();
+        let ledger_path =
+            std::env::temp_dir().join(format!("mock-usage-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&ledger_path);
+        let ledger = Arc::new(Ledger::new(ledger_path.clone()));
+
+        let latency = Arc::new(LatencyTracker::default());
+        let mut client = AsrClient::new("mock".to_string())
+            .with_connection(server.connection())
+            .with_latency(latency.clone())
+            .with_ledger(ledger.clone());
+
+        let (audio_tx, audio_rx) = mpsc::channel(100);
+        let (event_tx, mut event_rx) = mpsc::channel(100);
+        // 5 seconds of audio covers every result of the fixture
+        for _ in 0..50 {
+            audio_tx.send(AudioChunk::new(vec![0; 3200])).await.unwrap();
+        }
+        drop(audio_tx);
+        client.start_recognition(audio_rx, event_tx).await.unwrap();
+
+        let mut finals = Vec::new();
+        while let Some(event) = event_rx.recv().await {
+            match event {
+                AsrEvent::ResultGenerated(result) if result.is_final => {
+                    latency.typed(result.sentence_id);
+                    finals.push(result.preferred().unwrap().text.clone());
+                }
+                AsrEvent::TaskFinished => break,
+                AsrEvent::TaskFailed { error } => panic!("task failed: {}", error),
+                _ => {}
+            }
+        }
+        assert!(!finals.is_empty());
+
+        let report = latency.take_report();
+        assert_eq!(report.final_result.unwrap().count, finals.len());
+        assert_eq!(report.typing.unwrap().count, finals.len());
+        assert!(report.first_partial.is_some());
+
+        let usage = ledger.read().unwrap();
+        std::fs::remove_file(&ledger_path).unwrap();
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].audio_seconds, 5.0);
+        let characters: usize = finals.iter().map(|text| text.chars().count()).sum();
+        assert_eq!(usage[0].characters, characters as u64);
+    }
+
+    #[tokio::test]
+    async fn session_continues_across_rotation() {
+        let entries = read_cassette(Path::new("tests/fixtures/streaming_en.jsonl")).unwrap();
+        let server = MockServer::start(entries).await.unwrap();
+        let mut client = AsrClient::new("mock".to_string())
+            .with_connection(server.connection())
+            .with_keepalive(KeepalivePolicy::Comfort)
+            .with_rotation(Some(Duration::from_millis(300)));
+
+        let (audio_tx, audio_rx) = mpsc::channel(200);
+        let (event_tx, mut event_rx) = mpsc::channel(1000);
+        let session =
+            tokio::spawn(async move { client.start_recognition(audio_rx, event_tx).await });
+
+        // Speech, then a pause long enough for the gate to close and the
+        // rotation check to replace the task
+        for _ in 0..50 {
+            audio_tx.send(speech()).await.unwrap();
+        }
+        for _ in 0..20 {
+            audio_tx.send(AudioChunk::new(vec![0; 3200])).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(1400)).await;
+        for _ in 0..50 {
+            audio_tx.send(speech()).await.unwrap();
+        }
+        drop(audio_tx);
+        session.await.unwrap().unwrap();
+
+        let mut events = Vec::new();
+        while let Some(event) = event_rx.recv().await {
+            events.push(event);
+        }
+        let started = events
+            .iter()
+            .filter(|event| matches!(event, AsrEvent::TaskStarted))
+            .count();
+        let finished = events
+            .iter()
+            .filter(|event| matches!(event, AsrEvent::TaskFinished))
+            .count();
+        assert_eq!((started, finished), (1, 1));
+        assert!(matches!(events.last(), Some(AsrEvent::TaskFinished)));
+
+        // Both tasks answered with the whole fixture, one after the other
+        let finals: Vec<&RecognitionResult> = events
+            .iter()
+            .filter_map(|event| match event {
+                AsrEvent::ResultGenerated(result) if result.is_final => Some(result),
+                AsrEvent::TaskFailed { error } => panic!("task failed: {}", error),
+                _ => None,
+            })
+            .collect();
+        let half = finals.len() / 2;
+        assert!(half > 0 && finals.len() == 2 * half);
+        let texts = |finals: &[&RecognitionResult]| -> Vec<String> {
+            finals
+                .iter()
+                .map(|result| result.preferred().unwrap().text.clone())
+                .collect()
+        };
+        assert_eq!(texts(&finals[..half]), texts(&finals[half..]));
+        assert!(finals[half].sentence_id > finals[half - 1].sentence_id);
+
+        // The second task starts where the capture was when it took over:
+        // 5s of speech, 1.5s of hangover and 0.2s held back, with the last
+        // 0.3s of pre-roll going to the new task
+        let shift = finals[half].begin_time - finals[0].begin_time;
+        assert_eq!(shift, 6700);
+        assert!(finals[half].begin_time > finals[half - 1].end_time);
+    }
+}
@@ -1,12 +1,54 @@
 use anyhow::{Context, Result};
+use futures_util::stream::SplitSink;
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
 use tokio::sync::mpsc;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
-use tracing::{debug, error, info, warn};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::{
+    client_async_with_config, tungstenite::Message, MaybeTlsStream, WebSocketStream,
+};
+use tracing::{debug, error, info, warn, Instrument};
 use uuid::Uuid;
 
-const WS_URL: &str = "wss://dashscope.aliyuncs.com/api-ws/v1/inference/";
+mod batch;
+mod cassette;
+mod error;
+mod keepalive;
+mod keys;
+mod latency;
+mod mock;
+mod openai;
+mod options;
+mod result;
+mod timeline;
+mod tls;
+mod translation;
+mod transport;
+mod warm;
+
+pub use batch::{BatchClient, DEFAULT_BATCH_MODEL};
+pub use cassette::{read_cassette, replay, CassetteRecorder};
+pub use error::AsrError;
+pub use keepalive::KeepalivePolicy;
+pub use keys::KeyPool;
+pub use latency::LatencyTracker;
+pub use mock::MockServer;
+pub use openai::{RealtimeClient, DEFAULT_OPENAI_MODEL};
+pub use options::{ModelCapabilities, RecognitionOptions, DEFAULT_MODEL};
+
+use crate::audio::AudioChunk;
+use crate::usage::{Ledger, TaskMeter};
+use keepalive::AudioGate;
+use latency::{heard_until, AudioClock};
+pub use result::{RecognitionResult, SentenceText, Shown};
+pub use timeline::SharedTimeline;
+use translation::TranslationPicker;
+pub use transport::ConnectionConfig;
+pub use warm::WarmConnection;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Header {
@@ -87,6 +129,19 @@ pub struct Parameters {
     pub transcription_enabled: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub translation_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diarization_enabled: Option<bool>,
+    /// Silence in milliseconds that ends a sentence
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_sentence_silence: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub semantic_punctuation_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disfluency_removal_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inverse_text_normalization_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heartbeat: Option<bool>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -119,27 +174,233 @@ pub struct Event {
 #[derive(Debug, Clone)]
 pub enum AsrEvent {
     TaskStarted,
-    ResultGenerated { text: String, is_final: bool },
+    ResultGenerated(RecognitionResult),
     TaskFinished,
-    TaskFailed { error: String },
+    TaskFailed { error: AsrError },
 }
 
+/// Translate a server event into an `AsrEvent`, or `None` if it carries nothing to act on
+pub(crate) fn asr_event(event: &Event) -> Option<AsrEvent> {
+    let event_type = event.header.event.as_deref()?;
+    match event_type {
+        "task-started" => {
+            info!("Task started");
+            Some(AsrEvent::TaskStarted)
+        }
+        "result-generated" => {
+            let result = event
+                .payload
+                .output
+                .as_ref()
+                .and_then(RecognitionResult::from_output)?;
+            for stream in result.streams() {
+                debug!(
+                    "Sentence {} [{}] {}-{}ms, {}/{} words fixed: {} (final: {})",
+                    result.sentence_id,
+                    stream.lang.as_deref().unwrap_or("source"),
+                    stream.begin_time,
+                    stream.end_time,
+                    stream.fixed_word_count(),
+                    stream.words.len(),
+                    crate::logging::text(&stream.text),
+                    stream.sentence_end
+                );
+            }
+            Some(AsrEvent::ResultGenerated(result))
+        }
+        "task-finished" => {
+            info!("Task finished");
+            Some(AsrEvent::TaskFinished)
+        }
+        "task-failed" => {
+            let error = AsrError::from_dashscope(
+                event.header.error_code.as_deref(),
+                event.header.error_message.as_deref(),
+            );
+            error!("Task failed: {}", error);
+            Some(AsrEvent::TaskFailed { error })
+        }
+        _ => {
+            warn!("Unknown event: {}", event_type);
+            None
+        }
+    }
+}
+
+/// An authenticated WebSocket connection that has not started a task yet
+pub struct AsrConnection {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    opened_at: Instant,
+    /// Index of the pool key the connection authenticated with
+    key: usize,
+}
+
+impl AsrConnection {
+    /// Time since the WebSocket handshake completed
+    pub fn age(&self) -> Duration {
+        self.opened_at.elapsed()
+    }
+
+    /// Send a ping and wait for the matching pong to check the connection is still usable
+    pub async fn ping(&mut self, timeout: Duration) -> Result<(), AsrError> {
+        self.stream.send(Message::Ping(Vec::new())).await?;
+
+        let wait_pong = async {
+            while let Some(msg) = self.stream.next().await {
+                match msg? {
+                    Message::Pong(_) => return Ok(()),
+                    Message::Close(_) => {
+                        return Err(AsrError::Network("connection closed by server".to_string()))
+                    }
+                    _ => {}
+                }
+            }
+            Err(AsrError::Network("connection closed".to_string()))
+        };
+
+        tokio::time::timeout(timeout, wait_pong)
+            .await
+            .map_err(|_| AsrError::Network("ping timed out".to_string()))?
+    }
+}
+
+/// How long a due rotation waits for a pause before rotating mid-speech
+const ROTATION_GRACE: Duration = Duration::from_secs(120);
+
+/// How often rotation is re-checked while no audio is being sent
+const ROTATION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
 pub struct AsrClient {
-    api_key: String,
+    keys: Arc<KeyPool>,
     task_id: Option<String>,
+    connection: ConnectionConfig,
+    model: String,
+    options: RecognitionOptions,
+    keepalive: KeepalivePolicy,
+    rotate_after: Option<Duration>,
+    vocabulary_id: Option<String>,
+    recorder: Option<Arc<CassetteRecorder>>,
+    latency: Option<Arc<LatencyTracker>>,
+    ledger: Option<Arc<Ledger>>,
+    timeline: Option<SharedTimeline>,
 }
 
 impl AsrClient {
     pub fn new(api_key: String) -> Self {
+        Self::from_keys(Arc::new(KeyPool::single(api_key)))
+    }
+
+    /// Client authenticating with the keys of `keys`, moving on when one is
+    /// rate limited or out of quota
+    pub fn from_keys(keys: Arc<KeyPool>) -> Self {
         Self {
-            api_key,
+            keys,
             task_id: None,
+            connection: ConnectionConfig::default(),
+            model: DEFAULT_MODEL.to_string(),
+            options: RecognitionOptions::default(),
+            keepalive: KeepalivePolicy::Off,
+            rotate_after: None,
+            vocabulary_id: None,
+            recorder: None,
+            latency: None,
+            ledger: None,
+            timeline: None,
         }
     }
 
+    pub fn keys(&self) -> &Arc<KeyPool> {
+        &self.keys
+    }
+
+    /// Record the protocol traffic of every session to a cassette
+    pub fn with_recorder(mut self, recorder: Arc<CassetteRecorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Use a different recognition model
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = model.into();
+        self
+    }
+
+    /// Set the optional recognition parameters (segmentation, punctuation, ...)
+    pub fn with_options(mut self, options: RecognitionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// How to keep the task open while nobody speaks
+    ///
+    /// `Heartbeat` also needs `RecognitionOptions::heartbeat` to be set.
+    pub fn with_keepalive(mut self, policy: KeepalivePolicy) -> Self {
+        self.keepalive = policy;
+        self
+    }
+
+    /// Replace the task with a new one once it has run this long
+    pub fn with_rotation(mut self, rotate_after: Option<Duration>) -> Self {
+        self.rotate_after = rotate_after;
+        self
+    }
+
+    /// Measure how long results take after the audio was captured
+    pub fn with_latency(mut self, latency: Arc<LatencyTracker>) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Record the usage of every task in `ledger`
+    pub fn with_ledger(mut self, ledger: Arc<Ledger>) -> Self {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    /// Stitch results onto `timeline` instead of one of the session's own
+    pub fn with_timeline(mut self, timeline: SharedTimeline) -> Self {
+        self.timeline = Some(timeline);
+        self
+    }
+
+    /// Bias recognition towards the hotwords of this vocabulary
+    pub fn with_vocabulary(mut self, vocabulary_id: Option<String>) -> Self {
+        self.vocabulary_id = vocabulary_id;
+        self
+    }
+
+    fn keepalive(&self) -> KeepalivePolicy {
+        self.keepalive.resolve(&self.model)
+    }
+
+    /// Use a different endpoint, proxy or CA bundle
+    pub fn with_connection(mut self, connection: ConnectionConfig) -> Self {
+        self.connection = connection;
+        self
+    }
+
     fn generate_run_task_cmd(&mut self) -> Event {
         let task_id = Uuid::new_v4().to_string();
         self.task_id = Some(task_id.clone());
+        let translate = options::supports_translation(&self.model);
+
+        let mut parameters = Parameters {
+            format: Some("pcm".to_string()),
+            sample_rate: Some(16000),
+            vocabulary_id: self.vocabulary_id.clone(),
+            language: None,
+            transcription_enabled: translate.then_some(true),
+            translation_enabled: translate.then_some(true),
+            translation_target_languages: translate.then(|| vec!["en".to_string()]),
+            diarization_enabled: None,
+            max_sentence_silence: None,
+            semantic_punctuation_enabled: None,
+            disfluency_removal_enabled: None,
+            inverse_text_normalization_enabled: None,
+            heartbeat: None,
+        };
+        self.options.apply(&mut parameters);
 
         Event {
             header: Header {
@@ -155,27 +416,19 @@ impl AsrClient {
                 task_group: Some("audio".to_string()),
                 task: Some("asr".to_string()),
                 function: Some("recognition".to_string()),
-                model: Some("gummy-realtime-v1".to_string()),
-                parameters: Some(Parameters {
-                    format: Some("pcm".to_string()),
-                    sample_rate: Some(16000),
-                    vocabulary_id: None,
-                    language: None,
-                    transcription_enabled: Some(true),
-                    translation_enabled: Some(true),
-                    translation_target_languages: Some(vec!["en".to_string()]),
-                }),
+                model: Some(self.model.clone()),
+                parameters: Some(parameters),
                 input: Some(Input {}),
                 output: None,
             },
         }
     }
 
-    fn generate_finish_task_cmd(&self) -> Option<Event> {
-        self.task_id.as_ref().map(|task_id| Event {
+    fn generate_finish_task_cmd(task_id: &str) -> Event {
+        Event {
             header: Header {
                 action: Some("finish-task".to_string()),
-                task_id: task_id.clone(),
+                task_id: task_id.to_string(),
                 streaming: Some("duplex".to_string()),
                 event: None,
                 error_code: None,
@@ -191,20 +444,23 @@ impl AsrClient {
                 input: Some(Input {}),
                 output: None,
             },
-        })
+        }
     }
 
-    pub async fn start_recognition(
-        &mut self,
-        mut audio_rx: mpsc::Receiver<Vec<u8>>,
-        event_tx: mpsc::Sender<AsrEvent>,
-    ) -> Result<()> {
-        let url = url::Url::parse(WS_URL)?;
+    /// Open an authenticated WebSocket connection to DashScope
+    pub async fn connect(&self) -> Result<AsrConnection> {
+        let url = &self.connection.endpoint;
+        let host = url.host_str().unwrap_or("dashscope.aliyuncs.com");
+        let host_header = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
 
+        let lease = self.keys.acquire()?;
         let request = http::Request::builder()
-            .uri(WS_URL)
-            .header("Authorization", format!("bearer {}", self.api_key))
-            .header("Host", url.host_str().unwrap_or("dashscope.aliyuncs.com"))
+            .uri(url.as_str())
+            .header("Authorization", format!("bearer {}", lease.key.expose()))
+            .header("Host", host_header)
             .header("Connection", "Upgrade")
             .header("Upgrade", "websocket")
             .header("Sec-WebSocket-Version", "13")
@@ -214,145 +470,444 @@ impl AsrClient {
             )
             .body(())?;
 
-        let (ws_stream, _) = connect_async(request)
+        let stream = self
+            .connection
+            .open_stream()
+            .await
+            .context("Failed to connect to WebSocket")?;
+        let stream = self.connection.tls_stream(stream).await?;
+
+        let (ws_stream, _) = client_async_with_config(request, stream, None)
             .await
+            .map_err(|e| {
+                let mut error = AsrError::from(e);
+                self.keys.report(lease.index, &error);
+                self.keys.redact_error(&mut error);
+                error
+            })
             .context("Failed to connect to WebSocket")?;
 
-        info!("Connected to DashScope WebSocket");
+        if self.keys.len() > 1 {
+            info!(
+                "Connected to DashScope WebSocket at {} with key {}",
+                url, lease.label
+            );
+        } else {
+            info!("Connected to DashScope WebSocket at {}", url);
+        }
+
+        Ok(AsrConnection {
+            stream: ws_stream,
+            opened_at: Instant::now(),
+            key: lease.index,
+        })
+    }
+
+    pub async fn start_recognition(
+        &mut self,
+        audio_rx: mpsc::Receiver<AudioChunk>,
+        event_tx: mpsc::Sender<AsrEvent>,
+    ) -> Result<()> {
+        let connection = self.connect().await?;
+        self.run(connection, audio_rx, event_tx).await
+    }
+
+    /// Run recognition on an already open connection until the audio ends
+    ///
+    /// With the `Reconnect` keepalive policy, a task the server closes while
+    /// nobody speaks is replaced by a new one as soon as speech resumes. With
+    /// rotation, a new task takes over before the current one gets too old.
+    pub async fn run(
+        &mut self,
+        connection: AsrConnection,
+        audio_rx: mpsc::Receiver<AudioChunk>,
+        event_tx: mpsc::Sender<AsrEvent>,
+    ) -> Result<()> {
+        let keepalive = self.keepalive();
+        let mut gate = AudioGate::new(audio_rx, keepalive, self.options.max_sentence_silence);
+        let timeline = self.timeline.clone().unwrap_or_else(SharedTimeline::new);
+        // Rotated-out tasks still delivering their last results
+        let mut draining = Vec::new();
+
+        let mut task = Some(
+            self.start_task(connection, &gate, &timeline, &event_tx, true)
+                .await?,
+        );
+
+        loop {
+            let Some(active) = task.as_mut() else {
+                // Wait for speech before opening (and paying for) a new task
+                let Some(chunk) = gate.next().await else {
+                    break;
+                };
+                gate.unread(chunk);
+                info!("Speech resumed, opening a new task");
+                let connection = self.connect().await?;
+                task = Some(
+                    self.start_task(connection, &gate, &timeline, &event_tx, false)
+                        .await?,
+                );
+                continue;
+            };
+
+            if self.rotation_due(active.started.elapsed(), gate.is_gated()) {
+                info!(
+                    "Rotating to a new task after {}s",
+                    active.started.elapsed().as_secs()
+                );
+                // Audio queues up in the capture channel meanwhile, and goes
+                // to the new task once it has started
+                let connection = self.connect().await?;
+                let next = self
+                    .start_task(connection, &gate, &timeline, &event_tx, false)
+                    .await?;
+                if let Some(old) = task.replace(next) {
+                    old.rotated.store(true, Ordering::SeqCst);
+                    draining.push(old.finish().await?);
+                }
+                continue;
+            }
+
+            tokio::select! {
+                audio_data = gate.next() => {
+                    let Some(audio_data) = audio_data else {
+                        break;
+                    };
+                    if let Err(e) = active.send_audio(audio_data).await {
+                        error!("Failed to send audio: {}", e);
+                        break;
+                    }
+                }
+                end = &mut active.read_handle => {
+                    task = None;
+                    match end.unwrap_or(TaskEnd::Done) {
+                        TaskEnd::Done => break,
+                        TaskEnd::Idle => {
+                            info!("Task closed while idle, reopening when speech resumes");
+                        }
+                    }
+                }
+                // Re-check rotation while silence is held back
+                _ = tokio::time::sleep(ROTATION_CHECK_INTERVAL), if self.rotate_after.is_some() => {}
+            }
+        }
+
+        if let Some(active) = task {
+            draining.push(active.finish().await?);
+        }
+
+        // Wait for the last results of every task
+        for read_handle in draining {
+            let _ = read_handle.await;
+        }
+
+        if keepalive != KeepalivePolicy::Off {
+            info!("Held back {:.1}s of silence", gate.skipped_seconds());
+        }
+        Ok(())
+    }
+
+    /// Whether the current task should be replaced, preferring a pause
+    fn rotation_due(&self, age: Duration, silent: bool) -> bool {
+        match self.rotate_after {
+            Some(rotate_after) => {
+                age >= rotate_after && (silent || age >= rotate_after + ROTATION_GRACE)
+            }
+            None => false,
+        }
+    }
+
+    /// Send run-task on `connection`, wait until the task has started and
+    /// spawn the reader that forwards its events
+    ///
+    /// Only the `first` task of a session reports `TaskStarted`; tasks that
+    /// replace it continue the same session.
+    async fn start_task(
+        &mut self,
+        connection: AsrConnection,
+        gate: &AudioGate,
+        timeline: &SharedTimeline,
+        event_tx: &mpsc::Sender<AsrEvent>,
+        first: bool,
+    ) -> Result<ActiveTask> {
+        let AsrConnection {
+            stream: ws_stream,
+            key,
+            ..
+        } = connection;
+        let keys = self.keys.clone();
 
         let (mut write, mut read) = ws_stream.split();
 
+        let recorder = self.recorder.clone();
+
         // Send run-task command
         let run_task_cmd = self.generate_run_task_cmd();
+        let task_id = run_task_cmd.header.task_id.clone();
+        crate::logging::record_task("dashscope", &self.model, &task_id);
         let run_task_json = serde_json::to_string(&run_task_cmd)?;
-        debug!("Sending run-task: {}", run_task_json);
-        write.send(Message::Text(run_task_json)).await?;
+        // Only a summary: commands are not logged in full
+        debug!("Sending run-task {} (model {})", task_id, self.model);
+        if let Some(recorder) = &recorder {
+            recorder.sent(&run_task_json);
+        }
+        write
+            .send(Message::Text(run_task_json))
+            .await
+            .map_err(AsrError::from)?;
 
         // Wait for task-started event
-        let mut task_started = false;
-        while !task_started {
-            if let Some(msg) = read.next().await {
-                match msg? {
-                    Message::Text(text) => {
-                        let event: Event = serde_json::from_str(&text)?;
-                        if let Some(event_type) = &event.header.event {
-                            if event_type == "task-started" {
-                                info!("Task started");
-                                task_started = true;
+        loop {
+            let Some(msg) = read.next().await else {
+                return Err(AsrError::Protocol(
+                    "connection closed before task started".to_string(),
+                )
+                .into());
+            };
+            match msg.map_err(AsrError::from)? {
+                Message::Text(text) => {
+                    if let Some(recorder) = &recorder {
+                        recorder.received(&text);
+                    }
+                    let event: Event = serde_json::from_str(&text).map_err(AsrError::from)?;
+                    match asr_event(&event) {
+                        Some(AsrEvent::TaskStarted) => {
+                            keys.task_started(key);
+                            if first {
                                 event_tx.send(AsrEvent::TaskStarted).await?;
-                            } else if event_type == "task-failed" {
-                                let error = event
-                                    .header
-                                    .error_message
-                                    .unwrap_or_else(|| "Unknown error".to_string());
-                                error!("Task failed: {}", error);
-                                event_tx
-                                    .send(AsrEvent::TaskFailed { error: error.clone() })
-                                    .await?;
-                                return Err(anyhow::anyhow!("Task failed: {}", error));
                             }
+                            break;
+                        }
+                        Some(AsrEvent::TaskFailed { mut error }) => {
+                            keys.report(key, &error);
+                            keys.redact_error(&mut error);
+                            return Err(error.into());
                         }
+                        _ => {}
                     }
-                    Message::Close(_) => {
-                        return Err(anyhow::anyhow!("Connection closed before task started"));
+                }
+                Message::Close(_) => {
+                    if let Some(recorder) = &recorder {
+                        recorder.closed();
                     }
-                    _ => {}
+                    return Err(AsrError::Protocol(
+                        "connection closed before task started".to_string(),
+                    )
+                    .into());
                 }
+                _ => {}
             }
         }
 
+        let meter = self.ledger.as_ref().map(|ledger| {
+            let meter = ledger.task(&task_id, "dashscope", &self.model, Some(keys.label(key)));
+            meter.suppressed_from(gate.skipped_counter());
+            Arc::new(meter)
+        });
+        let read_meter = meter.clone();
+        let mut picker = (!self.options.translate.is_empty())
+            .then(|| TranslationPicker::new(self.options.translate.clone()));
+        // Offset by the audio captured so far, held-back silence included,
+        // which is also what the server times of earlier tasks leave out
+        let task_index = timeline.begin_task(gate.position_ms());
+        let timeline = timeline.clone();
+        let clock = Arc::new(std::sync::Mutex::new(AudioClock::default()));
+        let read_clock = clock.clone();
+        let latency = self.latency.clone();
         let event_tx_clone = event_tx.clone();
-        let _task_id = self.task_id.clone();
+        let read_recorder = recorder.clone();
+        let read_keys = keys.clone();
+        // A task that ends while the gate holds back silence is an idle
+        // timeout; with `Reconnect` it is replaced instead of reported
+        let idle_reconnect = self.keepalive() == KeepalivePolicy::Reconnect;
+        let gated = gate.gated_flag();
+        let is_idle = move || idle_reconnect && gated.load(Ordering::SeqCst);
+        // A rotated-out task ends without ending the session
+        let rotated = Arc::new(AtomicBool::new(false));
+        let read_rotated = rotated.clone();
 
         // Spawn task to handle incoming messages
-        let read_handle = tokio::spawn(async move {
-            while let Some(msg) = read.next().await {
-                match msg {
-                    Ok(Message::Text(text)) => {
-                        if let Ok(event) = serde_json::from_str::<Event>(&text) {
-                            if let Some(event_type) = &event.header.event {
-                                match event_type.as_str() {
-                                    "result-generated" => {
-                                        if let Some(output) = &event.payload.output {
-                                            // Prefer translation over transcription
-                                            if !output.translations.is_empty() {
-                                                let translation = &output.translations[0];
-                                                let is_final = translation.sentence_end;
-                                                let text = translation.text.clone();
-                                                debug!(
-                                                    "Translation: {} (final: {})",
-                                                    text, is_final
-                                                );
-                                                let _ = event_tx_clone
-                                                    .send(AsrEvent::ResultGenerated { text, is_final })
-                                                    .await;
-                                            } else if let Some(transcription) = &output.transcription {
-                                                let is_final = transcription.sentence_end;
-                                                let text = transcription.text.clone();
-                                                debug!(
-                                                    "Transcription: {} (final: {})",
-                                                    text, is_final
-                                                );
-                                                let _ = event_tx_clone
-                                                    .send(AsrEvent::ResultGenerated { text, is_final })
-                                                    .await;
-                                            }
-                                        }
+        let read_handle = tokio::spawn(
+            async move {
+                while let Some(msg) = read.next().await {
+                    match msg {
+                        Ok(Message::Text(text)) => {
+                            let arrived = Instant::now();
+                            if let Some(recorder) = &read_recorder {
+                                recorder.received(&text);
+                            }
+                            let Some(mut event) = serde_json::from_str::<Event>(&text)
+                                .ok()
+                                .as_ref()
+                                .and_then(asr_event)
+                            else {
+                                continue;
+                            };
+                            match &mut event {
+                                AsrEvent::ResultGenerated(result) => {
+                                    if let Some(picker) = &mut picker {
+                                        picker.pick(result);
                                     }
-                                    "task-finished" => {
-                                        info!("Task finished");
-                                        let _ = event_tx_clone.send(AsrEvent::TaskFinished).await;
-                                        break;
+                                    if let Some(meter) = &read_meter {
+                                        meter.result(result);
                                     }
-                                    "task-failed" => {
-                                        let error = event
-                                            .header
-                                            .error_message
-                                            .unwrap_or_else(|| "Unknown error".to_string());
-                                        error!("Task failed: {}", error);
-                                        let _ = event_tx_clone
-                                            .send(AsrEvent::TaskFailed { error })
-                                            .await;
-                                        break;
+                                    // Server times are still relative to this task here
+                                    let spoken_at =
+                                        read_clock.lock().unwrap().spoken_at(heard_until(result));
+                                    timeline.stitch(task_index, result);
+                                    if let (Some(latency), Some(spoken_at)) = (&latency, spoken_at)
+                                    {
+                                        latency.result(
+                                            result.sentence_id,
+                                            result.is_final,
+                                            spoken_at,
+                                            arrived,
+                                        );
                                     }
-                                    _ => {
-                                        warn!("Unknown event: {}", event_type);
+                                }
+                                AsrEvent::TaskFailed { error } if is_idle() => {
+                                    debug!("Idle task ended: {}", error);
+                                    return TaskEnd::Idle;
+                                }
+                                AsrEvent::TaskFinished if read_rotated.load(Ordering::SeqCst) => {
+                                    debug!("Rotated-out task finished");
+                                    break;
+                                }
+                                AsrEvent::TaskFailed { error } => {
+                                    read_keys.report(key, error);
+                                    read_keys.redact_error(error);
+                                    if read_rotated.load(Ordering::SeqCst) {
+                                        warn!("Rotated-out task failed: {}", error);
+                                        break;
                                     }
                                 }
+                                _ => {}
+                            }
+                            let done = matches!(
+                                event,
+                                AsrEvent::TaskFinished | AsrEvent::TaskFailed { .. }
+                            );
+                            let _ = event_tx_clone.send(event).await;
+                            if done {
+                                break;
                             }
                         }
+                        Ok(Message::Close(_)) => {
+                            if let Some(recorder) = &read_recorder {
+                                recorder.closed();
+                            }
+                            if is_idle() {
+                                debug!("Idle task closed by server");
+                                return TaskEnd::Idle;
+                            }
+                            if read_rotated.load(Ordering::SeqCst) {
+                                warn!("Rotated-out task closed before it finished");
+                                break;
+                            }
+                            // A normal session ends with task-finished before the close
+                            // frame, so a bare close means the server dropped the task
+                            warn!("WebSocket closed before task finished");
+                            let error =
+                                AsrError::Network("connection closed by server".to_string());
+                            let _ = event_tx_clone.send(AsrEvent::TaskFailed { error }).await;
+                            break;
+                        }
+                        Err(e) => {
+                            if is_idle() {
+                                debug!("Idle task lost: {}", e);
+                                return TaskEnd::Idle;
+                            }
+                            if read_rotated.load(Ordering::SeqCst) {
+                                warn!("Rotated-out task lost: {}", e);
+                                break;
+                            }
+                            let error = AsrError::from(e);
+                            error!("WebSocket error: {}", error);
+                            let _ = event_tx_clone.send(AsrEvent::TaskFailed { error }).await;
+                            break;
+                        }
+                        _ => {}
                     }
-                    Ok(Message::Close(_)) => {
-                        info!("WebSocket closed");
-                        break;
-                    }
-                    Err(e) => {
-                        error!("WebSocket error: {}", e);
-                        break;
-                    }
-                    _ => {}
                 }
+                TaskEnd::Done
             }
-        });
+            .in_current_span(),
+        );
 
-        // Send audio data
-        while let Some(audio_data) = audio_rx.recv().await {
-            if let Err(e) = write.send(Message::Binary(audio_data)).await {
-                error!("Failed to send audio: {}", e);
-                break;
-            }
-        }
+        Ok(ActiveTask {
+            task_id,
+            write,
+            read_handle,
+            recorder,
+            clock,
+            keys,
+            key,
+            meter,
+            rotated,
+            started: Instant::now(),
+        })
+    }
+}
 
-        // Send finish-task command
-        if let Some(finish_cmd) = self.generate_finish_task_cmd() {
-            let finish_json = serde_json::to_string(&finish_cmd)?;
-            debug!("Sending finish-task: {}", finish_json);
-            write.send(Message::Text(finish_json)).await?;
-        }
+type WsWrite = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
 
-        // Wait for read task to complete
-        let _ = read_handle.await;
+/// A started task: audio goes out through `write`, events come in through
+/// the spawned reader
+struct ActiveTask {
+    task_id: String,
+    write: WsWrite,
+    read_handle: JoinHandle<TaskEnd>,
+    recorder: Option<Arc<CassetteRecorder>>,
+    /// Capture time of the audio sent, for latency measurement
+    clock: Arc<std::sync::Mutex<AudioClock>>,
+    keys: Arc<KeyPool>,
+    key: usize,
+    /// Usage of the task, written to the ledger once the task and its reader are gone
+    meter: Option<Arc<TaskMeter>>,
+    /// Set once a newer task has taken over; its end is then not reported
+    rotated: Arc<AtomicBool>,
+    started: Instant,
+}
 
+impl ActiveTask {
+    async fn send_audio(&mut self, chunk: AudioChunk) -> Result<(), AsrError> {
+        if let Some(recorder) = &self.recorder {
+            recorder.audio(chunk.data.len());
+        }
+        self.clock
+            .lock()
+            .unwrap()
+            .sent(chunk.data.len(), chunk.captured_at);
+        self.keys.audio_sent(self.key, chunk.data.len());
+        if let Some(meter) = &self.meter {
+            meter.audio_sent(chunk.data.len());
+        }
+        self.write.send(Message::Binary(chunk.data)).await?;
         Ok(())
     }
+
+    /// Send finish-task; the returned reader ends once the last results are in
+    async fn finish(mut self) -> Result<JoinHandle<TaskEnd>> {
+        if !self.read_handle.is_finished() {
+            let finish_cmd = AsrClient::generate_finish_task_cmd(&self.task_id);
+            let finish_json = serde_json::to_string(&finish_cmd)?;
+            debug!("Sending finish-task {}", self.task_id);
+            if let Some(recorder) = &self.recorder {
+                recorder.sent(&finish_json);
+            }
+            self.write
+                .send(Message::Text(finish_json))
+                .await
+                .map_err(AsrError::from)?;
+        }
+        Ok(self.read_handle)
+    }
+}
+
+/// How a single task ended
+enum TaskEnd {
+    /// Audio ended, or the task finished or failed (already reported)
+    Done,
+    /// The server closed the task during silence; open a new one on speech
+    Idle,
 }
@@ -0,0 +1,652 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_tungstenite::{
+    client_async_with_config, tungstenite::Message, MaybeTlsStream, WebSocketStream,
+};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use super::latency::AudioClock;
+use super::{
+    AsrError, AsrEvent, ConnectionConfig, LatencyTracker, RecognitionOptions, RecognitionResult,
+    SentenceText, SharedTimeline, Shown,
+};
+use crate::audio::AudioChunk;
+use crate::usage::Ledger;
+
+/// OpenAI's endpoint for transcription-only realtime sessions
+const DEFAULT_URL: &str = "wss://api.openai.com/v1/realtime?intent=transcription";
+
+/// Transcription model used when none is configured
+pub const DEFAULT_OPENAI_MODEL: &str = "gpt-4o-transcribe";
+
+/// Capture is 16kHz mono PCM16, resampled before it is sent
+const INPUT_RATE: u32 = 16000;
+/// The protocol expects 24kHz mono PCM16
+const OUTPUT_RATE: u32 = 24000;
+
+/// Silence that ends a turn when the profile does not set one
+const DEFAULT_SILENCE_MS: u32 = 500;
+
+/// How long to wait for the last transcriptions after the audio ended
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Client for servers speaking the OpenAI realtime transcription protocol
+///
+/// Each committed audio item becomes one sentence: transcription deltas are
+/// reported as partial results and the completed transcript as the final one.
+#[derive(Clone)]
+pub struct RealtimeClient {
+    token: Option<String>,
+    connection: ConnectionConfig,
+    model: String,
+    silence_ms: u32,
+    latency: Option<Arc<LatencyTracker>>,
+    ledger: Option<Arc<Ledger>>,
+    timeline: Option<SharedTimeline>,
+}
+
+impl RealtimeClient {
+    pub fn new(token: Option<String>, connection: ConnectionConfig) -> Self {
+        Self {
+            token,
+            connection,
+            model: DEFAULT_OPENAI_MODEL.to_string(),
+            silence_ms: DEFAULT_SILENCE_MS,
+            latency: None,
+            ledger: None,
+            timeline: None,
+        }
+    }
+
+    /// Read the endpoint and token from the environment
+    ///
+    /// - `OPENAI_REALTIME_URL`: endpoint [default: OpenAI's transcription endpoint]
+    /// - `OPENAI_API_KEY`: bearer token, optional for self-hosted servers
+    pub fn from_env() -> Result<Self> {
+        let url = std::env::var("OPENAI_REALTIME_URL")
+            .ok()
+            .filter(|url| !url.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_URL.to_string());
+        let token = std::env::var("OPENAI_API_KEY")
+            .ok()
+            .filter(|token| !token.trim().is_empty());
+        if let Some(token) = &token {
+            crate::logging::add_secret(token);
+        }
+        Ok(Self::new(token, ConnectionConfig::for_endpoint(&url)?))
+    }
+
+    pub fn endpoint(&self) -> &url::Url {
+        &self.connection.endpoint
+    }
+
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = model.into();
+        self
+    }
+
+    /// Apply the recognition options this protocol has an equivalent for
+    ///
+    /// Only `max_sentence_silence` maps (to the server VAD silence duration);
+    /// anything else is rejected.
+    pub fn with_options(mut self, options: &RecognitionOptions) -> Result<Self, AsrError> {
+        let supported = RecognitionOptions {
+            max_sentence_silence: options.max_sentence_silence,
+            ..RecognitionOptions::default()
+        };
+        if *options != supported {
+            return Err(AsrError::InvalidParameter {
+                code: "InvalidParameter".to_string(),
+                message: "only max_sentence_silence is supported by the openai backend".to_string(),
+            });
+        }
+        self.silence_ms = options.max_sentence_silence.unwrap_or(DEFAULT_SILENCE_MS);
+        Ok(self)
+    }
+
+    /// Measure how long results take after the audio was captured
+    pub fn with_latency(mut self, latency: Arc<LatencyTracker>) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Record the usage of every session in `ledger`
+    pub fn with_ledger(mut self, ledger: Arc<Ledger>) -> Self {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    /// Stitch results onto `timeline`, numbered and timed as part of a longer session
+    pub fn with_timeline(mut self, timeline: SharedTimeline) -> Self {
+        self.timeline = Some(timeline);
+        self
+    }
+
+    fn session_update(&self) -> Value {
+        json!({
+            "type": "transcription_session.update",
+            "session": {
+                "input_audio_format": "pcm16",
+                "input_audio_transcription": {"model": self.model},
+                "turn_detection": {
+                    "type": "server_vad",
+                    "silence_duration_ms": self.silence_ms,
+                },
+            },
+        })
+    }
+
+    /// Open an authenticated WebSocket connection to the endpoint
+    async fn connect(&self) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+        let url = &self.connection.endpoint;
+        let host = url.host_str().unwrap_or_default();
+        let host_header = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        let mut request = http::Request::builder()
+            .uri(url.as_str())
+            .header("Host", host_header)
+            .header("OpenAI-Beta", "realtime=v1")
+            .header("Connection", "Upgrade")
+            .header("Upgrade", "websocket")
+            .header("Sec-WebSocket-Version", "13")
+            .header(
+                "Sec-WebSocket-Key",
+                tokio_tungstenite::tungstenite::handshake::client::generate_key(),
+            );
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+
+        let stream = self
+            .connection
+            .open_stream()
+            .await
+            .context("Failed to connect to realtime endpoint")?;
+        let stream = self.connection.tls_stream(stream).await?;
+        let (ws_stream, _) = client_async_with_config(request.body(())?, stream, None)
+            .await
+            .map_err(AsrError::from)
+            .context("Failed to connect to realtime endpoint")?;
+        info!("Connected to realtime endpoint at {}", url);
+        Ok(ws_stream)
+    }
+
+    /// Check the endpoint accepts our connection
+    pub async fn probe(&self) -> Result<()> {
+        let mut ws_stream = self.connect().await?;
+        let _ = ws_stream.close(None).await;
+        Ok(())
+    }
+
+    /// Stream audio until the channel closes, then commit the rest and wait
+    /// for its transcription
+    pub async fn start_recognition(
+        &mut self,
+        mut audio_rx: mpsc::Receiver<AudioChunk>,
+        event_tx: mpsc::Sender<AsrEvent>,
+    ) -> Result<()> {
+        let (mut write, mut read) = self.connect().await?.split();
+        write
+            .send(Message::Text(self.session_update().to_string()))
+            .await
+            .map_err(AsrError::from)?;
+
+        // The session is ready once our configuration is confirmed
+        loop {
+            let Some(message) = read.next().await else {
+                return Err(AsrError::Protocol(
+                    "connection closed before the session was configured".to_string(),
+                )
+                .into());
+            };
+            let Message::Text(text) = message.map_err(AsrError::from)? else {
+                continue;
+            };
+            let event: Value = serde_json::from_str(&text).map_err(AsrError::from)?;
+            match event["type"].as_str() {
+                Some("transcription_session.updated") => break,
+                Some("error") => return Err(error_of(&event).into()),
+                _ => {}
+            }
+        }
+        event_tx.send(AsrEvent::TaskStarted).await?;
+        let task_id = Uuid::new_v4().to_string();
+        crate::logging::record_task("openai", &self.model, &task_id);
+        let meter = self
+            .ledger
+            .as_ref()
+            .map(|ledger| ledger.task(&task_id, "openai", &self.model, None));
+
+        let mut session = Session::new(self.latency.clone());
+        session.timeline = self
+            .timeline
+            .as_ref()
+            .map(|timeline| (timeline.clone(), timeline.begin_task(0)));
+        let mut upsampler = Upsampler::default();
+        let mut audio_open = true;
+        let mut deadline = tokio::time::Instant::now();
+
+        loop {
+            tokio::select! {
+                chunk = audio_rx.recv(), if audio_open => match chunk {
+                    Some(chunk) => {
+                        session.clock.sent(chunk.data.len(), chunk.captured_at);
+                        if let Some(meter) = &meter {
+                            meter.audio_sent(chunk.data.len());
+                        }
+                        let append = json!({
+                            "type": "input_audio_buffer.append",
+                            "audio": base64::engine::general_purpose::STANDARD
+                                .encode(upsampler.process(&chunk.data)),
+                        });
+                        write.send(Message::Text(append.to_string())).await.map_err(AsrError::from)?;
+                    }
+                    None => {
+                        // Transcribe whatever the server VAD has not committed yet
+                        debug!("Audio ended, committing the input buffer");
+                        let commit = json!({"type": "input_audio_buffer.commit"});
+                        write.send(Message::Text(commit.to_string())).await.map_err(AsrError::from)?;
+                        audio_open = false;
+                        session.awaiting_commit = true;
+                        deadline = tokio::time::Instant::now() + FLUSH_TIMEOUT;
+                    }
+                },
+                message = read.next() => {
+                    let Some(message) = message else {
+                        let error = AsrError::Network("connection closed by server".to_string());
+                        event_tx.send(AsrEvent::TaskFailed { error }).await?;
+                        return Ok(());
+                    };
+                    let Message::Text(text) = message.map_err(AsrError::from)? else {
+                        continue;
+                    };
+                    let event: Value = serde_json::from_str(&text).map_err(AsrError::from)?;
+                    for asr_event in session.handle(&event) {
+                        if let (Some(meter), AsrEvent::ResultGenerated(result)) = (&meter, &asr_event) {
+                            meter.result(result);
+                        }
+                        let failed = matches!(asr_event, AsrEvent::TaskFailed { .. });
+                        event_tx.send(asr_event).await?;
+                        if failed {
+                            return Ok(());
+                        }
+                    }
+                }
+                _ = tokio::time::sleep_until(deadline), if !audio_open => {
+                    warn!("Timed out waiting for the last transcriptions");
+                    break;
+                }
+            }
+
+            if !audio_open && session.is_flushed() {
+                break;
+            }
+        }
+
+        event_tx.send(AsrEvent::TaskFinished).await?;
+        let _ = write.close().await;
+        Ok(())
+    }
+}
+
+/// Transcription state of one connection
+struct Session {
+    items: HashMap<String, Item>,
+    next_sentence_id: i32,
+    /// Our final commit has not been acknowledged yet
+    awaiting_commit: bool,
+    clock: AudioClock,
+    latency: Option<Arc<LatencyTracker>>,
+    /// Session timeline and this connection's task on it
+    timeline: Option<(SharedTimeline, usize)>,
+}
+
+/// One committed audio item, reported as one sentence
+#[derive(Default)]
+struct Item {
+    sentence_id: i32,
+    begin_time: i64,
+    end_time: i64,
+    text: String,
+    completed: bool,
+}
+
+impl Session {
+    fn new(latency: Option<Arc<LatencyTracker>>) -> Self {
+        Self {
+            items: HashMap::new(),
+            next_sentence_id: 0,
+            awaiting_commit: false,
+            clock: AudioClock::default(),
+            latency,
+            timeline: None,
+        }
+    }
+
+    /// Every committed item has been transcribed
+    fn is_flushed(&self) -> bool {
+        !self.awaiting_commit && self.items.values().all(|item| item.completed)
+    }
+
+    fn item(&mut self, event: &Value) -> &mut Item {
+        let item_id = event["item_id"].as_str().unwrap_or_default().to_string();
+        let next = &mut self.next_sentence_id;
+        self.items.entry(item_id).or_insert_with(|| {
+            *next += 1;
+            Item {
+                sentence_id: *next - 1,
+                ..Item::default()
+            }
+        })
+    }
+
+    /// Translate one server event
+    fn handle(&mut self, event: &Value) -> Vec<AsrEvent> {
+        let event_type = event["type"].as_str().unwrap_or_default();
+        match event_type {
+            "input_audio_buffer.speech_started" => {
+                let item = self.item(event);
+                item.begin_time = event["audio_start_ms"].as_i64().unwrap_or_default();
+                Vec::new()
+            }
+            "input_audio_buffer.speech_stopped" => {
+                let item = self.item(event);
+                item.end_time = event["audio_end_ms"].as_i64().unwrap_or_default();
+                Vec::new()
+            }
+            "input_audio_buffer.committed" => {
+                self.awaiting_commit = false;
+                self.item(event);
+                Vec::new()
+            }
+            "conversation.item.input_audio_transcription.delta" => {
+                let delta = event["delta"].as_str().unwrap_or_default();
+                let item = self.item(event);
+                item.text.push_str(delta);
+                self.result(event, false)
+            }
+            "conversation.item.input_audio_transcription.completed" => {
+                let transcript = event["transcript"].as_str().unwrap_or_default();
+                let item = self.item(event);
+                item.text = transcript.to_string();
+                item.completed = true;
+                self.result(event, true)
+            }
+            "conversation.item.input_audio_transcription.failed" => {
+                warn!("Transcription failed: {}", error_of(event));
+                self.item(event).completed = true;
+                Vec::new()
+            }
+            "error" if event["error"]["code"] == "input_audio_buffer_commit_empty" => {
+                // The server VAD already committed everything
+                self.awaiting_commit = false;
+                Vec::new()
+            }
+            "error" => vec![AsrEvent::TaskFailed {
+                error: error_of(event),
+            }],
+            _ => {
+                debug!("Ignoring realtime event {}", event_type);
+                Vec::new()
+            }
+        }
+    }
+
+    fn result(&mut self, event: &Value, is_final: bool) -> Vec<AsrEvent> {
+        let arrived = Instant::now();
+        let item = self.item(event);
+        let (sentence_id, begin_time, end_time) =
+            (item.sentence_id, item.begin_time, item.end_time);
+        let text = item.text.clone();
+
+        let mut result = RecognitionResult {
+            sentence_id,
+            begin_time,
+            end_time,
+            transcription: Some(SentenceText {
+                text,
+                lang: None,
+                begin_time,
+                end_time,
+                words: Vec::new(),
+                sentence_end: is_final,
+            }),
+            translations: Default::default(),
+            is_final,
+            pre_end_failed: false,
+            shown: Shown::default(),
+        };
+        if let Some((timeline, task)) = &self.timeline {
+            timeline.stitch(*task, &mut result);
+        }
+
+        // Speech end is only known once the server VAD stopped the turn
+        if let Some(latency) = &self.latency {
+            let heard = if end_time > 0 { end_time } else { i64::MAX };
+            if let Some(spoken_at) = self.clock.spoken_at(heard) {
+                latency.result(result.sentence_id, is_final, spoken_at, arrived);
+            }
+        }
+
+        vec![AsrEvent::ResultGenerated(result)]
+    }
+}
+
+fn error_of(event: &Value) -> AsrError {
+    let error = &event["error"];
+    AsrError::from_openai(
+        error["type"].as_str(),
+        error["code"].as_str(),
+        error["message"].as_str(),
+    )
+}
+
+/// Linear 16kHz to 24kHz resampling of 16-bit little-endian PCM
+///
+/// Keeps the last sample of each chunk so consecutive chunks join smoothly.
+#[derive(Default)]
+struct Upsampler {
+    previous: i16,
+    /// Position of the next output sample after `previous`, in units of
+    /// 1/OUTPUT_RATE input samples so it stays exact
+    position: u64,
+}
+
+impl Upsampler {
+    fn process(&mut self, pcm: &[u8]) -> Vec<u8> {
+        let input: Vec<i16> = pcm
+            .chunks_exact(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect();
+        let unit = OUTPUT_RATE as u64;
+        let end = input.len() as u64 * unit;
+        let sample = |index: usize| {
+            if index == 0 {
+                self.previous
+            } else {
+                input[index - 1]
+            }
+        };
+
+        let mut output = Vec::with_capacity(input.len() * 3 / 2 * 2);
+        let mut position = self.position;
+        while position < end {
+            let index = (position / unit) as usize;
+            let frac = (position % unit) as f64 / unit as f64;
+            let value = sample(index) as f64 * (1.0 - frac) + sample(index + 1) as f64 * frac;
+            output.extend_from_slice(&(value.round() as i16).to_le_bytes());
+            position += INPUT_RATE as u64;
+        }
+
+        self.position = position - end;
+        if let Some(&last) = input.last() {
+            self.previous = last;
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn upsamples_to_24khz_across_chunks() {
+        let mut upsampler = Upsampler::default();
+        let ramp: Vec<u8> = (0..1600i16).flat_map(|s| (s * 3).to_le_bytes()).collect();
+
+        let first = upsampler.process(&ramp);
+        let second = upsampler.process(&ramp);
+        assert_eq!(first.len(), 2400 * 2);
+        assert_eq!(second.len(), 2400 * 2);
+
+        let samples: Vec<i16> = first
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        // One input sample behind: output 3 lies on input 1 (value 3)
+        assert_eq!(&samples[..7], &[0, 0, 1, 3, 5, 7, 9]);
+    }
+
+    /// Stand-in server: confirms the session, expects audio, then answers the
+    /// final commit with two transcribed items
+    async fn stand_in() -> (ConnectionConfig, tokio::task::JoinHandle<Vec<Value>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "ws://{}/v1/realtime?intent=transcription",
+            listener.local_addr().unwrap()
+        );
+
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let mut received = Vec::new();
+            let send = |value: Value| Message::Text(value.to_string());
+
+            while let Some(Ok(Message::Text(text))) = ws.next().await {
+                let event: Value = serde_json::from_str(&text).unwrap();
+                match event["type"].as_str().unwrap() {
+                    "transcription_session.update" => {
+                        ws.send(send(json!({"type": "transcription_session.created"})))
+                            .await
+                            .unwrap();
+                        ws.send(send(json!({"type": "transcription_session.updated"})))
+                            .await
+                            .unwrap();
+                    }
+                    "input_audio_buffer.commit" => {
+                        for event in [
+                            json!({"type": "input_audio_buffer.speech_started", "item_id": "a", "audio_start_ms": 100}),
+                            json!({"type": "input_audio_buffer.speech_stopped", "item_id": "a", "audio_end_ms": 900}),
+                            json!({"type": "input_audio_buffer.committed", "item_id": "a"}),
+                            json!({"type": "conversation.item.input_audio_transcription.delta", "item_id": "a", "delta": "Hello"}),
+                            json!({"type": "conversation.item.input_audio_transcription.delta", "item_id": "a", "delta": " wor"}),
+                            json!({"type": "input_audio_buffer.committed", "item_id": "b"}),
+                            json!({"type": "conversation.item.input_audio_transcription.completed", "item_id": "a", "transcript": "Hello world."}),
+                            json!({"type": "conversation.item.input_audio_transcription.completed", "item_id": "b", "transcript": "Bye."}),
+                        ] {
+                            ws.send(send(event)).await.unwrap();
+                        }
+                    }
+                    _ => {}
+                }
+                received.push(event);
+            }
+            received
+        });
+
+        (ConnectionConfig::for_endpoint(&url).unwrap(), handle)
+    }
+
+    #[tokio::test]
+    async fn maps_transcription_events() {
+        let (connection, server) = stand_in().await;
+        let options = RecognitionOptions {
+            max_sentence_silence: Some(700),
+            ..RecognitionOptions::default()
+        };
+        let mut client = RealtimeClient::new(Some("token".into()), connection)
+            .with_model("whisper-large-v3")
+            .with_options(&options)
+            .unwrap();
+
+        let (audio_tx, audio_rx) = mpsc::channel(10);
+        let (event_tx, mut event_rx) = mpsc::channel(100);
+        audio_tx.send(AudioChunk::new(vec![0; 3200])).await.unwrap();
+        drop(audio_tx);
+        client.start_recognition(audio_rx, event_tx).await.unwrap();
+
+        let mut results = Vec::new();
+        while let Some(event) = event_rx.recv().await {
+            match event {
+                AsrEvent::ResultGenerated(result) => results.push((
+                    result.sentence_id,
+                    result.transcription.unwrap().text,
+                    result.is_final,
+                )),
+                AsrEvent::TaskFinished => break,
+                AsrEvent::TaskFailed { error } => panic!("task failed: {}", error),
+                AsrEvent::TaskStarted => {}
+            }
+        }
+        assert_eq!(
+            results,
+            vec![
+                (0, "Hello".to_string(), false),
+                (0, "Hello wor".to_string(), false),
+                (0, "Hello world.".to_string(), true),
+                (1, "Bye.".to_string(), true),
+            ]
+        );
+
+        let received = server.await.unwrap();
+        assert_eq!(
+            received[0]["session"]["input_audio_transcription"]["model"],
+            "whisper-large-v3"
+        );
+        assert_eq!(
+            received[0]["session"]["turn_detection"]["silence_duration_ms"],
+            700
+        );
+        // 100ms at 16kHz becomes 100ms at 24kHz
+        let audio = base64::engine::general_purpose::STANDARD
+            .decode(received[1]["audio"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(audio.len(), 4800);
+        assert_eq!(received[2]["type"], "input_audio_buffer.commit");
+    }
+
+    #[test]
+    fn maps_error_events() {
+        let mut session = Session::new(None);
+        let events = session.handle(&json!({
+            "type": "error",
+            "error": {"type": "invalid_request_error", "code": "invalid_api_key", "message": "bad key"}
+        }));
+        assert!(matches!(
+            events.as_slice(),
+            [AsrEvent::TaskFailed {
+                error: AsrError::Auth { .. }
+            }]
+        ));
+
+        session.awaiting_commit = true;
+        let events = session.handle(&json!({
+            "type": "error",
+            "error": {"type": "invalid_request_error", "code": "input_audio_buffer_commit_empty"}
+        }));
+        assert!(events.is_empty());
+        assert!(session.is_flushed());
+    }
+}
@@ -0,0 +1,301 @@
+use super::{AsrError, Parameters};
+
+/// Model used when none is configured
+pub const DEFAULT_MODEL: &str = "gummy-realtime-v1";
+
+/// Allowed range for `max_sentence_silence` in milliseconds
+const SENTENCE_SILENCE_RANGE: std::ops::RangeInclusive<u32> = 200..=6000;
+
+/// Which optional run-task parameters a realtime model accepts
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModelCapabilities {
+    pub translation: bool,
+    pub diarization: bool,
+    pub max_sentence_silence: bool,
+    pub semantic_punctuation: bool,
+    pub disfluency_removal: bool,
+    pub inverse_text_normalization: bool,
+    pub heartbeat: bool,
+}
+
+impl ModelCapabilities {
+    /// Capabilities of a known model, or `None` if the model is not known
+    pub fn of(model: &str) -> Option<Self> {
+        let caps = match model {
+            "gummy-realtime-v1" => Self {
+                translation: true,
+                max_sentence_silence: true,
+                ..Self::default()
+            },
+            "paraformer-realtime-v2" | "paraformer-realtime-8k-v2" => Self {
+                diarization: true,
+                max_sentence_silence: true,
+                semantic_punctuation: true,
+                disfluency_removal: true,
+                inverse_text_normalization: true,
+                heartbeat: true,
+                ..Self::default()
+            },
+            "paraformer-realtime-v1" | "paraformer-realtime-8k-v1" => Self {
+                max_sentence_silence: true,
+                disfluency_removal: true,
+                inverse_text_normalization: true,
+                heartbeat: true,
+                ..Self::default()
+            },
+            _ => return None,
+        };
+        Some(caps)
+    }
+}
+
+/// Whether `model` accepts the translation parameters
+pub(super) fn supports_translation(model: &str) -> bool {
+    ModelCapabilities::of(model).is_some_and(|caps| caps.translation)
+}
+
+/// Optional recognition parameters sent with run-task
+///
+/// Unset options are left to the server default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecognitionOptions {
+    /// Label words with the speaker who said them
+    pub diarization: bool,
+    /// Silence in milliseconds that ends a sentence (VAD segmentation)
+    pub max_sentence_silence: Option<u32>,
+    /// Split sentences by meaning instead of by silence
+    pub semantic_punctuation: Option<bool>,
+    /// Drop filler words such as "um" and "uh"
+    pub disfluency_removal: Option<bool>,
+    /// Write numbers, dates and amounts as digits
+    pub inverse_text_normalization: Option<bool>,
+    /// Keep the task alive through long silence instead of timing out
+    pub heartbeat: Option<bool>,
+    /// Translation target languages; each sentence is shown in the first one
+    /// that is not spoken (see `TranslationPicker`), and every one can be
+    /// routed to an output. Empty leaves the default of English.
+    pub translate: Vec<String>,
+}
+
+impl RecognitionOptions {
+    /// Check the options against what `model` accepts
+    ///
+    /// Unknown models are not checked, so new models work before this table
+    /// knows about them.
+    pub fn validate(&self, model: &str) -> Result<(), AsrError> {
+        if let Some(silence) = self.max_sentence_silence {
+            if !SENTENCE_SILENCE_RANGE.contains(&silence) {
+                return Err(invalid(format!(
+                    "max_sentence_silence must be between {} and {} ms, got {}",
+                    SENTENCE_SILENCE_RANGE.start(),
+                    SENTENCE_SILENCE_RANGE.end(),
+                    silence
+                )));
+            }
+            if self.semantic_punctuation == Some(true) {
+                return Err(invalid(
+                    "max_sentence_silence only applies to VAD segmentation and cannot be combined with semantic_punctuation"
+                        .to_string(),
+                ));
+            }
+        }
+
+        if let Some(lang) = self
+            .translate
+            .iter()
+            .enumerate()
+            .find_map(|(i, lang)| self.translate[..i].contains(lang).then_some(lang))
+        {
+            return Err(invalid(format!(
+                "translation language {} is listed twice",
+                lang
+            )));
+        }
+
+        let Some(caps) = ModelCapabilities::of(model) else {
+            return Ok(());
+        };
+
+        let requested = [
+            ("diarization", self.diarization, caps.diarization),
+            (
+                "max_sentence_silence",
+                self.max_sentence_silence.is_some(),
+                caps.max_sentence_silence,
+            ),
+            (
+                "semantic_punctuation",
+                self.semantic_punctuation.is_some(),
+                caps.semantic_punctuation,
+            ),
+            (
+                "disfluency_removal",
+                self.disfluency_removal.is_some(),
+                caps.disfluency_removal,
+            ),
+            (
+                "inverse_text_normalization",
+                self.inverse_text_normalization.is_some(),
+                caps.inverse_text_normalization,
+            ),
+            ("heartbeat", self.heartbeat.is_some(), caps.heartbeat),
+            ("translate", !self.translate.is_empty(), caps.translation),
+        ];
+        let unsupported: Vec<&str> = requested
+            .iter()
+            .filter(|(_, set, supported)| *set && !*supported)
+            .map(|(name, _, _)| *name)
+            .collect();
+
+        if unsupported.is_empty() {
+            Ok(())
+        } else {
+            Err(invalid(format!(
+                "{} not supported by model {}",
+                unsupported.join(", "),
+                model
+            )))
+        }
+    }
+
+    /// Copy the options into run-task parameters
+    pub(super) fn apply(&self, parameters: &mut Parameters) {
+        parameters.diarization_enabled = self.diarization.then_some(true);
+        parameters.max_sentence_silence = self.max_sentence_silence;
+        parameters.semantic_punctuation_enabled = self.semantic_punctuation;
+        parameters.disfluency_removal_enabled = self.disfluency_removal;
+        parameters.inverse_text_normalization_enabled = self.inverse_text_normalization;
+        parameters.heartbeat = self.heartbeat;
+        if !self.translate.is_empty() {
+            parameters.transcription_enabled = Some(true);
+            parameters.translation_enabled = Some(true);
+            parameters.translation_target_languages = Some(self.translate.clone());
+        }
+    }
+}
+
+fn invalid(message: String) -> AsrError {
+    AsrError::InvalidParameter {
+        code: "InvalidParameter".to_string(),
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::AsrClient;
+    use serde_json::json;
+
+    fn run_task_parameters(model: &str, options: RecognitionOptions) -> serde_json::Value {
+        let mut client = AsrClient::new(String::new())
+            .with_model(model)
+            .with_options(options);
+        let event = client.generate_run_task_cmd();
+        serde_json::to_value(&event.payload.parameters).unwrap()
+    }
+
+    #[test]
+    fn default_parameters_omit_unset_options() {
+        let parameters = run_task_parameters(DEFAULT_MODEL, RecognitionOptions::default());
+        assert_eq!(
+            parameters,
+            json!({
+                "format": "pcm",
+                "sample_rate": 16000,
+                "transcription_enabled": true,
+                "translation_enabled": true,
+                "translation_target_languages": ["en"],
+            })
+        );
+    }
+
+    #[test]
+    fn options_serialize_with_dashscope_names() {
+        let options = RecognitionOptions {
+            diarization: true,
+            max_sentence_silence: None,
+            semantic_punctuation: Some(true),
+            disfluency_removal: Some(true),
+            inverse_text_normalization: Some(false),
+            heartbeat: Some(true),
+            translate: Vec::new(),
+        };
+        options.validate("paraformer-realtime-v2").unwrap();
+
+        let parameters = run_task_parameters("paraformer-realtime-v2", options);
+        assert_eq!(
+            parameters,
+            json!({
+                "format": "pcm",
+                "sample_rate": 16000,
+                "diarization_enabled": true,
+                "semantic_punctuation_enabled": true,
+                "disfluency_removal_enabled": true,
+                "inverse_text_normalization_enabled": false,
+                "heartbeat": true,
+            })
+        );
+
+        let options = RecognitionOptions {
+            max_sentence_silence: Some(1200),
+            ..RecognitionOptions::default()
+        };
+        let parameters = run_task_parameters("paraformer-realtime-v1", options);
+        assert_eq!(parameters["max_sentence_silence"], json!(1200));
+
+        let options = RecognitionOptions {
+            translate: vec!["zh".to_string(), "en".to_string()],
+            ..RecognitionOptions::default()
+        };
+        options.validate(DEFAULT_MODEL).unwrap();
+        let parameters = run_task_parameters(DEFAULT_MODEL, options);
+        assert_eq!(
+            parameters["translation_target_languages"],
+            json!(["zh", "en"])
+        );
+    }
+
+    #[test]
+    fn rejects_options_the_model_does_not_accept() {
+        let options = RecognitionOptions {
+            diarization: true,
+            heartbeat: Some(true),
+            max_sentence_silence: Some(800),
+            ..RecognitionOptions::default()
+        };
+        let error = options.validate("gummy-realtime-v1").unwrap_err();
+        assert!(matches!(error, AsrError::InvalidParameter { .. }));
+        assert!(error.to_string().contains("diarization, heartbeat"));
+
+        // Unknown models are passed through unchecked
+        options.validate("some-future-model").unwrap();
+    }
+
+    #[test]
+    fn rejects_out_of_range_or_conflicting_silence() {
+        let too_short = RecognitionOptions {
+            max_sentence_silence: Some(50),
+            ..RecognitionOptions::default()
+        };
+        assert!(too_short.validate("paraformer-realtime-v2").is_err());
+
+        let conflicting = RecognitionOptions {
+            max_sentence_silence: Some(800),
+            semantic_punctuation: Some(true),
+            ..RecognitionOptions::default()
+        };
+        assert!(conflicting.validate("paraformer-realtime-v2").is_err());
+
+        let twice = RecognitionOptions {
+            translate: vec!["en".to_string(), "ja".to_string(), "en".to_string()],
+            ..RecognitionOptions::default()
+        };
+        assert!(twice.validate(DEFAULT_MODEL).is_err());
+        let bidirectional = RecognitionOptions {
+            translate: vec!["zh".to_string(), "en".to_string()],
+            ..RecognitionOptions::default()
+        };
+        assert!(bidirectional.validate("paraformer-realtime-v2").is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+
+use super::{Output, Transcription, Translation, Word};
+
+/// Text of one sentence in one language, with word-level timing
+#[derive(Debug, Clone)]
+pub struct SentenceText {
+    pub text: String,
+    /// Language code for translations; `None` for the source transcription
+    pub lang: Option<String>,
+    /// Sentence start and end in milliseconds since the task started
+    pub begin_time: i64,
+    pub end_time: i64,
+    /// Words with timing, `fixed` (will not change any more) and `speaker_id`
+    pub words: Vec<Word>,
+    pub sentence_end: bool,
+}
+
+impl From<&Transcription> for SentenceText {
+    fn from(transcription: &Transcription) -> Self {
+        Self {
+            text: transcription.text.clone(),
+            lang: None,
+            begin_time: transcription.begin_time,
+            end_time: transcription.end_time,
+            words: transcription.words.clone(),
+            sentence_end: transcription.sentence_end,
+        }
+    }
+}
+
+impl From<&Translation> for SentenceText {
+    fn from(translation: &Translation) -> Self {
+        Self {
+            text: translation.text.clone(),
+            lang: Some(translation.lang.clone()),
+            begin_time: translation.begin_time,
+            end_time: translation.end_time,
+            words: translation.words.clone(),
+            sentence_end: translation.sentence_end,
+        }
+    }
+}
+
+impl SentenceText {
+    /// Number of leading words the server has marked as fixed
+    pub fn fixed_word_count(&self) -> usize {
+        self.words.iter().take_while(|word| word.fixed).count()
+    }
+}
+
+/// Which stream of a result is shown where no particular one is asked for
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Shown {
+    /// The translation, if there is one
+    ///
+    /// Only meant for results with a single translation: a task with
+    /// configured target languages picks one of them explicitly.
+    #[default]
+    FirstTranslation,
+    /// The translation into this language
+    Translation(String),
+    /// The source transcription
+    Transcription,
+}
+
+/// Everything a `result-generated` event says about one sentence
+///
+/// Results for the same `sentence_id` keep arriving with a refined hypothesis
+/// until one with `is_final` set.
+#[derive(Debug, Clone)]
+pub struct RecognitionResult {
+    pub sentence_id: i32,
+    /// Sentence start and end in milliseconds since the task started
+    pub begin_time: i64,
+    pub end_time: i64,
+    /// Source-language transcription, if transcription is enabled
+    pub transcription: Option<SentenceText>,
+    /// Every translation, keyed by target language
+    pub translations: BTreeMap<String, SentenceText>,
+    /// The sentence has ended and will not be updated again
+    pub is_final: bool,
+    /// The server could not translate the sentence before it ended
+    pub pre_end_failed: bool,
+    /// What `preferred()` returns
+    pub shown: Shown,
+}
+
+impl RecognitionResult {
+    /// Build a result from the payload output, or `None` if it carries no sentence
+    pub fn from_output(output: &Output) -> Option<Self> {
+        let transcription = output.transcription.as_ref().map(SentenceText::from);
+        let translations: BTreeMap<String, SentenceText> = output
+            .translations
+            .iter()
+            .map(|translation| (translation.lang.clone(), SentenceText::from(translation)))
+            .collect();
+
+        // The transcription and all translations describe the same sentence
+        let (sentence_id, begin_time, end_time) =
+            match (&output.transcription, output.translations.first()) {
+                (Some(t), _) => (t.sentence_id, t.begin_time, t.end_time),
+                (None, Some(t)) => (t.sentence_id, t.begin_time, t.end_time),
+                (None, None) => return None,
+            };
+
+        let is_final = transcription.iter().any(|t| t.sentence_end)
+            || translations.values().any(|t| t.sentence_end);
+        let pre_end_failed = output.translations.iter().any(|t| t.pre_end_failed);
+
+        Some(Self {
+            sentence_id,
+            begin_time,
+            end_time,
+            transcription,
+            translations,
+            is_final,
+            pre_end_failed,
+            shown: Shown::default(),
+        })
+    }
+
+    /// The stream chosen by `shown`, falling back to the other kind of
+    /// stream when the result lacks it
+    pub fn preferred(&self) -> Option<&SentenceText> {
+        let first_translation = || self.translations.values().next();
+        match &self.shown {
+            Shown::FirstTranslation => first_translation().or(self.transcription.as_ref()),
+            Shown::Translation(lang) => self.translations.get(lang).or(self.transcription.as_ref()),
+            Shown::Transcription => self.transcription.as_ref().or_else(first_translation),
+        }
+    }
+
+    /// All streams in this result: the transcription first, then the translations
+    pub fn streams(&self) -> impl Iterator<Item = &SentenceText> {
+        self.transcription.iter().chain(self.translations.values())
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::RecognitionResult;
+
+/// Maps results of consecutive tasks onto one continuous timeline
+///
+/// Every task numbers its sentences from 0 and times them from its own
+/// start. Sentences get session-wide ids in order of first appearance, and
+/// times are shifted by where in the captured audio the task started, so
+/// sentences of overlapping tasks do not collide.
+pub struct Timeline {
+    /// Start of each task in milliseconds since the session started
+    task_offsets: Vec<i64>,
+    sentence_ids: HashMap<(usize, i32), i32>,
+    next_sentence_id: i32,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self {
+            task_offsets: Vec::new(),
+            sentence_ids: HashMap::new(),
+            next_sentence_id: 0,
+        }
+    }
+
+    /// Register a task whose audio starts `offset_ms` into the session
+    pub fn begin_task_at(&mut self, offset_ms: i64) -> usize {
+        self.task_offsets.push(offset_ms);
+        self.task_offsets.len() - 1
+    }
+
+    /// Rewrite a result of `task` to session-wide sentence id and times
+    pub fn stitch(&mut self, task: usize, result: &mut RecognitionResult) {
+        let next = &mut self.next_sentence_id;
+        result.sentence_id = *self
+            .sentence_ids
+            .entry((task, result.sentence_id))
+            .or_insert_with(|| {
+                *next += 1;
+                *next - 1
+            });
+
+        let offset = self.task_offsets[task];
+        if offset == 0 {
+            return;
+        }
+        result.begin_time += offset;
+        result.end_time += offset;
+        for stream in result
+            .transcription
+            .iter_mut()
+            .chain(result.translations.values_mut())
+        {
+            stream.begin_time += offset;
+            stream.end_time += offset;
+            for word in &mut stream.words {
+                word.begin_time += offset;
+                word.end_time += offset;
+            }
+        }
+    }
+}
+
+/// The one `Timeline` of a session, as handed to the backend producing its
+/// results
+///
+/// A backend that takes over mid-session (failover, or another API key) gets
+/// the same timeline with `starting_at()` the offset of the audio it is fed,
+/// so every result is stitched exactly once.
+#[derive(Clone)]
+pub struct SharedTimeline {
+    timeline: Arc<Mutex<Timeline>>,
+    /// Where the backend's audio starts, in milliseconds into the session
+    offset_ms: i64,
+}
+
+impl SharedTimeline {
+    pub fn new() -> Self {
+        Self {
+            timeline: Arc::new(Mutex::new(Timeline::new())),
+            offset_ms: 0,
+        }
+    }
+
+    /// The same timeline for a backend whose audio starts `offset_ms` into
+    /// the session
+    pub fn starting_at(&self, offset_ms: i64) -> Self {
+        Self {
+            timeline: self.timeline.clone(),
+            offset_ms,
+        }
+    }
+
+    /// Register a task whose audio starts `position_ms` into the backend's audio
+    pub fn begin_task(&self, position_ms: i64) -> usize {
+        self.timeline
+            .lock()
+            .unwrap()
+            .begin_task_at(self.offset_ms + position_ms)
+    }
+
+    pub fn stitch(&self, task: usize, result: &mut RecognitionResult) {
+        self.timeline.lock().unwrap().stitch(task, result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::{Output, Transcription};
+
+    fn result(sentence_id: i32, begin_time: i64, end_time: i64) -> RecognitionResult {
+        let output = Output {
+            translations: Vec::new(),
+            transcription: Some(Transcription {
+                sentence_id,
+                begin_time,
+                end_time,
+                text: String::new(),
+                words: Vec::new(),
+                sentence_end: false,
+            }),
+        };
+        RecognitionResult::from_output(&output).unwrap()
+    }
+
+    #[test]
+    fn overlapping_tasks_get_distinct_ids_and_shifted_times() {
+        let mut timeline = Timeline::new();
+        let first = timeline.begin_task_at(0);
+
+        let mut r = result(0, 0, 900);
+        timeline.stitch(first, &mut r);
+        assert_eq!((r.sentence_id, r.begin_time), (0, 0));
+
+        let mut r = result(1, 1000, 2000);
+        timeline.stitch(first, &mut r);
+        assert_eq!(r.sentence_id, 1);
+
+        // Rotation: the second task starts 1_800_000ms in, while sentence 1
+        // of the first task is still being finalized
+        let second = timeline.begin_task_at(1_800_000);
+        let mut r = result(0, 200, 700);
+        timeline.stitch(second, &mut r);
+        assert_eq!(
+            (r.sentence_id, r.begin_time, r.end_time),
+            (2, 1_800_200, 1_800_700)
+        );
+        assert_eq!(r.transcription.as_ref().unwrap().begin_time, 1_800_200);
+
+        let mut r = result(1, 1000, 2500);
+        timeline.stitch(first, &mut r);
+        assert_eq!((r.sentence_id, r.end_time), (1, 2500));
+
+        let mut r = result(0, 200, 900);
+        timeline.stitch(second, &mut r);
+        assert_eq!(r.sentence_id, 2);
+    }
+
+    #[test]
+    fn backends_taking_over_share_ids_and_offset_their_tasks() {
+        let session = SharedTimeline::new();
+        let first = session.begin_task(0);
+        let mut r = result(0, 0, 900);
+        session.stitch(first, &mut r);
+        assert_eq!(r.sentence_id, 0);
+
+        // The next backend's audio starts with a replay from 5s in; its own
+        // rotated task starts 60s into that audio
+        let fallback = session.starting_at(5000);
+        let task = fallback.begin_task(0);
+        let mut r = result(0, 300, 900);
+        fallback.stitch(task, &mut r);
+        assert_eq!((r.sentence_id, r.begin_time), (1, 5300));
+
+        let rotated = fallback.begin_task(60_000);
+        let mut r = result(0, 300, 900);
+        fallback.stitch(rotated, &mut r);
+        assert_eq!((r.sentence_id, r.begin_time), (2, 65_300));
+    }
+}
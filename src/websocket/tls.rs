@@ -0,0 +1,452 @@
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+use tokio::net::TcpStream;
+use tokio_tungstenite::MaybeTlsStream;
+
+use super::AsrError;
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("enable the `native-tls` or `rustls` feature");
+
+/// SHA-256 of a server certificate's SubjectPublicKeyInfo, written
+/// `sha256/<base64>` like HPKP and curl's `--pinnedpubkey`
+///
+/// Pinning the key rather than the certificate keeps the pin valid when the
+/// certificate is renewed with the same key.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SpkiPin([u8; 32]);
+
+impl SpkiPin {
+    /// Pin of the key in a DER certificate
+    pub fn of_certificate(der: &[u8]) -> Result<Self> {
+        let spki = subject_public_key_info(der).context("Malformed server certificate")?;
+        Ok(Self(Sha256::digest(spki).into()))
+    }
+}
+
+impl FromStr for SpkiPin {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some(hash) = s.strip_prefix("sha256//").or(s.strip_prefix("sha256/")) else {
+            bail!("Pinned key {:?} must start with sha256/", s);
+        };
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(hash)
+            .with_context(|| format!("Pinned key {:?} is not base64", s))?;
+        let hash = bytes
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Pinned key {:?} is not a SHA-256 hash", s))?;
+        Ok(Self(hash))
+    }
+}
+
+impl fmt::Display for SpkiPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sha256/{}",
+            base64::engine::general_purpose::STANDARD.encode(self.0)
+        )
+    }
+}
+
+impl fmt::Debug for SpkiPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Run the TLS handshake with `host` on `stream`, trusting the default roots
+/// plus `ca_bundle` (PEM blocks)
+///
+/// With `pins`, the server certificate's key must match one of them. This is
+/// checked right after the handshake, before anything (such as the API key)
+/// is sent over the connection.
+pub(super) async fn connect(
+    stream: TcpStream,
+    host: &str,
+    ca_bundle: &[String],
+    pins: &[SpkiPin],
+) -> Result<MaybeTlsStream<TcpStream>> {
+    let (stream, certificate) = handshake(stream, host, ca_bundle).await?;
+    if !pins.is_empty() {
+        let pin = match certificate {
+            Some(der) => SpkiPin::of_certificate(&der)?,
+            None => bail!(AsrError::Protocol(format!(
+                "{} sent no certificate to check the pinned keys against",
+                host
+            ))),
+        };
+        if !pins.contains(&pin) {
+            bail!(AsrError::Transport(format!(
+                "the key of {} ({}) matches none of the pinned keys",
+                host, pin
+            )));
+        }
+    }
+    Ok(stream)
+}
+
+fn handshake_error(host: &str, error: impl fmt::Display) -> AsrError {
+    AsrError::Network(format!("TLS handshake with {} failed: {}", host, error))
+}
+
+/// Handshake through the system TLS library; returns the server certificate
+#[cfg(not(feature = "rustls"))]
+async fn handshake(
+    stream: TcpStream,
+    host: &str,
+    ca_bundle: &[String],
+) -> Result<(MaybeTlsStream<TcpStream>, Option<Vec<u8>>)> {
+    let mut builder = native_tls::TlsConnector::builder();
+    for block in ca_bundle {
+        let cert = native_tls::Certificate::from_pem(block.as_bytes())
+            .context("Invalid certificate in CA bundle")?;
+        builder.add_root_certificate(cert);
+    }
+    let connector = builder.build().context("Failed to build TLS connector")?;
+
+    let stream = tokio_native_tls::TlsConnector::from(connector)
+        .connect(host, stream)
+        .await
+        .map_err(|e| handshake_error(host, e))?;
+    let certificate = stream
+        .get_ref()
+        .peer_certificate()
+        .ok()
+        .flatten()
+        .and_then(|cert| cert.to_der().ok());
+    Ok((MaybeTlsStream::NativeTls(stream), certificate))
+}
+
+/// Handshake with rustls and the bundled webpki roots; returns the server
+/// certificate
+#[cfg(feature = "rustls")]
+async fn handshake(
+    stream: TcpStream,
+    host: &str,
+    ca_bundle: &[String],
+) -> Result<(MaybeTlsStream<TcpStream>, Option<Vec<u8>>)> {
+    use rustls::pki_types::{CertificateDer, ServerName};
+    use std::sync::Arc;
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    for block in ca_bundle {
+        roots
+            .add(CertificateDer::from(pem_to_der(block)?))
+            .context("Invalid certificate in CA bundle")?;
+    }
+    let config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let name = ServerName::try_from(host.to_string())
+        .map_err(|_| AsrError::Protocol(format!("invalid TLS server name {:?}", host)))?;
+    let stream = tokio_rustls::TlsConnector::from(Arc::new(config))
+        .connect(name, stream)
+        .await
+        .map_err(|e| handshake_error(host, e))?;
+    let certificate = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|chain| chain.first())
+        .map(|cert| cert.to_vec());
+    Ok((MaybeTlsStream::Rustls(stream), certificate))
+}
+
+/// DER contents of one PEM certificate block
+#[cfg(feature = "rustls")]
+fn pem_to_der(block: &str) -> Result<Vec<u8>> {
+    let body: String = block
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    base64::engine::general_purpose::STANDARD
+        .decode(body.trim())
+        .context("Invalid base64 in CA bundle")
+}
+
+/// Tag, complete encoding and contents of a DER element, and the input after it
+type Element<'a> = (u8, &'a [u8], &'a [u8], &'a [u8]);
+
+/// The DER element at the start of `input`
+fn der_element(input: &[u8]) -> Option<Element<'_>> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || rest.len() < count {
+            return None;
+        }
+        let len = rest[..count]
+            .iter()
+            .fold(0usize, |len, &byte| (len << 8) | byte as usize);
+        (len, &rest[count..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let header = input.len() - rest.len();
+    Some((tag, &input[..header + len], &rest[..len], &rest[len..]))
+}
+
+/// The encoded SubjectPublicKeyInfo of an X.509 certificate
+fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    const SEQUENCE: u8 = 0x30;
+    const VERSION: u8 = 0xa0;
+
+    let (SEQUENCE, _, certificate, _) = der_element(certificate)? else {
+        return None;
+    };
+    let (SEQUENCE, _, mut fields, _) = der_element(certificate)? else {
+        return None;
+    };
+    if fields.first() == Some(&VERSION) {
+        fields = der_element(fields)?.3;
+    }
+    // Serial number, signature algorithm, issuer, validity, subject
+    for _ in 0..5 {
+        fields = der_element(fields)?.3;
+    }
+    match der_element(fields)? {
+        (SEQUENCE, spki, _, _) => Some(spki),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Backend, BackendChain, BackendKind};
+    use crate::websocket::{AsrClient, AsrEvent, ConnectionConfig, KeyPool};
+    use futures_util::{SinkExt, StreamExt};
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio_rustls::rustls;
+    use tokio_tungstenite::tungstenite::handshake::server::{
+        Callback, ErrorResponse, Request, Response,
+    };
+    use tokio_tungstenite::tungstenite::Message;
+
+    /// A CA and a `localhost` certificate it signed
+    struct Certificates {
+        ca_pem: String,
+        der: Vec<u8>,
+        key_der: Vec<u8>,
+        spki: Vec<u8>,
+    }
+
+    fn certificates() -> Certificates {
+        let mut params = CertificateParams::new(vec![]);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "audio2text test CA");
+        let ca = Certificate::from_params(params).unwrap();
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "localhost");
+        let leaf = Certificate::from_params(params).unwrap();
+        Certificates {
+            ca_pem: ca.serialize_pem().unwrap(),
+            der: leaf.serialize_der_with_signer(&ca).unwrap(),
+            key_der: leaf.serialize_private_key_der(),
+            spki: leaf.get_key_pair().public_key_der(),
+        }
+    }
+
+    /// TLS WebSocket server for one connection; yields the Authorization
+    /// header it received, or `None` if the client never sent its request
+    async fn server(certs: &Certificates) -> (url::Url, tokio::task::JoinHandle<Option<String>>) {
+        let config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![certs.der.clone().into()],
+                rustls::pki_types::PrivatePkcs8KeyDer::from(certs.key_der.clone()).into(),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "wss://localhost:{}/api-ws/v1/inference/",
+            listener.local_addr().unwrap().port()
+        );
+
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let stream = acceptor.accept(stream).await.ok()?;
+            let mut authorization = None;
+            let mut ws =
+                tokio_tungstenite::accept_hdr_async(stream, Authorization(&mut authorization))
+                    .await
+                    .ok()?;
+            let message = ws.next().await?.ok()?;
+            ws.send(message).await.ok()?;
+            authorization
+        });
+        (url.parse().unwrap(), handle)
+    }
+
+    /// Handshake callback that keeps the Authorization header of the request
+    struct Authorization<'a>(&'a mut Option<String>);
+
+    impl Callback for Authorization<'_> {
+        fn on_request(
+            self,
+            request: &Request,
+            response: Response,
+        ) -> Result<Response, ErrorResponse> {
+            *self.0 = request
+                .headers()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            Ok(response)
+        }
+    }
+
+    fn ca_bundle(certs: &Certificates) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("tls-ca-{}.pem", uuid::Uuid::new_v4()));
+        std::fs::write(&path, &certs.ca_pem).unwrap();
+        path
+    }
+
+    async fn connect(certs: &Certificates, pins: Vec<SpkiPin>) -> (Result<()>, Option<String>) {
+        let (endpoint, server) = server(certs).await;
+        let path = ca_bundle(certs);
+        let client = AsrClient::new("sk-tls-test".to_string()).with_connection(ConnectionConfig {
+            endpoint,
+            proxy: None,
+            ca_bundle: Some(path.clone()),
+            pins,
+        });
+        let result = async {
+            let mut connection = client.connect().await?;
+            connection.stream.send(Message::Text("ping".into())).await?;
+            let echo = connection.stream.next().await.unwrap()?;
+            assert_eq!(echo, Message::Text("ping".into()));
+            Ok(())
+        }
+        .await;
+        let authorization = server.await.unwrap();
+        let _ = std::fs::remove_file(path);
+        (result, authorization)
+    }
+
+    #[test]
+    fn parses_pins() {
+        let pin: SpkiPin = "sha256//47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="
+            .parse()
+            .unwrap();
+        assert_eq!(
+            pin.to_string(),
+            "sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="
+        );
+        assert!("md5/1B2M2Y8AsgTpgAmY7PhCfg==".parse::<SpkiPin>().is_err());
+        assert!("sha256/AAAA".parse::<SpkiPin>().is_err());
+    }
+
+    #[test]
+    fn extracts_the_public_key() {
+        let certs = certificates();
+        assert_eq!(subject_public_key_info(&certs.der), Some(&certs.spki[..]));
+        assert_eq!(subject_public_key_info(&certs.der[..40]), None);
+    }
+
+    #[tokio::test]
+    async fn connects_to_a_trusted_server() {
+        let certs = certificates();
+        let (result, authorization) = connect(&certs, vec![]).await;
+        result.unwrap();
+        assert_eq!(authorization.as_deref(), Some("bearer sk-tls-test"));
+    }
+
+    #[tokio::test]
+    async fn connects_when_the_key_is_pinned() {
+        let certs = certificates();
+        let pin = SpkiPin(Sha256::digest(&certs.spki).into());
+        let other = SpkiPin([7; 32]);
+        let (result, authorization) = connect(&certs, vec![other, pin]).await;
+        result.unwrap();
+        assert!(authorization.is_some());
+    }
+
+    #[tokio::test]
+    async fn refuses_a_key_that_is_not_pinned() {
+        let certs = certificates();
+        let (result, authorization) = connect(&certs, vec![SpkiPin([7; 32])]).await;
+        let error = result.unwrap_err();
+        assert!(
+            matches!(
+                error.downcast_ref::<AsrError>(),
+                Some(AsrError::Transport(message)) if message.contains("pinned")
+            ),
+            "{:#}",
+            error
+        );
+        // The API key was never sent
+        assert_eq!(authorization, None);
+    }
+
+    #[tokio::test]
+    async fn pin_mismatch_ends_a_multi_key_session() {
+        let certs = certificates();
+        let (endpoint, _server) = server(&certs).await;
+        let path = ca_bundle(&certs);
+        let keys = Arc::new(KeyPool::parse("sk-one,sk-two").unwrap());
+        let client = AsrClient::from_keys(keys.clone()).with_connection(ConnectionConfig {
+            endpoint,
+            proxy: None,
+            ca_bundle: Some(path.clone()),
+            pins: vec![SpkiPin([7; 32])],
+        });
+        let mut chain = BackendChain::new(BackendKind::Dashscope, Backend::DashScope(client));
+
+        let (_audio_tx, audio_rx) = mpsc::channel(1);
+        let (event_tx, mut event_rx) = mpsc::channel(10);
+        tokio::time::timeout(Duration::from_secs(5), chain.run(None, audio_rx, event_tx))
+            .await
+            .expect("session did not end")
+            .unwrap();
+        let _ = std::fs::remove_file(path);
+
+        // Reported as it is, not retried with the other key
+        match event_rx.recv().await {
+            Some(AsrEvent::TaskFailed { error }) => {
+                assert!(matches!(error, AsrError::Transport(_)), "{}", error)
+            }
+            _ => panic!("expected the pin mismatch to end the session"),
+        }
+        assert!(keys.has_available());
+    }
+
+    #[tokio::test]
+    async fn refuses_an_untrusted_certificate() {
+        let certs = certificates();
+        let (endpoint, _server) = server(&certs).await;
+        let client = AsrClient::new("sk-tls-test".to_string()).with_connection(ConnectionConfig {
+            endpoint,
+            ..ConnectionConfig::default()
+        });
+        let Err(error) = client.connect().await else {
+            panic!("connected to a server with an untrusted certificate");
+        };
+        assert!(
+            matches!(error.downcast_ref::<AsrError>(), Some(AsrError::Network(_))),
+            "{:#}",
+            error
+        );
+    }
+}
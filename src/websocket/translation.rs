@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+
+use super::{RecognitionResult, Shown};
+
+/// Writing systems told apart when detecting the spoken language
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Latin,
+    Han,
+    Kana,
+    Hangul,
+    Cyrillic,
+}
+
+/// Script a language is written in; anything not listed is taken to be Latin
+fn script_of(lang: &str) -> Script {
+    let base = lang.split(['-', '_']).next().unwrap_or(lang);
+    match base.to_ascii_lowercase().as_str() {
+        "zh" | "yue" => Script::Han,
+        "ja" => Script::Kana,
+        "ko" => Script::Hangul,
+        "ru" | "uk" | "be" | "bg" | "sr" | "mk" | "kk" => Script::Cyrillic,
+        _ => Script::Latin,
+    }
+}
+
+/// Script most letters of `text` are written in, `None` without letters
+fn dominant_script(text: &str) -> Option<Script> {
+    let mut counts = [0usize; 5];
+    for c in text.chars() {
+        let script = match c as u32 {
+            0x3040..=0x30FF | 0x31F0..=0x31FF => Script::Kana,
+            0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => Script::Hangul,
+            0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF => Script::Han,
+            0x0400..=0x04FF => Script::Cyrillic,
+            _ if c.is_alphabetic() => Script::Latin,
+            _ => continue,
+        };
+        counts[script as usize] += 1;
+    }
+    // Japanese mixes kanji into kana
+    if counts[Script::Kana as usize] > 0 {
+        counts[Script::Kana as usize] += std::mem::take(&mut counts[Script::Han as usize]);
+    }
+    [
+        Script::Latin,
+        Script::Han,
+        Script::Kana,
+        Script::Hangul,
+        Script::Cyrillic,
+    ]
+    .into_iter()
+    .filter(|&script| counts[script as usize] > 0)
+    .max_by_key(|&script| counts[script as usize])
+}
+
+/// Chooses, per sentence, the translation to show for the configured target
+/// languages
+///
+/// The spoken language is detected from the script of the transcription.
+/// The first target that differs from it is shown; when every target is the
+/// spoken language (or it cannot be detected), the transcription is. With
+/// `["zh", "en"]`, Chinese speech comes out in English and English speech in
+/// Chinese. Without a transcription, the first target in the configured order
+/// is shown. Every translation stays in the result for outputs routed to a
+/// particular language.
+pub(super) struct TranslationPicker {
+    targets: Vec<String>,
+    /// Choice for sentences whose shown text already has fixed words, which
+    /// may have been typed and must not switch language any more
+    locked: HashMap<i32, Option<String>>,
+}
+
+impl TranslationPicker {
+    pub fn new(targets: Vec<String>) -> Self {
+        Self {
+            targets,
+            locked: HashMap::new(),
+        }
+    }
+
+    /// Set which stream of `result` is shown
+    pub fn pick(&mut self, result: &mut RecognitionResult) {
+        let target = match self.locked.get(&result.sentence_id) {
+            Some(target) => target.clone(),
+            None => self.target(result),
+        };
+        result.shown = match &target {
+            Some(lang) => Shown::Translation(lang.clone()),
+            None => Shown::Transcription,
+        };
+
+        if result.is_final {
+            self.locked.remove(&result.sentence_id);
+        } else if result
+            .preferred()
+            .is_some_and(|stream| stream.fixed_word_count() > 0)
+        {
+            self.locked.insert(result.sentence_id, target);
+        }
+    }
+
+    /// First target language that is not the spoken one, or the first one
+    /// translated if there is no transcription to tell the spoken one by
+    fn target(&self, result: &RecognitionResult) -> Option<String> {
+        let Some(transcription) = &result.transcription else {
+            return self
+                .targets
+                .iter()
+                .find(|lang| result.translations.contains_key(*lang))
+                .cloned();
+        };
+        let spoken = dominant_script(&transcription.text)?;
+        self.targets
+            .iter()
+            .find(|lang| script_of(lang) != spoken)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::SentenceText;
+    use std::collections::BTreeMap;
+
+    fn text(text: &str, lang: Option<&str>, fixed: bool) -> SentenceText {
+        SentenceText {
+            text: text.to_string(),
+            lang: lang.map(str::to_string),
+            begin_time: 0,
+            end_time: 1000,
+            words: vec![crate::websocket::Word {
+                begin_time: 0,
+                end_time: 1000,
+                text: text.to_string(),
+                punctuation: None,
+                fixed,
+                speaker_id: None,
+            }],
+            sentence_end: false,
+        }
+    }
+
+    fn result(
+        sentence_id: i32,
+        spoken: &str,
+        zh: &str,
+        en: &str,
+        fixed: bool,
+    ) -> RecognitionResult {
+        RecognitionResult {
+            sentence_id,
+            begin_time: 0,
+            end_time: 1000,
+            transcription: Some(text(spoken, None, fixed)),
+            translations: BTreeMap::from([
+                ("en".to_string(), text(en, Some("en"), fixed)),
+                ("zh".to_string(), text(zh, Some("zh"), fixed)),
+            ]),
+            is_final: false,
+            pre_end_failed: false,
+            shown: Shown::default(),
+        }
+    }
+
+    fn shown(result: &RecognitionResult) -> &str {
+        &result.preferred().unwrap().text
+    }
+
+    #[test]
+    fn detects_scripts() {
+        assert_eq!(dominant_script("今天开会, OK?"), Some(Script::Han));
+        assert_eq!(
+            dominant_script("Let's ship 这个 today"),
+            Some(Script::Latin)
+        );
+        assert_eq!(dominant_script("会議を始めます"), Some(Script::Kana));
+        assert_eq!(dominant_script("42, 17."), None);
+        assert_eq!(script_of("zh-CN"), Script::Han);
+    }
+
+    #[test]
+    fn translates_each_sentence_out_of_its_language() {
+        let mut picker = TranslationPicker::new(vec!["zh".to_string(), "en".to_string()]);
+
+        let mut chinese = result(0, "你好", "你好", "Hello", false);
+        picker.pick(&mut chinese);
+        assert_eq!(shown(&chinese), "Hello");
+
+        let mut english = result(1, "Good morning", "早上好", "Good morning", false);
+        picker.pick(&mut english);
+        assert_eq!(shown(&english), "早上好");
+
+        // Nothing to tell the language by: keep the transcription
+        let mut digits = result(2, "2024", "2024", "2024", false);
+        picker.pick(&mut digits);
+        assert_eq!(digits.shown, Shown::Transcription);
+        assert_eq!(digits.translations.len(), 2);
+
+        // One-way translation into the spoken language shows the transcription
+        let mut picker = TranslationPicker::new(vec!["en".to_string()]);
+        let mut english = result(3, "Good morning", "早上好", "Good morning.", false);
+        picker.pick(&mut english);
+        assert_eq!(shown(&english), "Good morning");
+    }
+
+    #[test]
+    fn follows_the_configured_order_without_a_transcription() {
+        let mut picker = TranslationPicker::new(vec!["zh".to_string(), "en".to_string()]);
+
+        let mut result = result(0, "", "你好", "Hello", false);
+        result.transcription = None;
+        picker.pick(&mut result);
+        assert_eq!(result.shown, Shown::Translation("zh".to_string()));
+        assert_eq!(shown(&result), "你好");
+    }
+
+    #[test]
+    fn keeps_the_choice_once_words_are_fixed() {
+        let mut picker = TranslationPicker::new(vec!["zh".to_string(), "en".to_string()]);
+
+        let mut partial = result(0, "OK", "好的", "OK", true);
+        picker.pick(&mut partial);
+        assert_eq!(shown(&partial), "好的");
+
+        // More Chinese follows, but the start may already be typed
+        let mut next = result(
+            0,
+            "OK 我们开始吧",
+            "好的, 我们开始吧",
+            "OK, let's start",
+            true,
+        );
+        picker.pick(&mut next);
+        assert_eq!(shown(&next), "好的, 我们开始吧");
+
+        let mut last = result(
+            0,
+            "OK 我们开始吧",
+            "好的, 我们开始吧",
+            "OK, let's start",
+            true,
+        );
+        last.is_final = true;
+        picker.pick(&mut last);
+        assert_eq!(shown(&last), "好的, 我们开始吧");
+        assert!(picker.locked.is_empty());
+    }
+}
@@ -0,0 +1,808 @@
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::MaybeTlsStream;
+use tracing::{debug, info, warn};
+use url::Url;
+
+use super::tls::{self, SpkiPin};
+use super::AsrError;
+
+/// Mainland China endpoint (Beijing)
+const CN_WS_URL: &str = "wss://dashscope.aliyuncs.com/api-ws/v1/inference/";
+
+/// International endpoint (Singapore)
+const INTL_WS_URL: &str = "wss://dashscope-intl.aliyuncs.com/api-ws/v1/inference/";
+
+/// DashScope deployment regions with their own endpoint and API keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Cn,
+    Intl,
+}
+
+impl Region {
+    pub fn ws_url(self) -> &'static str {
+        match self {
+            Region::Cn => CN_WS_URL,
+            Region::Intl => INTL_WS_URL,
+        }
+    }
+}
+
+impl std::str::FromStr for Region {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "cn" | "china" | "beijing" | "mainland" => Ok(Region::Cn),
+            "intl" | "international" | "singapore" | "sg" => Ok(Region::Intl),
+            other => bail!(
+                "Unknown DashScope region '{}' (expected 'cn' or 'intl')",
+                other
+            ),
+        }
+    }
+}
+
+/// Where and how to open the WebSocket connection
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    pub endpoint: Url,
+    pub proxy: Option<Proxy>,
+    /// Extra PEM root certificates to trust, e.g. for a TLS-intercepting proxy
+    pub ca_bundle: Option<PathBuf>,
+    /// Keys the server certificate must match one of, if any (see `tls`)
+    pub pins: Vec<SpkiPin>,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: Url::parse(CN_WS_URL).expect("valid default endpoint"),
+            proxy: None,
+            ca_bundle: None,
+            pins: Vec::new(),
+        }
+    }
+}
+
+impl ConnectionConfig {
+    /// Read the connection settings from the environment
+    ///
+    /// - `DASHSCOPE_WS_URL`: full endpoint URL, overrides the region preset
+    /// - `DASHSCOPE_REGION`: `cn` (default) or `intl`
+    /// - `HTTPS_PROXY` / `ALL_PROXY` (and lowercase variants), honoring `NO_PROXY`
+    /// - `DASHSCOPE_CA_BUNDLE`: PEM file with additional trusted root certificates
+    /// - `DASHSCOPE_PINNED_KEYS`: comma-separated `sha256/<base64>` key pins
+    pub fn from_env() -> Result<Self> {
+        let endpoint = match env_var("DASHSCOPE_WS_URL") {
+            Some(url) => url,
+            None => match env_var("DASHSCOPE_REGION") {
+                Some(region) => region.parse::<Region>()?.ws_url().to_string(),
+                None => CN_WS_URL.to_string(),
+            },
+        };
+        let mut config = Self::for_endpoint(&endpoint)?;
+        if let Some(pins) = env_var("DASHSCOPE_PINNED_KEYS") {
+            config.pins = pins
+                .split(',')
+                .map(str::trim)
+                .filter(|pin| !pin.is_empty())
+                .map(str::parse)
+                .collect::<Result<_>>()
+                .context("Invalid DASHSCOPE_PINNED_KEYS")?;
+        }
+        Ok(config)
+    }
+
+    /// Connection settings for another endpoint, with the proxy and CA
+    /// bundle from the environment
+    pub fn for_endpoint(endpoint: &str) -> Result<Self> {
+        let endpoint =
+            Url::parse(endpoint).with_context(|| format!("Invalid endpoint URL: {}", endpoint))?;
+        if !matches!(endpoint.scheme(), "ws" | "wss") {
+            bail!("Endpoint must be a ws:// or wss:// URL: {}", endpoint);
+        }
+        Self::with_env(endpoint)
+    }
+
+    /// Connection settings for an HTTP endpoint, used through `http_client()`
+    pub fn for_http_endpoint(endpoint: &str) -> Result<Self> {
+        let endpoint =
+            Url::parse(endpoint).with_context(|| format!("Invalid endpoint URL: {}", endpoint))?;
+        if !matches!(endpoint.scheme(), "http" | "https") {
+            bail!("Endpoint must be an http:// or https:// URL: {}", endpoint);
+        }
+        Self::with_env(endpoint)
+    }
+
+    fn with_env(endpoint: Url) -> Result<Self> {
+        let host = endpoint.host_str().unwrap_or_default().to_string();
+        let proxy = match Proxy::from_env()? {
+            Some(_) if is_no_proxy(&host) => {
+                debug!("{} matches NO_PROXY, connecting directly", host);
+                None
+            }
+            proxy => proxy,
+        };
+
+        Ok(Self {
+            endpoint,
+            proxy,
+            ca_bundle: env_var("DASHSCOPE_CA_BUNDLE").map(PathBuf::from),
+            pins: Vec::new(),
+        })
+    }
+
+    /// Open the TCP connection to the endpoint, tunnelling through the proxy if configured
+    pub async fn open_stream(&self) -> Result<TcpStream, AsrError> {
+        // IPv6 literals without their URL brackets
+        let host = match self.endpoint.host() {
+            Some(url::Host::Ipv6(ip)) => ip.to_string(),
+            Some(host) => host.to_string(),
+            None => return Err(AsrError::Protocol("endpoint URL has no host".to_string())),
+        };
+        let host = host.as_str();
+        let port = self.endpoint.port_or_known_default().unwrap_or(443);
+
+        match &self.proxy {
+            Some(proxy) => proxy.tunnel(host, port).await,
+            None => TcpStream::connect((host, port)).await.map_err(|e| {
+                AsrError::Network(format!("failed to connect to {}:{}: {}", host, port, e))
+            }),
+        }
+    }
+
+    /// Secure an open stream for a wss:// endpoint, trusting the custom CA
+    /// bundle and checking the pinned keys; ws:// stays plain
+    pub async fn tls_stream(&self, stream: TcpStream) -> Result<MaybeTlsStream<TcpStream>> {
+        if self.endpoint.scheme() == "ws" {
+            return Ok(MaybeTlsStream::Plain(stream));
+        }
+        let host = self.endpoint.host_str().unwrap_or_default();
+        let blocks = match &self.ca_bundle {
+            Some(path) => {
+                let blocks = read_ca_bundle(path)?;
+                info!(
+                    "Trusting {} certificate(s) from {}",
+                    blocks.len(),
+                    path.display()
+                );
+                blocks
+            }
+            None => Vec::new(),
+        };
+        tls::connect(stream, host, &blocks, &self.pins).await
+    }
+
+    /// HTTP(S) URL of a REST API path on the same host as the WebSocket endpoint
+    pub fn http_url(&self, path: &str) -> Url {
+        let mut url = self.endpoint.clone();
+        let scheme = if matches!(url.scheme(), "ws" | "http") {
+            "http"
+        } else {
+            "https"
+        };
+        // Switching between special schemes always succeeds
+        let _ = url.set_scheme(scheme);
+        url.set_path(path);
+        url.set_query(None);
+        url
+    }
+
+    /// HTTP client for the REST APIs, using the same proxy and CA bundle
+    pub fn http_client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder().no_proxy();
+        #[cfg(feature = "rustls")]
+        {
+            builder = builder.use_rustls_tls();
+        }
+        if !self.pins.is_empty() {
+            warn!("Pinned keys only apply to the WebSocket, not to REST calls");
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy.to_reqwest()?);
+        }
+        if let Some(path) = &self.ca_bundle {
+            for block in read_ca_bundle(path)? {
+                let cert = reqwest::Certificate::from_pem(block.as_bytes())
+                    .with_context(|| format!("Invalid certificate in {}", path.display()))?;
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        builder.build().context("Failed to build HTTP client")
+    }
+}
+
+/// Outbound proxy used to reach the endpoint
+#[derive(Clone)]
+pub struct Proxy {
+    kind: ProxyKind,
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProxyKind {
+    /// HTTP proxy using the CONNECT method
+    HttpConnect,
+    /// SOCKS5, resolving the target host locally
+    Socks5,
+    /// SOCKS5, letting the proxy resolve the target host
+    Socks5h,
+}
+
+impl std::fmt::Debug for Proxy {
+    // Keep proxy passwords out of logs
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Proxy")
+            .field("kind", &self.kind)
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field(
+                "credentials",
+                &self.credentials.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
+impl Proxy {
+    /// Proxy from `HTTPS_PROXY`, falling back to `ALL_PROXY`
+    pub fn from_env() -> Result<Option<Self>> {
+        let value = ["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]
+            .iter()
+            .find_map(|name| env_var(name));
+
+        value.map(|value| Self::parse(&value)).transpose()
+    }
+
+    pub fn parse(value: &str) -> Result<Self> {
+        // A bare "host:port" is treated as an HTTP proxy, like curl does
+        let value = if value.contains("://") {
+            value.to_string()
+        } else {
+            format!("http://{}", value)
+        };
+        let url = Url::parse(&value).with_context(|| format!("Invalid proxy URL: {}", value))?;
+
+        let (kind, default_port) = match url.scheme() {
+            "http" => (ProxyKind::HttpConnect, 80),
+            "socks5" => (ProxyKind::Socks5, 1080),
+            "socks5h" => (ProxyKind::Socks5h, 1080),
+            other => bail!(
+                "Unsupported proxy scheme '{}' (use http, socks5 or socks5h)",
+                other
+            ),
+        };
+
+        let host = url
+            .host_str()
+            .with_context(|| format!("Proxy URL has no host: {}", value))?
+            .trim_matches(|c| c == '[' || c == ']')
+            .to_string();
+
+        let credentials = if url.username().is_empty() {
+            None
+        } else {
+            let password = percent_decode(url.password().unwrap_or_default());
+            crate::logging::add_secret(&password);
+            crate::logging::add_secret(url.password().unwrap_or_default());
+            Some((percent_decode(url.username()), password))
+        };
+
+        Ok(Self {
+            kind,
+            host,
+            port: url.port().unwrap_or(default_port),
+            credentials,
+        })
+    }
+
+    /// The same proxy for the HTTP client
+    fn to_reqwest(&self) -> Result<reqwest::Proxy> {
+        let scheme = match self.kind {
+            ProxyKind::HttpConnect => "http",
+            ProxyKind::Socks5 => "socks5",
+            ProxyKind::Socks5h => "socks5h",
+        };
+        let mut url = Url::parse(&format!("{}://{}", scheme, self.host))?;
+        let _ = url.set_port(Some(self.port));
+        if let Some((user, password)) = &self.credentials {
+            let _ = url.set_username(user);
+            let _ = url.set_password(Some(password));
+        }
+        Ok(reqwest::Proxy::all(url)?)
+    }
+
+    /// Connect to the proxy and ask it to open a tunnel to `host:port`
+    pub async fn tunnel(&self, host: &str, port: u16) -> Result<TcpStream, AsrError> {
+        info!(
+            "Connecting to {}:{} via proxy {}:{}",
+            host, port, self.host, self.port
+        );
+
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .map_err(|e| {
+                AsrError::Network(format!(
+                    "failed to connect to proxy {}:{}: {}",
+                    self.host, self.port, e
+                ))
+            })?;
+
+        let result = match self.kind {
+            ProxyKind::HttpConnect => self.http_connect(&mut stream, host, port).await,
+            ProxyKind::Socks5 => {
+                let addr = tokio::net::lookup_host((host, port))
+                    .await
+                    .ok()
+                    .and_then(|mut addrs| addrs.next())
+                    .ok_or_else(|| AsrError::Network(format!("failed to resolve {}", host)))?;
+                self.socks5_connect(&mut stream, &addr.ip().to_string(), port)
+                    .await
+            }
+            ProxyKind::Socks5h => self.socks5_connect(&mut stream, host, port).await,
+        };
+
+        result.map(|()| stream)
+    }
+
+    async fn http_connect(
+        &self,
+        stream: &mut TcpStream,
+        host: &str,
+        port: u16,
+    ) -> Result<(), AsrError> {
+        let authority = match host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+            _ => format!("{}:{}", host, port),
+        };
+        let mut request = format!(
+            "CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\nProxy-Connection: Keep-Alive\r\n"
+        );
+        if let Some((user, password)) = &self.credentials {
+            let token =
+                base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password));
+            request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        stream
+            .write_all(request.as_bytes())
+            .await
+            .map_err(proxy_io_error)?;
+
+        // Read the response headers byte by byte so nothing after them is consumed
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() > 8192 {
+                return Err(AsrError::Protocol(
+                    "proxy response headers too large".to_string(),
+                ));
+            }
+            let byte = stream.read_u8().await.map_err(proxy_io_error)?;
+            response.push(byte);
+        }
+
+        let response = String::from_utf8_lossy(&response);
+        let status_line = response.lines().next().unwrap_or_default();
+        let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+        match status {
+            "200" => Ok(()),
+            "407" => Err(AsrError::Transport(format!(
+                "proxy rejected credentials: {}",
+                status_line
+            ))),
+            _ => Err(AsrError::Network(format!(
+                "proxy CONNECT failed: {}",
+                status_line
+            ))),
+        }
+    }
+
+    async fn socks5_connect(
+        &self,
+        stream: &mut TcpStream,
+        host: &str,
+        port: u16,
+    ) -> Result<(), AsrError> {
+        // Greeting: offer "no auth", plus username/password if we have credentials
+        let greeting: &[u8] = if self.credentials.is_some() {
+            &[0x05, 0x02, 0x00, 0x02]
+        } else {
+            &[0x05, 0x01, 0x00]
+        };
+        stream.write_all(greeting).await.map_err(proxy_io_error)?;
+
+        let mut choice = [0u8; 2];
+        stream
+            .read_exact(&mut choice)
+            .await
+            .map_err(proxy_io_error)?;
+        match choice {
+            [0x05, 0x00] => {}
+            [0x05, 0x02] => self.socks5_authenticate(stream).await?,
+            [0x05, 0xff] => {
+                return Err(AsrError::Transport(
+                    "SOCKS5 proxy accepted none of our authentication methods".to_string(),
+                ))
+            }
+            _ => {
+                return Err(AsrError::Protocol(
+                    "invalid SOCKS5 greeting reply".to_string(),
+                ))
+            }
+        }
+
+        // CONNECT request with the target as IP address or domain name
+        let mut request = vec![0x05, 0x01, 0x00];
+        match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => {
+                request.push(0x01);
+                request.extend_from_slice(&ip.octets());
+            }
+            Ok(IpAddr::V6(ip)) => {
+                request.push(0x04);
+                request.extend_from_slice(&ip.octets());
+            }
+            Err(_) => {
+                let name = host.as_bytes();
+                let len = u8::try_from(name.len())
+                    .map_err(|_| AsrError::Protocol("host name too long for SOCKS5".to_string()))?;
+                request.push(0x03);
+                request.push(len);
+                request.extend_from_slice(name);
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await.map_err(proxy_io_error)?;
+
+        let mut reply = [0u8; 4];
+        stream
+            .read_exact(&mut reply)
+            .await
+            .map_err(proxy_io_error)?;
+        if reply[0] != 0x05 {
+            return Err(AsrError::Protocol(
+                "invalid SOCKS5 connect reply".to_string(),
+            ));
+        }
+        if reply[1] != 0x00 {
+            return Err(AsrError::Network(format!(
+                "SOCKS5 proxy refused connection to {}:{} (reply code {})",
+                host, port, reply[1]
+            )));
+        }
+
+        // Skip the bound address the proxy reports back
+        let addr_len = match reply[3] {
+            0x01 => 4,
+            0x04 => 16,
+            0x03 => stream.read_u8().await.map_err(proxy_io_error)? as usize,
+            _ => {
+                return Err(AsrError::Protocol(
+                    "invalid SOCKS5 address type".to_string(),
+                ))
+            }
+        };
+        let mut bound = vec![0u8; addr_len + 2];
+        stream
+            .read_exact(&mut bound)
+            .await
+            .map_err(proxy_io_error)?;
+
+        Ok(())
+    }
+
+    async fn socks5_authenticate(&self, stream: &mut TcpStream) -> Result<(), AsrError> {
+        let Some((user, password)) = &self.credentials else {
+            return Err(AsrError::Protocol(
+                "SOCKS5 proxy requested credentials we did not offer".to_string(),
+            ));
+        };
+
+        let too_long = || AsrError::Protocol("SOCKS5 username or password too long".to_string());
+        let mut request = vec![0x01, u8::try_from(user.len()).map_err(|_| too_long())?];
+        request.extend_from_slice(user.as_bytes());
+        request.push(u8::try_from(password.len()).map_err(|_| too_long())?);
+        request.extend_from_slice(password.as_bytes());
+        stream.write_all(&request).await.map_err(proxy_io_error)?;
+
+        let mut reply = [0u8; 2];
+        stream
+            .read_exact(&mut reply)
+            .await
+            .map_err(proxy_io_error)?;
+        if reply[1] != 0x00 {
+            return Err(AsrError::Transport(
+                "SOCKS5 proxy rejected credentials".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn proxy_io_error(err: std::io::Error) -> AsrError {
+    AsrError::Network(format!("proxy handshake failed: {}", err))
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+}
+
+/// Whether `host` is excluded from proxying by `NO_PROXY`
+fn is_no_proxy(host: &str) -> bool {
+    env_var("NO_PROXY")
+        .or_else(|| env_var("no_proxy"))
+        .is_some_and(|no_proxy| no_proxy_matches(&no_proxy, host))
+}
+
+/// Whether `host` matches an entry of a `NO_PROXY` list
+fn no_proxy_matches(no_proxy: &str, host: &str) -> bool {
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().trim_start_matches('.'))
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| entry == "*" || host == entry || host.ends_with(&format!(".{}", entry)))
+}
+
+/// Certificates of a PEM CA bundle, failing if it has none
+fn read_ca_bundle(path: &Path) -> Result<Vec<String>> {
+    let pem = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read CA bundle {}", path.display()))?;
+    let blocks = pem_certificates(&pem);
+    if blocks.is_empty() {
+        bail!("No certificates found in CA bundle {}", path.display());
+    }
+    Ok(blocks)
+}
+
+/// Split a PEM bundle into individual certificate blocks
+fn pem_certificates(pem: &str) -> Vec<String> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+
+    let mut certs = Vec::new();
+    let mut rest = pem;
+    while let Some(start) = rest.find(BEGIN) {
+        let Some(end) = rest[start..].find(END) else {
+            break;
+        };
+        let end = start + end + END.len();
+        certs.push(rest[start..end].to_string());
+        rest = &rest[end..];
+    }
+    certs
+}
+
+/// Decode %XX escapes in proxy credentials
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(byte) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use tokio::net::TcpListener;
+
+    /// Proxy stand-in that runs `script` on the first connection
+    async fn proxy_server<F, Fut>(scheme: &str, credentials: &str, script: F) -> Proxy
+    where
+        F: FnOnce(TcpStream) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            script(stream).await;
+        });
+        Proxy::parse(&format!("{}://{}127.0.0.1:{}", scheme, credentials, port)).unwrap()
+    }
+
+    /// HTTP request headers up to the blank line
+    async fn read_headers(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        while !request.ends_with(b"\r\n\r\n") {
+            request.push(stream.read_u8().await.unwrap());
+        }
+        String::from_utf8(request).unwrap()
+    }
+
+    async fn read_bytes(stream: &mut TcpStream, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0; len];
+        stream.read_exact(&mut bytes).await.unwrap();
+        bytes
+    }
+
+    /// What the client reads through the tunnel
+    async fn read_tunnel(mut stream: TcpStream) -> String {
+        let mut data = String::new();
+        stream.read_to_string(&mut data).await.unwrap();
+        data
+    }
+
+    #[test]
+    fn parses_regions() {
+        assert_eq!("cn".parse::<Region>().unwrap(), Region::Cn);
+        assert_eq!("Beijing".parse::<Region>().unwrap(), Region::Cn);
+        assert_eq!("INTL".parse::<Region>().unwrap(), Region::Intl);
+        assert_eq!("sg".parse::<Region>().unwrap(), Region::Intl);
+        assert!("us".parse::<Region>().is_err());
+        assert_eq!(Region::Intl.ws_url(), INTL_WS_URL);
+    }
+
+    #[test]
+    fn matches_no_proxy_entries() {
+        let no_proxy = "localhost, .internal.example,aliyuncs.com";
+        assert!(no_proxy_matches(no_proxy, "localhost"));
+        assert!(no_proxy_matches(no_proxy, "asr.internal.example"));
+        assert!(no_proxy_matches(no_proxy, "dashscope.aliyuncs.com"));
+        assert!(no_proxy_matches(no_proxy, "aliyuncs.com"));
+        assert!(!no_proxy_matches(no_proxy, "notaliyuncs.com"));
+        assert!(!no_proxy_matches(no_proxy, "example.com"));
+        assert!(no_proxy_matches("*", "example.com"));
+        assert!(!no_proxy_matches(" , ", "example.com"));
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(percent_decode("p%40ss%3Aword"), "p@ss:word");
+        assert_eq!(percent_decode("%E4%BD%A0"), "你");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+        assert_eq!(percent_decode("plain"), "plain");
+    }
+
+    #[tokio::test]
+    async fn tunnels_through_http_connect() {
+        let proxy = proxy_server("http", "user:p%40ss@", |mut stream| async move {
+            let request = read_headers(&mut stream).await;
+            assert!(request.starts_with("CONNECT dashscope.example:443 HTTP/1.1\r\n"));
+            // base64("user:p@ss")
+            assert!(request.contains("Proxy-Authorization: Basic dXNlcjpwQHNz\r\n"));
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\ntunnelled")
+                .await
+                .unwrap();
+        })
+        .await;
+
+        let stream = proxy.tunnel("dashscope.example", 443).await.unwrap();
+        assert_eq!(read_tunnel(stream).await, "tunnelled");
+    }
+
+    #[tokio::test]
+    async fn brackets_ipv6_targets_in_http_connect() {
+        let proxy = proxy_server("http", "", |mut stream| async move {
+            let request = read_headers(&mut stream).await;
+            assert!(request.starts_with("CONNECT [::1]:443 HTTP/1.1\r\nHost: [::1]:443\r\n"));
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\ntunnelled")
+                .await
+                .unwrap();
+        })
+        .await;
+
+        let stream = proxy.tunnel("::1", 443).await.unwrap();
+        assert_eq!(read_tunnel(stream).await, "tunnelled");
+    }
+
+    #[tokio::test]
+    async fn reports_http_connect_failures() {
+        let proxy = proxy_server("http", "user:wrong@", |mut stream| async move {
+            read_headers(&mut stream).await;
+            stream
+                .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                .await
+                .unwrap();
+        })
+        .await;
+        let error = proxy.tunnel("dashscope.example", 443).await.unwrap_err();
+        assert!(matches!(&error, AsrError::Transport(_)), "{}", error);
+        assert!(!error.is_retryable());
+
+        let proxy = proxy_server("http", "", |mut stream| async move {
+            read_headers(&mut stream).await;
+            stream
+                .write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n")
+                .await
+                .unwrap();
+        })
+        .await;
+        let error = proxy.tunnel("dashscope.example", 443).await.unwrap_err();
+        assert!(matches!(&error, AsrError::Network(message) if message.contains("502")));
+    }
+
+    #[tokio::test]
+    async fn tunnels_through_socks5_with_credentials() {
+        let proxy = proxy_server("socks5h", "user:p%40ss@", |mut stream| async move {
+            assert_eq!(read_bytes(&mut stream, 4).await, [0x05, 0x02, 0x00, 0x02]);
+            stream.write_all(&[0x05, 0x02]).await.unwrap();
+            assert_eq!(read_bytes(&mut stream, 11).await, b"\x01\x04user\x04p@ss");
+            stream.write_all(&[0x01, 0x00]).await.unwrap();
+
+            // CONNECT by domain name, resolved by the proxy
+            let mut request = vec![0x05, 0x01, 0x00, 0x03, 17];
+            request.extend_from_slice(b"dashscope.example");
+            request.extend_from_slice(&443u16.to_be_bytes());
+            assert_eq!(read_bytes(&mut stream, request.len()).await, request);
+            stream
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 10, 0, 0, 1, 0x1f, 0x90])
+                .await
+                .unwrap();
+            stream.write_all(b"tunnelled").await.unwrap();
+        })
+        .await;
+
+        let stream = proxy.tunnel("dashscope.example", 443).await.unwrap();
+        assert_eq!(read_tunnel(stream).await, "tunnelled");
+    }
+
+    #[tokio::test]
+    async fn reports_socks5_failures() {
+        // Credentials rejected
+        let proxy = proxy_server("socks5h", "user:wrong@", |mut stream| async move {
+            read_bytes(&mut stream, 4).await;
+            stream.write_all(&[0x05, 0x02]).await.unwrap();
+            read_bytes(&mut stream, 12).await;
+            stream.write_all(&[0x01, 0x01]).await.unwrap();
+        })
+        .await;
+        let error = proxy.tunnel("dashscope.example", 443).await.unwrap_err();
+        assert!(matches!(error, AsrError::Transport(_)), "{}", error);
+
+        // No acceptable authentication method
+        let proxy = proxy_server("socks5h", "", |mut stream| async move {
+            assert_eq!(read_bytes(&mut stream, 3).await, [0x05, 0x01, 0x00]);
+            stream.write_all(&[0x05, 0xff]).await.unwrap();
+        })
+        .await;
+        let error = proxy.tunnel("dashscope.example", 443).await.unwrap_err();
+        assert!(matches!(error, AsrError::Transport(_)), "{}", error);
+
+        // Connection refused by the proxy, to an address resolved locally
+        let proxy = proxy_server("socks5", "", |mut stream| async move {
+            read_bytes(&mut stream, 3).await;
+            stream.write_all(&[0x05, 0x00]).await.unwrap();
+            assert_eq!(
+                read_bytes(&mut stream, 10).await,
+                [0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1, 0x01, 0xbb]
+            );
+            stream
+                .write_all(&[0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+        })
+        .await;
+        let error = proxy.tunnel("127.0.0.1", 443).await.unwrap_err();
+        assert!(
+            matches!(&error, AsrError::Network(message) if message.contains("reply code 5")),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use super::{AsrClient, AsrConnection};
+
+/// How often the idle connection is pinged
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long to wait for a pong before treating the connection as dead
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Idle connections older than this are replaced before the server drops them
+const MAX_IDLE: Duration = Duration::from_secs(50);
+
+/// Upper bound for the reconnect backoff after failed attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Keeps an authenticated WebSocket connection open in the background so a
+/// new session can skip the TLS handshake and WebSocket upgrade.
+///
+/// The connection is pinged while idle and replaced when it gets too old or
+/// stops answering. `take` hands it out and triggers a refill for the next
+/// session.
+pub struct WarmConnection {
+    slot: Arc<Mutex<Option<AsrConnection>>>,
+    refill: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+impl WarmConnection {
+    pub fn spawn(client: AsrClient) -> Self {
+        let slot = Arc::new(Mutex::new(None));
+        let refill = Arc::new(Notify::new());
+
+        let task = tokio::spawn(keep_warm(client, slot.clone(), refill.clone()));
+
+        Self { slot, refill, task }
+    }
+
+    /// Take the ready connection, if there is one
+    pub async fn take(&self) -> Option<AsrConnection> {
+        let connection = self.slot.lock().await.take();
+        self.refill.notify_one();
+
+        if let Some(connection) = &connection {
+            info!(
+                "Using pre-warmed connection (opened {:.1}s ago)",
+                connection.age().as_secs_f32()
+            );
+        }
+        connection
+    }
+}
+
+impl Drop for WarmConnection {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn keep_warm(
+    client: AsrClient,
+    slot: Arc<Mutex<Option<AsrConnection>>>,
+    refill: Arc<Notify>,
+) {
+    let mut backoff = Duration::from_secs(1);
+
+    loop {
+        // Open a new connection if the slot is empty
+        if slot.lock().await.is_none() {
+            match client.connect().await {
+                Ok(connection) => {
+                    debug!("Pre-warmed connection ready");
+                    *slot.lock().await = Some(connection);
+                    backoff = Duration::from_secs(1);
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to pre-warm connection, retrying in {}s: {:#}",
+                        backoff.as_secs(),
+                        e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    continue;
+                }
+            }
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(PING_INTERVAL) => {}
+            _ = refill.notified() => continue,
+        }
+
+        // Keep the idle connection alive, or drop it so it gets replaced
+        let mut guard = slot.lock().await;
+        if let Some(connection) = guard.as_mut() {
+            if connection.age() >= MAX_IDLE {
+                debug!("Refreshing idle pre-warmed connection");
+                *guard = None;
+            } else if let Err(e) = connection.ping(PING_TIMEOUT).await {
+                debug!("Pre-warmed connection lost: {}", e);
+                *guard = None;
+            }
+        }
+    }
+}